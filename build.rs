@@ -0,0 +1,31 @@
+//! Embed the git commit being built, so that a running listener can say
+//! exactly which build it is. When building from a source tarball (as when
+//! packaging) there is no repository to ask, and HERO_GIT_SHA can be set in
+//! the environment instead.
+
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=HERO_GIT_SHA");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+
+    if std::env::var("HERO_GIT_SHA").is_ok() {
+        return;
+    }
+
+    let sha = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| {
+            output
+                .status
+                .success()
+        })
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+
+    if let Some(sha) = sha {
+        println!("cargo:rustc-env=HERO_GIT_SHA={}", sha.trim());
+    }
+}
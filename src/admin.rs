@@ -0,0 +1,299 @@
+//! Endpoints on the listener for operating it, as opposed to the webhook
+//! path GitHub delivers to. Deliveries are vouched for by their signature
+//! and only lead to Runs being looked up; these endpoints act on the
+//! listener itself, and so require credentials of their own: either a bearer
+//! token or a username and password for basic auth. Unless credentials are
+//! configured the endpoints aren't served at all.
+
+use anyhow::Result;
+use axum::body::Body;
+use axum::http::{Request, StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::sync::OnceLock;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use tracing::{info, warn};
+
+use crate::github::{self, Config, GitHubProblem};
+use crate::{
+    VERSION, deadletter, get_program_start, history, lookup_secret, queue, redaction, submit_run,
+    webhook,
+};
+
+struct AdminCredentials {
+    token: Option<String>,
+    basic: Option<(String, String)>,
+}
+
+static CREDENTIALS: OnceLock<AdminCredentials> = OnceLock::new();
+
+/// Read the credentials for the admin endpoints. The bearer token comes from
+/// HERO_ADMIN_TOKEN or an `admin-token` credential; for basic auth the
+/// password comes from HERO_ADMIN_PASSWORD or an `admin-password` credential,
+/// with the username from HERO_ADMIN_USER (defaulting to "admin").
+pub(crate) fn load_admin_credentials() -> Result<()> {
+    let token = lookup_secret("HERO_ADMIN_TOKEN", "admin-token")?;
+    let password = lookup_secret("HERO_ADMIN_PASSWORD", "admin-password")?;
+
+    if token.is_none() && password.is_none() {
+        return Ok(());
+    }
+
+    for secret in token
+        .iter()
+        .chain(password.iter())
+    {
+        redaction::register_secret(secret);
+    }
+
+    let basic = password.map(|password| {
+        let user = std::env::var("HERO_ADMIN_USER").unwrap_or_else(|_| "admin".to_string());
+        (user, password)
+    });
+
+    CREDENTIALS
+        .set(AdminCredentials { token, basic })
+        .ok();
+
+    info!("Admin endpoints enabled");
+
+    Ok(())
+}
+
+pub(crate) fn is_enabled() -> bool {
+    CREDENTIALS
+        .get()
+        .is_some()
+}
+
+/// Compare in time independent of where the first difference is, so the
+/// credentials can't be guessed a character at a time.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter()
+        .zip(b)
+        .fold(0, |difference, (x, y)| difference | (x ^ y))
+        == 0
+}
+
+fn is_authorized(request: &Request<Body>) -> bool {
+    let Some(credentials) = CREDENTIALS.get() else {
+        return false;
+    };
+
+    let Some(value) = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| {
+            value
+                .to_str()
+                .ok()
+        })
+    else {
+        return false;
+    };
+
+    if let (Some(token), Some(given)) = (&credentials.token, value.strip_prefix("Bearer ")) {
+        return constant_time_eq(
+            token.as_bytes(),
+            given
+                .trim()
+                .as_bytes(),
+        );
+    }
+
+    if let (Some((user, password)), Some(given)) =
+        (&credentials.basic, value.strip_prefix("Basic "))
+    {
+        let expected = format!("{}:{}", user, password);
+        return BASE64_STANDARD
+            .decode(given.trim())
+            .is_ok_and(|given| constant_time_eq(expected.as_bytes(), &given));
+    }
+
+    false
+}
+
+async fn require_auth(request: Request<Body>, next: Next) -> Response {
+    if is_authorized(&request) {
+        return next
+            .run(request)
+            .await;
+    }
+
+    warn!(
+        "Refusing unauthorized request for {}",
+        request
+            .uri()
+            .path()
+    );
+
+    let mut response = (StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+    let challenge = if CREDENTIALS
+        .get()
+        .is_some_and(|credentials| {
+            credentials
+                .basic
+                .is_some()
+        }) {
+        "Basic realm=\"action-hero\""
+    } else {
+        "Bearer"
+    };
+    response
+        .headers_mut()
+        .insert(
+            header::WWW_AUTHENTICATE,
+            challenge
+                .parse()
+                .unwrap(),
+        );
+    response
+}
+
+/// The admin endpoints, if credentials for them have been configured. Any
+/// added later go here, so that they are behind the same check.
+pub(crate) fn admin_router() -> Option<Router> {
+    CREDENTIALS.get()?;
+
+    let router = Router::new()
+        .route("/admin/status", get(status))
+        .route("/admin/reprocess", post(reprocess))
+        .route_layer(middleware::from_fn(require_auth));
+
+    Some(router)
+}
+
+async fn status() -> Json<Value> {
+    let started = *get_program_start();
+    let uptime = OffsetDateTime::now_utc() - started;
+
+    Json(json!({
+        "version": VERSION,
+        "started": started
+            .format(&Rfc3339)
+            .ok(),
+        "uptime_seconds": uptime.whole_seconds(),
+        "queue_depth": queue::depth(),
+        "dead_letter": deadletter::is_enabled(),
+    }))
+}
+
+#[derive(Deserialize)]
+struct ReprocessRequest {
+    #[serde(default)]
+    host: Option<String>,
+    owner: String,
+    repo: String,
+    run_id: u64,
+}
+
+fn refuse(status: StatusCode, message: String) -> Response {
+    warn!("{}", message);
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+/// Send a Run again, whether or not it has been sent before. Its record (if
+/// one is being kept) is removed, and the Run is queued if there is a queue,
+/// otherwise processed in the background.
+async fn reprocess(Json(request): Json<ReprocessRequest>) -> Response {
+    let host = request
+        .host
+        .unwrap_or_else(|| github::GITHUB_HOST.to_string());
+
+    // the API token is only sent to hosts we would accept webhooks from
+    if host != github::GITHUB_HOST && github::get_enterprise_host() != Some(host.as_str()) {
+        return refuse(StatusCode::BAD_REQUEST, format!("Unknown host {}", host));
+    }
+
+    // the workflow is filled in once we have the Run
+    let mut config = Config {
+        host,
+        owner: request.owner,
+        repository: request.repo,
+        workflow: String::new(),
+        devel: false,
+    };
+
+    let client = match github::setup_api_client(&config) {
+        Ok(client) => client,
+        Err(error) => return refuse(StatusCode::BAD_REQUEST, error.to_string()),
+    };
+
+    // the workflow is worked out as for the workflow_run event, so that the
+    // Run goes into the same trace as when it was first sent.
+    let run = match webhook::retrieve_run_of(&mut config, &client, request.run_id, None).await {
+        Ok(run) => run,
+        Err(GitHubProblem::ApiError(StatusCode::NOT_FOUND)) => {
+            return refuse(
+                StatusCode::NOT_FOUND,
+                format!(
+                    "Run {} not found in {}/{}",
+                    request.run_id, config.owner, config.repository
+                ),
+            );
+        }
+        Err(problem) => {
+            return refuse(
+                StatusCode::BAD_GATEWAY,
+                format!("Could not retrieve Run {}: {}", request.run_id, problem),
+            );
+        }
+    };
+
+    if run.status != "completed" {
+        return refuse(
+            StatusCode::CONFLICT,
+            format!("Run {} has not completed", run.run_id),
+        );
+    }
+
+    let forgotten = if history::is_enabled() {
+        let path = history::form_record_filename(&config, &run);
+        match history::forget_run(&path).await {
+            Ok(forgotten) => forgotten,
+            Err(error) => return refuse(StatusCode::INTERNAL_SERVER_ERROR, error.to_string()),
+        }
+    } else {
+        false
+    };
+
+    info!(
+        "Reprocessing Run {} of {}/{} {}",
+        run.run_id, config.owner, config.repository, config.workflow
+    );
+
+    let queued = queue::is_enabled();
+
+    if queued {
+        if let Err(error) = queue::enqueue(&config, &run).await {
+            return refuse(StatusCode::INTERNAL_SERVER_ERROR, error.to_string());
+        }
+    } else {
+        tokio::spawn(async move {
+            if let Err(error) = submit_run(&config, &client, &run).await {
+                warn!("Unable to reprocess Run {}: {}", run.run_id, error);
+            }
+        });
+    }
+
+    (
+        StatusCode::ACCEPTED,
+        Json(json!({
+            "run_id": request.run_id,
+            "record_removed": forgotten,
+            "queued": queued,
+        })),
+    )
+        .into_response()
+}
@@ -0,0 +1,260 @@
+//! Only accept webhook deliveries from the addresses GitHub sends them from.
+//! GitHub publishes the ranges its webhooks come from via its meta API; we
+//! fetch those at startup and then refresh them periodically, rejecting
+//! deliveries from anywhere else. When the listener sits behind a reverse
+//! proxy the connection comes from the proxy, so for proxies we are told to
+//! trust the original address is taken from X-Forwarded-For instead.
+
+use anyhow::{Result, anyhow};
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{OnceLock, RwLock};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use crate::forge::{self, Forge};
+use crate::get_api_token;
+use crate::github::{self, GITHUB_HOST};
+
+/// A range of addresses in CIDR notation, such as "192.30.252.0/22".
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Network {
+    address: IpAddr,
+    prefix: u32,
+}
+
+impl Network {
+    pub(crate) fn parse(value: &str) -> Result<Network> {
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value, None),
+        };
+
+        let address: IpAddr = address
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("Invalid address \"{}\"", value))?;
+
+        let width = if address.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|prefix| *prefix <= width)
+                .ok_or_else(|| anyhow!("Invalid prefix length in \"{}\"", value))?,
+            None => width,
+        };
+
+        Ok(Network { address, prefix })
+    }
+
+    fn contains(&self, address: &IpAddr) -> bool {
+        match (self.address, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix)
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix)
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+static ENABLED: OnceLock<bool> = OnceLock::new();
+
+static HOOK_RANGES: RwLock<Vec<Network>> = RwLock::new(Vec::new());
+
+static TRUSTED_PROXIES: OnceLock<Vec<Network>> = OnceLock::new();
+
+pub(crate) fn set_trusted_proxies(proxies: Vec<Network>) {
+    TRUSTED_PROXIES
+        .set(proxies)
+        .unwrap();
+}
+
+fn is_trusted_proxy(address: &IpAddr) -> bool {
+    TRUSTED_PROXIES
+        .get()
+        .is_some_and(|proxies| {
+            proxies
+                .iter()
+                .any(|network| network.contains(address))
+        })
+}
+
+/// Retrieve the ranges GitHub's webhooks are sent from, for github.com and
+/// for the GitHub Enterprise Server if there is one.
+async fn retrieve_ranges() -> Result<Vec<Network>> {
+    let mut hosts = vec![GITHUB_HOST];
+    if let Some(enterprise) = github::get_enterprise_host() {
+        hosts.push(enterprise);
+    }
+
+    let mut ranges = Vec::new();
+
+    for host in hosts {
+        // Forgejo doesn't publish where its webhooks come from.
+        if forge::forge_of(host) != Forge::GitHub {
+            continue;
+        }
+
+        let token = get_api_token(host, "")
+            .ok_or_else(|| anyhow!("No GitHub token available for {}", host))?;
+        let client = github::build_api_client(host, token)?;
+
+        for range in github::retrieve_hook_ranges(&client, host).await? {
+            ranges.push(Network::parse(&range)?);
+        }
+    }
+
+    Ok(ranges)
+}
+
+/// Fetch the ranges now, failing if they can't be had; a listener asked to
+/// restrict where deliveries come from shouldn't start out accepting
+/// everything (nor nothing).
+pub(crate) async fn enable_allowlist() -> Result<()> {
+    let ranges = retrieve_ranges().await?;
+    info!("Accepting webhooks from {} address ranges", ranges.len());
+
+    *HOOK_RANGES
+        .write()
+        .unwrap() = ranges;
+
+    ENABLED
+        .set(true)
+        .unwrap();
+
+    Ok(())
+}
+
+/// Refresh the ranges every interval. If GitHub can't be reached the ranges
+/// we already have are kept. This runs for as long as the listener does.
+pub(crate) async fn run_refresh(interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+
+    // the first tick is immediate, and we have only just fetched them
+    ticker
+        .tick()
+        .await;
+
+    loop {
+        ticker
+            .tick()
+            .await;
+
+        match retrieve_ranges().await {
+            Ok(ranges) => {
+                debug!("Refreshed {} webhook address ranges", ranges.len());
+                *HOOK_RANGES
+                    .write()
+                    .unwrap() = ranges;
+            }
+            Err(error) => warn!("Unable to refresh webhook address ranges: {}", error),
+        }
+    }
+}
+
+/// Work out where a request really came from. If the connection is from a
+/// trusted proxy we walk back through the addresses it (and any proxies
+/// before it) added to X-Forwarded-For, stopping at the first one that isn't
+/// itself a trusted proxy.
+fn client_address(request: &Request<Body>, peer: IpAddr) -> IpAddr {
+    if !is_trusted_proxy(&peer) {
+        return peer;
+    }
+
+    let forwarded: Vec<IpAddr> = request
+        .headers()
+        .get_all("X-Forwarded-For")
+        .iter()
+        .filter_map(|value| {
+            value
+                .to_str()
+                .ok()
+        })
+        .flat_map(|value| value.split(','))
+        .filter_map(|address| {
+            address
+                .trim()
+                .parse()
+                .ok()
+        })
+        .collect();
+
+    let mut client = peer;
+    for address in forwarded
+        .into_iter()
+        .rev()
+    {
+        client = address;
+        if !is_trusted_proxy(&address) {
+            break;
+        }
+    }
+
+    client
+}
+
+pub(crate) fn is_enabled() -> bool {
+    ENABLED
+        .get()
+        .is_some()
+}
+
+/// Reject webhook deliveries from addresses GitHub doesn't send from.
+/// Anything other than a POST is let through.
+pub(crate) async fn check_source(request: Request<Body>, next: Next) -> Response {
+    if ENABLED
+        .get()
+        .is_none()
+        || request.method() != Method::POST
+    {
+        return next
+            .run(request)
+            .await;
+    }
+
+    let Some(ConnectInfo(peer)) = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .copied()
+    else {
+        return next
+            .run(request)
+            .await;
+    };
+
+    let address = client_address(&request, peer.ip());
+
+    let allowed = HOOK_RANGES
+        .read()
+        .unwrap()
+        .iter()
+        .any(|network| network.contains(&address));
+
+    if !allowed {
+        warn!("Rejecting webhook delivery from {}", address);
+        return (
+            StatusCode::FORBIDDEN,
+            format!("Not accepting deliveries from {}", address),
+        )
+            .into_response();
+    }
+
+    next.run(request)
+        .await
+}
@@ -0,0 +1,2562 @@
+use anyhow::{Ok, Result, anyhow};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use futures::future;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use regex::Regex;
+use std::path::{Path, PathBuf};
+use std::{net::Ipv4Addr, process::ExitCode};
+use tracing::{debug, info, warn};
+
+use crate::github::{self, Config, GitHubProblem, WorkflowRun};
+use crate::settings::{
+    self, AttributeMappings, ConfigProfile, Settings, SkippedSteps, SpanKinds, StepFilter,
+};
+use crate::traces::{self, ExporterOptions};
+#[cfg(feature = "tui")]
+use crate::tui;
+use crate::{
+    ApiTokens, TOKEN_SOURCE, TokenSource, VERSION, admin, allowlist, compare, deadletter, fixtures,
+    forge, forward, get_api_token, get_program_start, history, live, metrics, mock, presets, queue,
+    read_api_tokens, recovery, redaction, set_api_tokens, set_program_start, signature, simulate,
+    submit_run, summary, webhook,
+};
+
+/// The `hero` program, run with the arguments it was given.
+pub async fn main() -> ExitCode {
+    // errors are reported here rather than by returning them from main() so
+    // that they are scrubbed of secrets like everything else we log.
+    match program().await {
+        Result::Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {}", redaction::scrub(&format!("{:?}", error)));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn program() -> Result<()> {
+    // Record start time
+    set_program_start();
+
+    // Initialize the logging subsystem, with secrets scrubbed from its output
+    tracing_subscriber::fmt()
+        .with_writer(redaction::ScrubbingWriter)
+        .init();
+
+    // Configure command-line argument parser
+    let command = Command::new("hero")
+            .version(VERSION)
+            .propagate_version(true)
+            .author("Andrew Cowie")
+            .about("Retrieve workflow and run from GitHub Actions and send to OpenTelemetry as spans and traces.")
+            .disable_help_subcommand(true)
+            .disable_help_flag(true)
+            .disable_version_flag(true)
+            .arg(
+                Arg::new("help")
+                    .long("help")
+                    .long_help("Print help")
+                    .global(true)
+                    .hide(true)
+                    .action(ArgAction::Help))
+            .arg(
+                Arg::new("version")
+                    .long("version")
+                    .long_help("Print version")
+                    .global(true)
+                    .hide(true)
+                    .action(ArgAction::Version))
+            .arg(
+                Arg::new("token-file")
+                    .long("token-file")
+                    .value_name("PATH")
+                    .global(true)
+                    .conflicts_with("token-stdin")
+                    .long_help("Read the default GitHub token from this file, in place of the GITHUB_TOKEN environment variable or credential. The file is read again when the listener reloads, so a token rotated by a secret manager is picked up."))
+            .arg(
+                Arg::new("token-stdin")
+                    .long("token-stdin")
+                    .global(true)
+                    .action(ArgAction::SetTrue)
+                    .long_help("Read the default GitHub token from the first line of standard input, in place of the GITHUB_TOKEN environment variable or credential."))
+            .arg(
+                Arg::new("github-http2")
+                    .long("github-http2")
+                    .global(true)
+                    .action(ArgAction::SetTrue)
+                    .long_help("Offer HTTP/2 when connecting to the GitHub API (and wherever it redirects to), so that with hosts which accept it requests share a single connection. Others, and plain HTTP, carry on with HTTP/1.1."))
+            .arg(
+                Arg::new("github-pool-idle")
+                    .long("github-pool-idle")
+                    .value_name("SECONDS")
+                    .global(true)
+                    .value_parser(clap::value_parser!(u64))
+                    .long_help("How long to keep an unused connection to the GitHub API open for reuse, in seconds. The default is 90."))
+            .arg(
+                Arg::new("github-max-idle")
+                    .long("github-max-idle")
+                    .value_name("COUNT")
+                    .global(true)
+                    .value_parser(clap::value_parser!(usize))
+                    .long_help("The most unused connections to each GitHub API host to keep open for reuse. This doesn't limit how many are open while requests are in flight; for `hero query` that follows from --concurrency. By default there is no limit."))
+            .arg(
+                Arg::new("user-agent-suffix")
+                    .long("user-agent-suffix")
+                    .value_name("TEXT")
+                    .global(true)
+                    .long_help("Text to append to the User-Agent sent with requests of the GitHub API, such as a team name or contact URL, so that GitHub can tell which deployment traffic came from. The User-Agent is otherwise \"action-hero/\" followed by the version."))
+            .arg(
+                Arg::new("forge")
+                    .long("forge")
+                    .value_name("KIND")
+                    .global(true)
+                    .value_parser(forge::FORGES)
+                    .long_help("What the --enterprise-host is running. The default is \"github\", for a GitHub Enterprise Server; give \"forgejo\" for a Forgejo (or Gitea) instance, whose API is reached and authenticated with differently and whose Runs and Jobs are adapted into the form GitHub would give them in. The token is the GITHUB_ENTERPRISE_TOKEN either way."))
+            .subcommand(
+                Command::new("listen")
+                    .about("Run HTTP server to receive webhook events from GitHub")
+                    .arg(Arg::new("host")
+                        .long("host")
+                        .long_help("Override the address the receiver will listen on. The default is 0.0.0.0")
+                    )
+                    .arg(Arg::new("port")
+                        .long("port")
+                        .long_help("Override the port the receiver will listen on. The default is port 34484")
+                    )
+                    .arg(Arg::new("state-dir")
+                        .long("state-dir")
+                        .long_help("Directory where records of processed Runs are kept. If this is the same directory used by `hero query` then Runs already sent by one won't be sent again by the other. Without this no record is kept when listening.")
+                    )
+                    .arg(Arg::new("clean-state")
+                        .long("clean-state")
+                        .action(ArgAction::SetTrue)
+                        .long_help("At startup, remove claims on Runs left behind in the --state-dir by processes which died, and Runs in the --queue-dir which are partially written or have been there for more than a week. Everything removed is logged.")
+                    )
+                    .arg(Arg::new("queue-dir")
+                        .long("queue-dir")
+                        .long_help("Directory in which to queue Runs as they are received, responding to GitHub straight away and processing them afterwards. Runs still in the queue when the listener stops are processed when it next starts. Without this Runs are processed before responding to GitHub.")
+                    )
+                    .arg(Arg::new("dead-letter-dir")
+                        .long("dead-letter-dir")
+                        .requires("queue-dir")
+                        .long_help("Directory to move queued Runs to when they can't be processed: immediately if the payload can't be decoded, otherwise after failing five times over the course of at least an hour. A file describing the failure is written alongside each. Without this such Runs stay in the --queue-dir and are tried again until they expire.")
+                    )
+                    .arg(Arg::new("self-metrics")
+                        .long("self-metrics")
+                        .action(ArgAction::SetTrue)
+                        .long_help("Send metrics about the listener itself: the number of tasks in the async runtime and how many are waiting to run, its memory use, the number of Runs in the --queue-dir, and counts of Runs processed and failed. These show when the listener is falling behind.")
+                    )
+                    .arg(Arg::new("max-events")
+                        .long("max-events")
+                        .value_name("COUNT")
+                        .value_parser(clap::value_parser!(u64).range(1..))
+                        .conflicts_with("queue-dir")
+                        .long_help("Exit after this many webhook events have been handled, once the telemetry for them has been sent. This is intended for smoke tests and for scripting the listener in CI.")
+                    )
+                    .arg(Arg::new("status-interval")
+                        .long("status-interval")
+                        .value_name("MINUTES")
+                        .value_parser(clap::value_parser!(u64))
+                        .long_help("How often to log a one line summary of the listener's activity: webhooks received, Runs exported and failed, the number of Runs in the --queue-dir, and how many requests remain in the GitHub rate limit. The default is every 15 minutes; 0 turns the summary off.")
+                    )
+                    .arg(Arg::new("live-jobs")
+                        .long("live-jobs")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("only-failures")
+                        .long_help("Also receive workflow_job events, and send the spans of each Job as soon as it completes rather than waiting for its Run to. The Run's span and any Jobs not already sent follow when the Run completes, in the same trace. Long Runs then show up in the backend as they progress. The webhook must be subscribed to \"Workflow jobs\" events as well as \"Workflow runs\".")
+                    )
+                    .arg(Arg::new("provisional-jobs")
+                        .long("provisional-jobs")
+                        .action(ArgAction::SetTrue)
+                        .requires("live-jobs")
+                        .long_help("With --live-jobs, also send a short provisional span for each Job as it starts, marked with the attribute provisional=true. The Job's real span follows when it completes.")
+                    )
+                    .arg(Arg::new("recover-hook")
+                        .long("recover-hook")
+                        .value_name("TARGET:ID")
+                        .action(ArgAction::Append)
+                        .long_help("Periodically check the deliveries made by this webhook on github.com, and have GitHub redeliver any events which failed to be received or processed (for example while the listener was restarting). Give the webhook as \"owner/repo:ID\", or as \"owner:ID\" for an organization's webhook. This option can be given more than once.")
+                    )
+                    .arg(Arg::new("recover-interval")
+                        .long("recover-interval")
+                        .value_parser(clap::value_parser!(u64))
+                        .long_help("How often to check for failed deliveries, in seconds. The default is 300.")
+                    )
+                    .arg(Arg::new("restrict-sources")
+                        .long("restrict-sources")
+                        .action(ArgAction::SetTrue)
+                        .long_help("Only accept webhook deliveries from the address ranges GitHub publishes (via its meta API) as those its webhooks are sent from. The ranges are fetched at startup, for github.com and for the --enterprise-host if there is one, and refreshed periodically.")
+                    )
+                    .arg(Arg::new("restrict-interval")
+                        .long("restrict-interval")
+                        .value_parser(clap::value_parser!(u64))
+                        .requires("restrict-sources")
+                        .long_help("How often to refresh GitHub's webhook address ranges, in seconds. The default is 3600.")
+                    )
+                    .arg(Arg::new("trusted-proxy")
+                        .long("trusted-proxy")
+                        .action(ArgAction::Append)
+                        .value_name("CIDR")
+                        .long_help("An address, or range of addresses such as \"10.0.0.0/8\", of a reverse proxy in front of the listener. For connections from a trusted proxy, the address a delivery came from is taken from the X-Forwarded-For header. This option can be given more than once.")
+                    )
+                    .arg(Arg::new("secret")
+                        .long("secret")
+                        .long_help("The secret GitHub signs deliveries with, as given to the webhook (or to `hero install-webhook`). Deliveries without a valid X-Hub-Signature-256 are refused with 401 Unauthorized before anything is done with them. If not given, the GITHUB_WEBHOOK_SECRET environment variable or a webhook-secret credential is used if set; without any of these, deliveries aren't verified.")
+                    )
+                    .arg(Arg::new("forward-to")
+                        .long("forward-to")
+                        .value_name("URL")
+                        .action(ArgAction::Append)
+                        .long_help("Once each webhook delivery has been processed, send it on to this URL, with the body and headers exactly as GitHub sent them (so its signature still verifies). Every delivery is forwarded, including events this program ignores, but not those refused by --restrict-sources. Failures to forward are logged and not retried. This option can be given more than once.")
+                    )
+                    .arg(Arg::new("enterprise-host")
+                        .long("enterprise-host")
+                        .value_name("HOST")
+                        .long_help("Also accept events from repositories on this GitHub Enterprise Server instance, for example \"github.example.com\". The API of that instance is accessed with the GITHUB_ENTERPRISE_TOKEN token. Events from github.com continue to be accepted as normal.")
+                    )
+                    .args(processing_args())
+                    .args(exporter_args())
+            )
+            .subcommand(
+                Command::new("query")
+                    .about("Query workflow runs directly")
+                    .arg(
+                        Arg::new("count")
+                            .long("count" )
+                            .long_help("The number of Runs for the specified Workflow to retrieve from GitHub and upload to Honeycomb. The default if unspecified is to check the 10 most recent Runs.")
+                        )
+                    .arg(
+                        Arg::new("repository")
+                            .action(ArgAction::Set)
+                            .required_unless_present("from-file")
+                            .long_help("Name of the GitHub organization and repository to retrieve workflows from. This must be specified in the form \"owner/repo\", or as the repository's URL."))
+                    .arg(
+                        Arg::new("workflow")
+                            .action(ArgAction::Set)
+                            .required_unless_present("from-file")
+                            .help("Name of the GitHub Actions workflow to present as a trace. This is typically a filename such as \"check.yaml\", but the workflow's numeric ID can be given instead."))
+                    .arg(
+                        Arg::new("from-file")
+                            .long("from-file")
+                            .value_name("FILE")
+                            .conflicts_with_all(["repository", "workflow"])
+                            .long_help("Query each of the repositories and workflows listed in this file, one \"owner/repo workflow\" pair per line, rather than a single one given on the command-line. Blank lines and lines starting with '#' are ignored. Give \"-\" to read the list from stdin. A failure querying one workflow is logged and the rest are still queried.")
+                        )
+                    .arg(
+                        Arg::new("state-dir")
+                            .long("state-dir")
+                            .action(ArgAction::Set)
+                            .long_help("Directory where records of processed Runs are written. The default is \"record\" under the current working directory, or on Windows hero\\record under %APPDATA%.")
+                        )
+                    .arg(
+                        Arg::new("incremental")
+                            .long("incremental")
+                            .action(ArgAction::SetTrue)
+                            .long_help("Only ask GitHub for Runs created since those seen by the previous query, rather than re-checking the most recent Runs each time. If more than --count Runs were created since then, further pages are requested until all of them have been. The point reached is kept in the --state-dir. Note that new attempts of Runs from before that point will not be picked up.")
+                        )
+                    .arg(
+                        Arg::new("attempts")
+                            .long("attempts")
+                            .value_parser(["latest", "all"])
+                            .long_help("Whether to send only the latest attempt of each Run (the default), or \"all\" of them, each as a trace of its own. Retrieving the earlier attempts of a re-run Run takes a request for each, but having them shows which Jobs failed and then passed when run again.")
+                        )
+                    .arg(
+                        Arg::new("clean-state")
+                            .long("clean-state")
+                            .action(ArgAction::SetTrue)
+                            .long_help("Before querying, remove claims on Runs left behind in the --state-dir by processes which died. Everything removed is logged.")
+                        )
+                    .arg(
+                        Arg::new("concurrency")
+                            .long("concurrency")
+                            .value_name("COUNT")
+                            .value_parser(clap::value_parser!(usize))
+                            .long_help("How many Runs to process at once. Most of the time spent on each Run is waiting for GitHub to return its Jobs and logs, so processing several together is much faster. The default is 4.")
+                        )
+                    .arg(
+                        Arg::new("enterprise-host")
+                            .long("enterprise-host")
+                            .value_name("HOST")
+                            .long_help("Query the repository on this GitHub Enterprise Server instance rather than on github.com, for example \"github.example.com\". The API of that instance is accessed with the GITHUB_ENTERPRISE_TOKEN token.")
+                        )
+                    .args(processing_args())
+                    .args(exporter_args())
+            )
+            .subcommand(
+                Command::new("runs")
+                    .about("List recent Runs of a workflow")
+                    .arg(
+                        Arg::new("count")
+                            .long("count" )
+                            .long_help("The number of Runs to list. The default if unspecified is the 10 most recent Runs.")
+                        )
+                    .arg(
+                        Arg::new("repository")
+                            .action(ArgAction::Set)
+                            .required(true)
+                            .long_help("Name of the GitHub organization and repository the workflow is in. This must be specified in the form \"owner/repo\", or as the repository's URL."))
+                    .arg(
+                        Arg::new("workflow")
+                            .action(ArgAction::Set)
+                            .required(true)
+                            .help("Name of the GitHub Actions workflow, typically a filename such as \"check.yaml\", or its numeric ID."))
+                    .arg(
+                        Arg::new("state-dir")
+                            .long("state-dir")
+                            .action(ArgAction::Set)
+                            .long_help("Directory where records of processed Runs are written, used to show whether each Run has been sent. The default is \"record\" under the current working directory, or on Windows hero\\record under %APPDATA%.")
+                        )
+                    .arg(
+                        Arg::new("enterprise-host")
+                            .long("enterprise-host")
+                            .value_name("HOST")
+                            .long_help("List Runs from this GitHub Enterprise Server instance rather than from github.com.")
+                        )
+            )
+            .subcommand(
+                Command::new("history")
+                    .about("Inspect the record of Runs which have been sent")
+                    .subcommand_required(true)
+                    .subcommand(
+                        Command::new("export")
+                            .about("Write out the record of sent Runs as CSV or JSON")
+                            .arg(
+                                Arg::new("state-dir")
+                                    .long("state-dir")
+                                    .action(ArgAction::Set)
+                                    .long_help("Directory where records of processed Runs are written. The default is \"record\" under the current working directory, or on Windows hero\\record under %APPDATA%.")
+                                )
+                            .arg(
+                                Arg::new("format")
+                                    .long("format")
+                                    .value_parser(["csv", "json"])
+                                    .long_help("Whether to write CSV (the default) or a JSON array.")
+                                )
+                    )
+            )
+            .subcommand(
+                Command::new("open")
+                    .about("Print the URL of the trace of a Run which has been sent, or open it in a browser")
+                    .arg(
+                        Arg::new("run_id")
+                            .action(ArgAction::Set)
+                            .required(true)
+                            .value_parser(clap::value_parser!(u64))
+                            .long_help("The ID of the Run, as found at the end of its URL on GitHub."))
+                    .arg(
+                        Arg::new("attempt")
+                            .long("attempt")
+                            .value_parser(clap::value_parser!(u64).range(1..))
+                            .long_help("Which attempt of the Run to open. The default is the latest attempt that was sent."))
+                    .arg(
+                        Arg::new("trace-url")
+                            .long("trace-url")
+                            .required(true)
+                            .long_help("Template for the URL of a trace in your telemetry backend, as given to `hero query` or `hero listen`. The placeholder {trace_id} is replaced with the TraceId recorded for the Run, and {trace_start} and {trace_end} with the Run's start and finish as Unix timestamps, which means retrieving the Run from GitHub. If there is no {trace_id} placeholder the TraceId is appended."))
+                    .arg(
+                        Arg::new("browser")
+                            .long("browser")
+                            .action(ArgAction::SetTrue)
+                            .long_help("Open the URL in the desktop's web browser as well as printing it."))
+                    .arg(
+                        Arg::new("state-dir")
+                            .long("state-dir")
+                            .long_help("Directory where records of processed Runs are kept. The default is \"record\" under the current working directory, or on Windows hero\\record under %APPDATA%."))
+                    .arg(
+                        Arg::new("enterprise-host")
+                            .long("enterprise-host")
+                            .value_name("HOST")
+                            .long_help("The GitHub Enterprise Server instance the Run is on, if it isn't on github.com. Only needed if the template has {trace_start} or {trace_end}."))
+            )
+            .subcommand(
+                Command::new("compare")
+                    .about("Show how long each Job and Step of two Runs took, and what changed")
+                    .arg(
+                        Arg::new("repository")
+                            .action(ArgAction::Set)
+                            .required(true)
+                            .long_help("Name of the GitHub organization and repository the Runs are in. This must be specified in the form \"owner/repo\", or as the repository's URL."))
+                    .arg(
+                        Arg::new("run_a")
+                            .action(ArgAction::Set)
+                            .required(true)
+                            .value_parser(clap::value_parser!(u64))
+                            .long_help("The ID of one of the Runs, as found at the end of its URL on GitHub."))
+                    .arg(
+                        Arg::new("run_b")
+                            .action(ArgAction::Set)
+                            .required(true)
+                            .value_parser(clap::value_parser!(u64))
+                            .long_help("The ID of the other Run, of the same workflow. Whichever order they are given in, the changes are from the older Run to the newer."))
+                    .arg(
+                        Arg::new("emit")
+                            .long("emit")
+                            .action(ArgAction::SetTrue)
+                            .long_help("Also send a span into the trace of the newer Run, carrying the change in its duration and in that of each of its Jobs as attributes. The newer Run should already have been sent, with the same workflow and --enterprise-host."))
+                    .arg(
+                        Arg::new("enterprise-host")
+                            .long("enterprise-host")
+                            .value_name("HOST")
+                            .long_help("Retrieve the Runs from this GitHub Enterprise Server instance rather than from github.com."))
+                    .args(exporter_args())
+            )
+            .subcommand(
+                Command::new("logs")
+                    .about("Print the log of a Job")
+                    .arg(
+                        Arg::new("repository")
+                            .action(ArgAction::Set)
+                            .required(true)
+                            .long_help("Name of the GitHub organization and repository the Job ran in. This must be specified in the form \"owner/repo\", or as the repository's URL."))
+                    .arg(
+                        Arg::new("job_id")
+                            .action(ArgAction::Set)
+                            .required(true)
+                            .value_parser(clap::value_parser!(u64))
+                            .long_help("The ID of the Job, as found in the job_id attribute of its span or at the end of the Job's URL."))
+                    .arg(
+                        Arg::new("errors")
+                            .long("errors")
+                            .action(ArgAction::SetTrue)
+                            .long_help("Print only the lines which would be considered error messages, redacted as they would be before being attached to a span. The first of these is the one that is attached."))
+                    .arg(
+                        Arg::new("enterprise-host")
+                            .long("enterprise-host")
+                            .value_name("HOST")
+                            .long_help("Retrieve the log from this GitHub Enterprise Server instance rather than from github.com."))
+            )
+            .subcommand(
+                Command::new("resubmit-dead-letter")
+                    .about("List Runs in the dead-letter directory, or try sending them again")
+                    .arg(
+                        Arg::new("dead-letter-dir")
+                            .long("dead-letter-dir")
+                            .required(true)
+                            .long_help("The dead-letter directory given to `hero listen`."))
+                    .arg(
+                        Arg::new("name")
+                            .action(ArgAction::Append)
+                            .long_help("The names of the Runs to send again, as listed when this command is run without any. Each Run which is sent successfully is removed from the dead-letter directory."))
+                    .arg(
+                        Arg::new("all")
+                            .long("all")
+                            .action(ArgAction::SetTrue)
+                            .conflicts_with("name")
+                            .long_help("Send every Run in the dead-letter directory again."))
+                    .arg(
+                        Arg::new("state-dir")
+                            .long("state-dir")
+                            .long_help("Directory where records of processed Runs are kept, as given to `hero listen`."))
+                    .arg(
+                        Arg::new("enterprise-host")
+                            .long("enterprise-host")
+                            .value_name("HOST")
+                            .long_help("The GitHub Enterprise Server instance given to `hero listen`, if any. Runs from it are retrieved using the GITHUB_ENTERPRISE_TOKEN token."))
+                    .args(processing_args())
+                    .args(exporter_args())
+            )
+            .subcommand(
+                Command::new("install-webhook")
+                    .about("Create or update the webhook sending events to a listener")
+                    .arg(
+                        Arg::new("target")
+                            .action(ArgAction::Set)
+                            .required(true)
+                            .long_help("The repository to install the webhook on, in the form \"owner/repo\", or the name of an organization to install it across all of that organization's repositories."))
+                    .arg(
+                        Arg::new("url")
+                            .long("url")
+                            .required(true)
+                            .long_help("The URL at which `hero listen` is reachable from GitHub, for example \"https://hero.example.com/\"."))
+                    .arg(
+                        Arg::new("secret")
+                            .long("secret")
+                            .long_help("The secret GitHub should sign deliveries with. If not given, the GITHUB_WEBHOOK_SECRET environment variable is used if set."))
+                    .arg(
+                        Arg::new("enterprise-host")
+                            .long("enterprise-host")
+                            .value_name("HOST")
+                            .long_help("Install the webhook on this GitHub Enterprise Server instance rather than on github.com."))
+            )
+            .subcommand(
+                Command::new("simulate")
+                    .about("Send fabricated Runs, to test how much telemetry a collector and backend can take")
+                    .arg(
+                        Arg::new("runs")
+                            .long("runs")
+                            .value_parser(clap::value_parser!(u32))
+                            .long_help("The number of Runs to send. The default is 10."))
+                    .arg(
+                        Arg::new("jobs")
+                            .long("jobs")
+                            .value_parser(clap::value_parser!(u32))
+                            .long_help("The number of Jobs in each Run. The default is 5."))
+                    .arg(
+                        Arg::new("steps")
+                            .long("steps")
+                            .value_parser(clap::value_parser!(u32))
+                            .long_help("The number of Steps in each Job. The default is 8."))
+                    .arg(
+                        Arg::new("step-duration")
+                            .long("step-duration")
+                            .value_parser(clap::value_parser!(f64))
+                            .long_help("The average duration of a Step, in seconds. Durations vary around this, most being shorter with the occasional much longer one. The default is 30."))
+                    .arg(
+                        Arg::new("failure-rate")
+                            .long("failure-rate")
+                            .value_parser(clap::value_parser!(f64))
+                            .long_help("The chance of each Job failing, between 0 and 1. The default is 0.1."))
+                    .arg(
+                        Arg::new("interval")
+                            .long("interval")
+                            .value_parser(clap::value_parser!(u64))
+                            .long_help("How long to wait between sending each Run, in milliseconds. The default is to send them as fast as possible."))
+                    .arg(
+                        Arg::new("seed")
+                            .long("seed")
+                            .value_parser(clap::value_parser!(u64))
+                            .long_help("Seed for the random choices made in fabricating Runs, so that a simulation can be repeated exactly. The default is taken from the current time."))
+                    .args(processing_args())
+                    .args(exporter_args())
+            )
+            .subcommand(
+                Command::new("mock-github")
+                    .about("Run a stand-in for the GitHub API serving canned Runs, Jobs, and logs")
+                    .arg(
+                        Arg::new("host")
+                            .long("host")
+                            .long_help("The address to listen on. The default is to listen on all interfaces."))
+                    .arg(
+                        Arg::new("port")
+                            .long("port")
+                            .value_parser(clap::value_parser!(u16))
+                            .long_help("The port to listen on. The default is 34485. Point other invocations of hero at it by setting HERO_GITHUB_API_URL to \"http://localhost:34485\"."))
+            );
+
+    #[cfg(feature = "tui")]
+    let command = command.subcommand(
+        Command::new("tui")
+            .about("Browse recent Runs interactively, sending them and opening their traces")
+            .arg(
+                Arg::new("count")
+                    .long("count")
+                    .long_help("The number of Runs of each workflow to show. The default if unspecified is the 10 most recent Runs."))
+            .arg(
+                Arg::new("repository")
+                    .action(ArgAction::Set)
+                    .required(true)
+                    .long_help("Name of the GitHub organization and repository the workflows are in. This must be specified in the form \"owner/repo\", or as the repository's URL."))
+            .arg(
+                Arg::new("workflow")
+                    .action(ArgAction::Append)
+                    .required(true)
+                    .num_args(1..)
+                    .help("Names of the GitHub Actions workflows to show, typically filenames such as \"check.yaml\", or their numeric IDs."))
+            .arg(
+                Arg::new("state-dir")
+                    .long("state-dir")
+                    .action(ArgAction::Set)
+                    .long_help("Directory where records of processed Runs are written. The default is \"record\" under the current working directory, or on Windows hero\\record under %APPDATA%."))
+            .arg(
+                Arg::new("enterprise-host")
+                    .long("enterprise-host")
+                    .value_name("HOST")
+                    .long_help("Show Runs from this GitHub Enterprise Server instance rather than from github.com."))
+            .args(processing_args())
+            .args(exporter_args()),
+    );
+
+    let matches = with_environment(command).get_matches();
+
+    if let Some(path) = matches.get_one::<String>("token-file") {
+        TOKEN_SOURCE
+            .set(TokenSource::File(PathBuf::from(path)))
+            .ok();
+    } else if matches.get_flag("token-stdin") {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        let token = line
+            .trim()
+            .to_string();
+        if token.is_empty() {
+            return Err(anyhow!("No token given on standard input"));
+        }
+        TOKEN_SOURCE
+            .set(TokenSource::Given(token))
+            .ok();
+    }
+
+    github::set_client_options(github::ClientOptions {
+        http2: matches.get_flag("github-http2"),
+        pool_idle_timeout: matches
+            .get_one::<u64>("github-pool-idle")
+            .map(|seconds| std::time::Duration::from_secs(*seconds)),
+        max_idle: matches
+            .get_one::<usize>("github-max-idle")
+            .copied(),
+        user_agent_suffix: matches
+            .get_one::<String>("user-agent-suffix")
+            .cloned(),
+    });
+
+    let profile = match matches.subcommand() {
+        Some((_, submatches)) => select_profile(submatches)?,
+        None => ConfigProfile::default(),
+    };
+
+    if let Some(name) = &profile.service_name {
+        traces::set_service_name(name.clone());
+    }
+
+    // Initialize the opentelemetry exporter
+    let exporter = match matches.subcommand() {
+        Some((_, submatches)) => exporter_options(submatches, &profile)?,
+        None => ExporterOptions::default(),
+    };
+    let provider = traces::setup_telemetry_machinery(&exporter)?;
+    let self_provider = traces::setup_self_telemetry(&exporter)?;
+    let meter_provider = metrics::setup_metrics_machinery(&exporter)?;
+    let self_meter_provider = match matches.subcommand() {
+        Some(("listen", submatches)) if submatches.get_flag("self-metrics") => {
+            Some(metrics::setup_self_metrics(&exporter)?)
+        }
+        _ => None,
+    };
+
+    // when developing we reset all the start times to be offset from when
+    // this program started running.
+
+    let devel = std::env::var("HERO_DEVELOPER").is_ok();
+
+    // ensure GitHub API token available from environment, and that GitHub
+    // will actually accept it. Inspecting our own records (or listing the
+    // dead-letter directory) doesn't involve GitHub at all, so there is no
+    // need in that case.
+    let offline = match matches.subcommand() {
+        Some(("history", _)) => true,
+        Some(("open", submatches)) => !submatches
+            .get_one::<String>("trace-url")
+            .is_some_and(|template| traces::trace_url_needs_times(template)),
+        Some(("mock-github", _)) => true,
+        Some(("simulate", _)) => true,
+        Some(("resubmit-dead-letter", submatches)) => {
+            !submatches.contains_id("name") && !submatches.get_flag("all")
+        }
+        _ => false,
+    };
+
+    if let Some((_, submatches)) = matches.subcommand() {
+        if let Some(directory) = submatches
+            .try_get_one::<String>("record-fixtures")
+            .ok()
+            .flatten()
+        {
+            fixtures::set_record_directory(directory)?;
+        }
+        if let Some(directory) = submatches
+            .try_get_one::<String>("fixtures")
+            .ok()
+            .flatten()
+        {
+            fixtures::set_replay_directory(directory);
+        }
+    }
+
+    if !offline {
+        // no token is needed to read responses from fixtures, but one is
+        // used if given so that the same owners are accessible.
+        let tokens = match read_api_tokens(&profile) {
+            Err(_) if fixtures::is_replaying() => ApiTokens::placeholder(),
+            result => result?,
+        };
+
+        if let Some(name) = matches.get_one::<String>("forge") {
+            forge::set_self_hosted_forge(name);
+        }
+
+        github::set_enterprise_host(
+            matches
+                .subcommand()
+                .and_then(|(_, submatches)| {
+                    submatches
+                        .try_get_one::<String>("enterprise-host")
+                        .ok()
+                        .flatten()
+                        .cloned()
+                }),
+        );
+
+        if !fixtures::is_replaying() {
+            tokens
+                .validate()
+                .await?;
+        }
+        set_api_tokens(tokens);
+    }
+
+    if let Some((_, submatches)) = matches.subcommand() {
+        settings::set_settings(form_settings(submatches)?);
+    }
+
+    match matches.subcommand() {
+        Some(("listen", submatches)) => {
+            let host = submatches.get_one::<String>("host");
+            let host = match host {
+                None => Ipv4Addr::UNSPECIFIED,
+                Some(value) => value
+                    .parse()
+                    .expect("Unable to parse supplied --host value"),
+            };
+
+            let port = submatches.get_one::<String>("port");
+            let port = match port {
+                None => 34484,
+                Some(value) => value
+                    .parse::<u16>()
+                    .expect("Unable to parse supplied --port value"),
+            };
+
+            let targets = submatches
+                .get_many::<String>("recover-hook")
+                .into_iter()
+                .flatten()
+                .map(|value| recovery::parse_target(value))
+                .collect::<Result<Vec<_>>>()?;
+
+            let interval = submatches
+                .get_one::<u64>("recover-interval")
+                .copied()
+                .unwrap_or(300);
+
+            admin::load_admin_credentials()?;
+
+            signature::load_webhook_secret(
+                submatches
+                    .get_one::<String>("secret")
+                    .cloned(),
+            )?;
+
+            let proxies = submatches
+                .get_many::<String>("trusted-proxy")
+                .into_iter()
+                .flatten()
+                .map(|value| allowlist::Network::parse(value))
+                .collect::<Result<Vec<_>>>()?;
+            allowlist::set_trusted_proxies(proxies);
+
+            if submatches.get_flag("restrict-sources") {
+                let interval = submatches
+                    .get_one::<u64>("restrict-interval")
+                    .copied()
+                    .unwrap_or(3600);
+
+                allowlist::enable_allowlist().await?;
+                tokio::spawn(allowlist::run_refresh(std::time::Duration::from_secs(
+                    interval,
+                )));
+            }
+
+            let clean = submatches.get_flag("clean-state");
+
+            if let Some(directory) = submatches.get_one::<String>("state-dir") {
+                history::set_record_directory(directory)?;
+                history::lock_record_directory("listen")?;
+                if clean {
+                    history::collect_garbage()?;
+                }
+            }
+
+            if let Some(directory) = submatches.get_one::<String>("dead-letter-dir") {
+                deadletter::set_dead_letter_directory(directory)?;
+            }
+
+            if let Some(directory) = submatches.get_one::<String>("queue-dir") {
+                queue::set_queue_directory(directory)?;
+                if clean {
+                    queue::collect_garbage()?;
+                }
+                tokio::spawn(queue::run_worker());
+            }
+
+            if !targets.is_empty() {
+                tokio::spawn(recovery::run_recovery(
+                    targets,
+                    std::time::Duration::from_secs(interval),
+                ));
+            }
+
+            if let Some(count) = submatches.get_one::<u64>("max-events") {
+                webhook::set_max_events(*count);
+            }
+
+            let targets: Vec<String> = submatches
+                .get_many::<String>("forward-to")
+                .unwrap_or_default()
+                .cloned()
+                .collect();
+            if !targets.is_empty() {
+                forward::set_forward_targets(targets)?;
+            }
+
+            if submatches.get_flag("live-jobs") {
+                live::enable(submatches.get_flag("provisional-jobs"));
+            }
+
+            let minutes = submatches
+                .get_one::<u64>("status-interval")
+                .copied()
+                .unwrap_or(15);
+            if minutes > 0 {
+                tokio::spawn(summary::run_summary(std::time::Duration::from_secs(
+                    minutes * 60,
+                )));
+            }
+
+            tokio::spawn(run_reload(submatches.clone()));
+
+            run_listen(host, port).await?;
+        }
+        Some(("query", submatches)) => {
+            let batch = submatches
+                .get_one::<String>("from-file")
+                .map(|path| read_batch(path, devel))
+                .transpose()?;
+
+            let count = submatches.get_one::<String>("count");
+            let count = match count {
+                None => 10,
+                Some(value) => value
+                    .parse::<u32>()
+                    .expect("Unable to parse supplied --count value"),
+            };
+
+            let state_dir = submatches
+                .get_one::<String>("state-dir")
+                .cloned()
+                .unwrap_or_else(history::default_record_directory);
+
+            history::set_record_directory(&state_dir)?;
+            history::lock_record_directory("query")?;
+
+            if submatches.get_flag("clean-state") {
+                history::collect_garbage()?;
+            }
+
+            let incremental = submatches.get_flag("incremental");
+
+            let concurrency = submatches
+                .get_one::<usize>("concurrency")
+                .copied()
+                .unwrap_or(4);
+
+            let all_attempts = submatches
+                .get_one::<String>("attempts")
+                .is_some_and(|attempts| attempts == "all");
+
+            match batch {
+                None => {
+                    let config = resolve_workflow(workflow_config(submatches, devel)?).await?;
+                    run_query(&config, count, incremental, concurrency, all_attempts).await?;
+                }
+                Some(configs) => {
+                    run_batch(configs, count, incremental, concurrency, all_attempts).await?
+                }
+            }
+        }
+        Some(("history", submatches)) => {
+            if let Some(("export", submatches)) = submatches.subcommand() {
+                let state_dir = submatches
+                    .get_one::<String>("state-dir")
+                    .cloned()
+                    .unwrap_or_else(history::default_record_directory);
+
+                let format = submatches
+                    .get_one::<String>("format")
+                    .map(String::as_str)
+                    .unwrap_or("csv");
+
+                run_history_export(&state_dir, format)?;
+            }
+        }
+        Some(("runs", submatches)) => {
+            let config = resolve_workflow(workflow_config(submatches, devel)?).await?;
+
+            let count = match submatches.get_one::<String>("count") {
+                None => 10,
+                Some(value) => value
+                    .parse::<u32>()
+                    .expect("Unable to parse supplied --count value"),
+            };
+
+            let state_dir = submatches
+                .get_one::<String>("state-dir")
+                .cloned()
+                .unwrap_or_else(history::default_record_directory);
+
+            // only look at the record if there is one; listing shouldn't
+            // leave an empty record directory behind.
+            if Path::new(&state_dir).exists() {
+                history::set_record_directory(&state_dir)?;
+            }
+
+            run_runs(&config, count).await?;
+        }
+        #[cfg(feature = "tui")]
+        Some(("tui", submatches)) => {
+            let mut configs: Vec<Config> = Vec::new();
+
+            for workflow in submatches
+                .get_many::<String>("workflow")
+                .into_iter()
+                .flatten()
+            {
+                let config = Config {
+                    workflow: workflow.clone(),
+                    ..workflow_config(submatches, devel)?
+                };
+                configs.push(resolve_workflow(config).await?);
+            }
+
+            let count = match submatches.get_one::<String>("count") {
+                None => 10,
+                Some(value) => value
+                    .parse::<u32>()
+                    .expect("Unable to parse supplied --count value"),
+            };
+
+            let state_dir = submatches
+                .get_one::<String>("state-dir")
+                .cloned()
+                .unwrap_or_else(history::default_record_directory);
+
+            history::set_record_directory(&state_dir)?;
+
+            tui::run_tui(configs, count).await?;
+        }
+        Some(("open", submatches)) => {
+            let run_id = *submatches
+                .get_one::<u64>("run_id")
+                .unwrap();
+
+            let attempt = submatches
+                .get_one::<u64>("attempt")
+                .copied();
+
+            let template = submatches
+                .get_one::<String>("trace-url")
+                .unwrap();
+
+            let state_dir = submatches
+                .get_one::<String>("state-dir")
+                .cloned()
+                .unwrap_or_else(history::default_record_directory);
+
+            run_open(
+                &state_dir,
+                run_id,
+                attempt,
+                template,
+                submatches.get_flag("browser"),
+            )
+            .await?;
+        }
+        Some(("compare", submatches)) => {
+            let repository = submatches
+                .get_one::<String>("repository")
+                .unwrap();
+
+            let (host, owner, repository) = parse_repository(repository)?;
+
+            let config = Config {
+                host,
+                owner,
+                repository,
+                workflow: String::new(),
+                devel,
+            };
+
+            let run_a = *submatches
+                .get_one::<u64>("run_a")
+                .unwrap();
+            let run_b = *submatches
+                .get_one::<u64>("run_b")
+                .unwrap();
+
+            run_compare(config, run_a, run_b, submatches.get_flag("emit")).await?;
+        }
+        Some(("logs", submatches)) => {
+            let repository = submatches
+                .get_one::<String>("repository")
+                .unwrap();
+
+            let (host, owner, repository) = parse_repository(repository)?;
+
+            let config = Config {
+                host,
+                owner,
+                repository,
+                workflow: String::new(),
+                devel,
+            };
+
+            let job_id = *submatches
+                .get_one::<u64>("job_id")
+                .unwrap();
+
+            run_logs(&config, job_id, submatches.get_flag("errors")).await?;
+        }
+        Some(("resubmit-dead-letter", submatches)) => {
+            let directory = submatches
+                .get_one::<String>("dead-letter-dir")
+                .unwrap();
+            deadletter::set_dead_letter_directory(directory)?;
+
+            if let Some(directory) = submatches.get_one::<String>("state-dir") {
+                history::set_record_directory(directory)?;
+            }
+
+            let names: Vec<&str> = submatches
+                .get_many::<String>("name")
+                .into_iter()
+                .flatten()
+                .map(String::as_str)
+                .collect();
+
+            run_resubmit_dead_letter(&names, submatches.get_flag("all")).await?;
+        }
+        Some(("install-webhook", submatches)) => {
+            let target = submatches
+                .get_one::<String>("target")
+                .unwrap();
+
+            let (owner, repository) = match target.split_once('/') {
+                Some((owner, repository)) => (owner, Some(repository)),
+                None => (target.as_str(), None),
+            };
+
+            let url = submatches
+                .get_one::<String>("url")
+                .unwrap();
+
+            let secret = submatches
+                .get_one::<String>("secret")
+                .cloned()
+                .or_else(|| std::env::var("GITHUB_WEBHOOK_SECRET").ok());
+
+            if let Some(secret) = &secret {
+                redaction::register_secret(secret);
+            }
+
+            run_install_webhook(owner, repository, url, secret.as_deref()).await?;
+        }
+        Some(("simulate", submatches)) => {
+            // fabricated Jobs have no logs to go looking for
+            let mut settings = form_settings(submatches)?;
+            settings.logs = false;
+            settings.cache_stats = false;
+            settings.log_groups = false;
+            settings::set_settings(settings);
+
+            let simulation = simulate::Simulation {
+                runs: submatches
+                    .get_one::<u32>("runs")
+                    .copied()
+                    .unwrap_or(10),
+                jobs: submatches
+                    .get_one::<u32>("jobs")
+                    .copied()
+                    .unwrap_or(5),
+                steps: submatches
+                    .get_one::<u32>("steps")
+                    .copied()
+                    .unwrap_or(8),
+                step_duration: submatches
+                    .get_one::<f64>("step-duration")
+                    .copied()
+                    .unwrap_or(30.0),
+                failure_rate: submatches
+                    .get_one::<f64>("failure-rate")
+                    .copied()
+                    .unwrap_or(0.1),
+                interval: std::time::Duration::from_millis(
+                    submatches
+                        .get_one::<u64>("interval")
+                        .copied()
+                        .unwrap_or(0),
+                ),
+                seed: submatches
+                    .get_one::<u64>("seed")
+                    .copied()
+                    .unwrap_or_else(|| get_program_start().unix_timestamp_nanos() as u64),
+            };
+
+            simulate::run_simulation(&simulation).await?;
+        }
+        Some(("mock-github", submatches)) => {
+            let host = match submatches.get_one::<String>("host") {
+                None => Ipv4Addr::UNSPECIFIED,
+                Some(value) => value
+                    .parse()
+                    .expect("Unable to parse supplied --host value"),
+            };
+
+            let port = submatches
+                .get_one::<u16>("port")
+                .copied()
+                .unwrap_or(34485);
+
+            mock::run_mock_server(host, port).await?;
+        }
+        Some(_) => {
+            println!("No valid subcommand was used")
+        }
+        None => {
+            println!("usage: hero [COMMAND] ...");
+            println!("Try '--help' for more information.");
+        }
+    }
+
+    // Ensure all spans are exported before the program exits
+    provider.shutdown()?;
+    traces::shutdown_repository_providers()?;
+    self_provider.shutdown()?;
+    meter_provider.shutdown()?;
+    if let Some(self_meter_provider) = self_meter_provider {
+        self_meter_provider.shutdown()?;
+    }
+
+    Ok(())
+}
+
+/// The API accepts a workflow's numeric ID in place of its filename, but it
+/// is the filename that goes into the trace ID and the record of what has
+/// been sent (and which the listener knows Runs by), so an ID is swapped for
+/// the filename of the workflow it identifies.
+async fn resolve_workflow(mut config: Config) -> Result<Config> {
+    let numeric = !config
+        .workflow
+        .is_empty()
+        && config
+            .workflow
+            .bytes()
+            .all(|byte| byte.is_ascii_digit());
+
+    if numeric {
+        let client = github::setup_api_client(&config)?;
+        let filename = github::retrieve_workflow_filename(&config, &client).await?;
+        info!("Workflow {} is {}", config.workflow, filename);
+        config.workflow = filename;
+    }
+
+    Ok(config)
+}
+
+/// Every option of every subcommand can also be given in an environment
+/// variable named after it, so --state-dir can be set with HERO_STATE_DIR and
+/// so on. Options given on the command-line take precedence. The global
+/// options belong to the top-level command, and are given theirs there before
+/// being passed down to the subcommands.
+fn with_environment(command: Command) -> Command {
+    command
+        .mut_args(|arg| {
+            let name = format!(
+                "HERO_{}",
+                arg.get_id()
+                    .as_str()
+                    .to_uppercase()
+                    .replace('-', "_")
+            );
+            match arg
+                .get_id()
+                .as_str()
+            {
+                "help" | "version" => arg,
+                _ => arg.env(name),
+            }
+        })
+        .mut_subcommands(with_environment)
+}
+
+/// Form the Config for the repository and workflow given on the command-line.
+fn workflow_config(submatches: &ArgMatches, devel: bool) -> Result<Config> {
+    // Now we get the details of what repository we're going to get the Action
+    // history from.
+
+    let repository = submatches
+        .get_one::<String>("repository")
+        .unwrap();
+
+    let (host, owner, repository) = parse_repository(repository)?;
+
+    debug!(owner);
+    debug!(repository);
+
+    let workflow = submatches
+        .get_one::<String>("workflow")
+        .unwrap()
+        .to_string();
+
+    debug!(workflow);
+
+    Ok(Config {
+        host,
+        owner,
+        repository,
+        workflow,
+        devel,
+    })
+}
+
+/// Read the list of repositories and workflows to query, given as
+/// "owner/repo workflow" on each line of the file (or of stdin if the path
+/// is "-").
+fn read_batch(path: &str, devel: bool) -> Result<Vec<Config>> {
+    let contents = if path == "-" {
+        std::io::read_to_string(std::io::stdin())?
+    } else {
+        std::fs::read_to_string(path)
+            .map_err(|error| anyhow!("Unable to read {}: {}", path, error))?
+    };
+
+    let mut configs = Vec::new();
+
+    for (number, line) in contents
+        .lines()
+        .enumerate()
+    {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut words = line.split_whitespace();
+        let (Some(repository), Some(workflow), None) = (words.next(), words.next(), words.next())
+        else {
+            return Err(anyhow!(
+                "Line {} of {} must be in the form \"owner/repo workflow\"",
+                number + 1,
+                path
+            ));
+        };
+
+        let (host, owner, repository) = parse_repository(repository)?;
+
+        configs.push(Config {
+            host,
+            owner,
+            repository,
+            workflow: workflow.to_string(),
+            devel,
+        });
+    }
+
+    Ok(configs)
+}
+
+/// Query each of the workflows in turn. One failing doesn't stop the others
+/// being queried, but is reported at the end.
+async fn run_batch(
+    configs: Vec<Config>,
+    count: u32,
+    incremental: bool,
+    concurrency: usize,
+    all_attempts: bool,
+) -> Result<()> {
+    let mut failed = 0;
+
+    for config in configs {
+        let description = format!(
+            "{} in {}/{}",
+            config.workflow, config.owner, config.repository
+        );
+
+        let outcome = match resolve_workflow(config).await {
+            Result::Ok(config) => {
+                run_query(&config, count, incremental, concurrency, all_attempts).await
+            }
+            Err(error) => Err(error),
+        };
+
+        if let Err(error) = outcome {
+            warn!("Unable to query {}: {}", description, error);
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        return Err(anyhow!("{} workflows could not be queried", failed));
+    }
+
+    Ok(())
+}
+
+/// Repositories are normally given as "owner/repo", but it's convenient to
+/// be able to paste in the repository's URL, whether the web address
+/// (https://github.com/owner/repo) or the one used to clone it over SSH
+/// (git@github.com:owner/repo.git). Returns the host along with the owner
+/// and repository names; if the host isn't given it is github.com, or the
+/// --enterprise-host if there is one.
+fn parse_repository(value: &str) -> Result<(String, String, String)> {
+    let value = value.trim();
+
+    let (host, path) = if let Some((_, rest)) = value.split_once("://") {
+        // https://host/owner/repo or ssh://git@host/owner/repo
+        let (authority, path) = rest
+            .split_once('/')
+            .unwrap_or((rest, ""));
+        let host = authority
+            .rsplit('@')
+            .next()
+            .unwrap_or(authority);
+        (Some(host), path)
+    } else if let Some((authority, path)) = value.split_once(':')
+        && let Some((_, host)) = authority.split_once('@')
+    {
+        // git@host:owner/repo.git
+        (Some(host), path)
+    } else {
+        (None, value)
+    };
+
+    let path = path.trim_end_matches('/');
+    let path = path
+        .strip_suffix(".git")
+        .unwrap_or(path);
+
+    let mut parts = path.split('/');
+    let (Some(owner), Some(repository)) = (parts.next(), parts.next()) else {
+        return Err(anyhow!(
+            "Repository must be specified in the form \"owner/repo\" or as its URL"
+        ));
+    };
+    if owner.is_empty() || repository.is_empty() {
+        return Err(anyhow!(
+            "Repository must be specified in the form \"owner/repo\" or as its URL"
+        ));
+    }
+
+    let enterprise = github::get_enterprise_host();
+
+    let host = match host {
+        None => enterprise
+            .unwrap_or(github::GITHUB_HOST)
+            .to_string(),
+        Some(host) if host.eq_ignore_ascii_case(github::GITHUB_HOST) => {
+            github::GITHUB_HOST.to_string()
+        }
+        Some(host)
+            if enterprise.is_some_and(|enterprise| enterprise.eq_ignore_ascii_case(host)) =>
+        {
+            host.to_lowercase()
+        }
+        Some(host) => {
+            return Err(anyhow!(
+                "Repository is on {}; pass --enterprise-host {} to use a GitHub Enterprise Server",
+                host,
+                host
+            ));
+        }
+    };
+
+    Ok((host, owner.to_string(), repository.to_string()))
+}
+
+/// Options controlling how Runs are processed, common to the subcommands
+/// that send telemetry.
+fn processing_args() -> Vec<Arg> {
+    vec![
+        Arg::new("config")
+            .long("config")
+            .value_name("FILE")
+            .long_help("Read further options from this JSON config file. See the README for the sections it can contain."),
+        Arg::new("profile")
+            .long("profile")
+            .value_name("NAME")
+            .requires("config")
+            .long_help("Use the named profile from the --config file, which can set where telemetry is sent, the service name it is sent as, and which GitHub token is used. Options given on the command-line take precedence over the profile."),
+        Arg::new("fresh-trace-id")
+            .long("fresh-trace-id")
+            .action(ArgAction::SetTrue)
+            .long_help("Give each Run a new, random TraceId rather than the one derived from the Run, so that Runs sent previously are sent again as separate traces. Runs already recorded in the --state-dir are still skipped; use a different directory to send them again."),
+        Arg::new("record-fixtures")
+            .long("record-fixtures")
+            .value_name("DIR")
+            .conflicts_with("fixtures")
+            .long_help("Save every response received from the GitHub API to this directory, so that it can be replayed later with --fixtures."),
+        Arg::new("fixtures")
+            .long("fixtures")
+            .value_name("DIR")
+            .long_help("Serve responses from the GitHub API out of this directory, as saved by --record-fixtures, rather than contacting GitHub at all. Requests for which nothing was saved are answered with 404 Not Found. No GitHub token is needed."),
+        Arg::new("xray-trace-id")
+            .long("xray-trace-id")
+            .action(ArgAction::SetTrue)
+            .long_help("Form TraceIds whose first four bytes are the time the Run started, in seconds since the epoch, as AWS X-Ray requires; the rest of the TraceId is derived from the Run as usual. Note that this changes the TraceId of Runs sent previously without this option."),
+        Arg::new("no-logs")
+            .long("no-logs")
+            .action(ArgAction::SetTrue)
+            .long_help("Don't retrieve the logs of failing Jobs. Normally the first error message found in the log is attached to the span of the failing Step, but for repositories whose output is sensitive this ensures no log content is sent."),
+        Arg::new("cache-stats")
+            .long("cache-stats")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("no-logs")
+            .long_help("Retrieve the log of every Job to find whether the caches restored by actions/cache (or setup-* actions with caching turned on) were hit, adding cache.hit, cache.key, and cache.restored_bytes attributes to the span of the Step that restored each. This costs an extra request of the GitHub API for each Job."),
+        Arg::new("log-groups")
+            .long("log-groups")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("no-logs")
+            .long_help("Retrieve the log of every Job and give each Step a child span for every group (marked with ::group:: and ::endgroup::) its output was divided into, so that long Steps running many things have some structure. Groups lasting less than a second are left out. This costs an extra request of the GitHub API for each Job, shared with --cache-stats."),
+        Arg::new("test-report")
+            .long("test-report")
+            .value_name("ARTIFACT")
+            .long_help("The name of the Artifact holding JUnit XML test reports, such as \"test-results\" or \"junit-*\". Matching Artifacts of each Run are downloaded and a span sent for every test case, under the Job that uploaded it, with failing tests marked as errors along with their message. Patterns are as for --include-step."),
+        Arg::new("redact")
+            .long("redact")
+            .action(ArgAction::Append)
+            .long_help("A regular expression matching text to be removed from anything taken from logs before it is attached to spans. Tokens, AWS keys, and Authorization headers are always removed. This option can be given more than once."),
+        Arg::new("skipped-steps")
+            .long("skipped-steps")
+            .value_parser(["omit", "mark", "collapse"])
+            .long_help("How to treat Steps that GitHub skipped. By default they are omitted. With \"mark\" each is sent as a zero duration span with the attribute skipped=true, and with \"collapse\" consecutive skipped Steps are combined into a single such span."),
+        Arg::new("only-failures")
+            .long("only-failures")
+            .action(ArgAction::SetTrue)
+            .long_help("Only send traces for Runs which failed, timed out, were cancelled, or failed to start. Other Runs are still recorded as having been processed, but as suppressed rather than with a TraceId."),
+        Arg::new("gap-spans")
+            .long("gap-spans")
+            .action(ArgAction::SetTrue)
+            .long_help("Send spans for the time within a Job not taken by any Step: runner setup before the first Step, overhead between Steps, and teardown after the last."),
+        Arg::new("service-per-repository")
+            .long("service-per-repository")
+            .action(ArgAction::SetTrue)
+            .long_help("Send the traces of each repository as a service named \"owner/repo\" rather than all under the \"github-actions\" service."),
+        Arg::new("lenient")
+            .long("lenient")
+            .action(ArgAction::SetTrue)
+            .long_help("Tolerate changes to the objects GitHub sends. Fields which are missing but not essential are given a placeholder value rather than failing the Run, Jobs and Steps which still can't be understood are skipped, and unrecognized or missing fields are logged the first time they are seen."),
+        Arg::new("include-step")
+            .long("include-step")
+            .action(ArgAction::Append)
+            .long_help("Only send spans for Steps whose name matches this pattern. Patterns are globs such as \"Run tests*\" unless prefixed with \"re:\", in which case they are regular expressions. This option can be given more than once."),
+        Arg::new("exclude-step")
+            .long("exclude-step")
+            .action(ArgAction::Append)
+            .long_help("Don't send spans for Steps whose name matches this pattern, for example \"Post *\". Patterns are as for --include-step. This option can be given more than once."),
+        Arg::new("span-kind")
+            .long("span-kind")
+            .action(ArgAction::Append)
+            .long_help("Set the SpanKind used for a layer of spans, in the form \"layer=kind\" where layer is one of run, job, or step and kind is one of internal, server, client, producer, or consumer. For example \"run=server\". This option can be given more than once. The default is internal for everything."),
+        Arg::new("trace-url")
+            .long("trace-url")
+            .long_help("Template for the URL of a trace in your telemetry backend, printed after each Run is submitted. The placeholder {trace_id} is replaced with the TraceId, and {trace_start} and {trace_end} with the Run's start and finish as Unix timestamps. If there is no {trace_id} placeholder the TraceId is appended."),
+    ]
+}
+
+/// Options for where and how telemetry is sent. These all have equivalents
+/// in the standard OTEL_EXPORTER_OTLP_* environment variables, but when given
+/// on the command line they take precedence.
+fn exporter_args() -> Vec<Arg> {
+    vec![
+        Arg::new("preset")
+            .long("preset")
+            .value_parser(presets::PRESETS)
+            .long_help("Send telemetry to one of the vendors whose OTLP ingest we know the details of, configuring the endpoint, protocol and the header carrying the API key. For honeycomb the key is read from HONEYCOMB_API_KEY; for grafana-cloud from GRAFANA_CLOUD_API_KEY, along with GRAFANA_CLOUD_INSTANCE_ID and GRAFANA_CLOUD_ZONE (such as \"prod-us-east-0\"); and for newrelic from NEW_RELIC_LICENSE_KEY, with NEW_RELIC_REGION set to \"eu\" for accounts in the EU. Each can instead be supplied as a credential named in lowercase with hyphens, such as honeycomb-api-key. The other --otlp-* options take precedence over what the preset configures."),
+        Arg::new("otlp-endpoint")
+            .long("otlp-endpoint")
+            .long_help("The URL of the OpenTelemetry collector to send to. Overrides OTEL_EXPORTER_OTLP_TRACES_ENDPOINT and OTEL_EXPORTER_OTLP_ENDPOINT. The default is http://localhost:4317 for gRPC and http://localhost:4318/v1/traces for HTTP. A collector listening on a Unix domain socket can be given as unix:///path/to/socket."),
+        Arg::new("otlp-protocol")
+            .long("otlp-protocol")
+            .value_parser(["grpc", "http/protobuf", "http/json"])
+            .long_help("The OTLP transport to use. Overrides OTEL_EXPORTER_OTLP_TRACES_PROTOCOL and OTEL_EXPORTER_OTLP_PROTOCOL. The default is grpc."),
+        Arg::new("otlp-header")
+            .long("otlp-header")
+            .action(ArgAction::Append)
+            .long_help("A header to send with each export, in the form \"name=value\". This option can be given more than once, and these headers are sent in addition to any in OTEL_EXPORTER_OTLP_TRACES_HEADERS or OTEL_EXPORTER_OTLP_HEADERS (taking precedence over a header of the same name)."),
+        Arg::new("otlp-timeout")
+            .long("otlp-timeout")
+            .value_parser(clap::value_parser!(u64))
+            .long_help("Timeout for each export, in milliseconds. Overrides OTEL_EXPORTER_OTLP_TRACES_TIMEOUT and OTEL_EXPORTER_OTLP_TIMEOUT. The default is 10000."),
+        Arg::new("otlp-connect-timeout")
+            .long("otlp-connect-timeout")
+            .value_parser(clap::value_parser!(u64))
+            .long_help("Timeout for establishing the connection to the collector, in milliseconds. Only applies when the protocol is grpc. By default there is no limit other than --otlp-timeout."),
+        Arg::new("otlp-keepalive")
+            .long("otlp-keepalive")
+            .value_parser(clap::value_parser!(u64))
+            .long_help("Interval between keepalive pings sent on the connection to the collector, in milliseconds, including while no exports are in progress. Only applies when the protocol is grpc. Setting this stops the first export after a long quiet period from failing on a connection that has been silently dropped. By default no keepalives are sent."),
+        Arg::new("otlp-keepalive-timeout")
+            .long("otlp-keepalive-timeout")
+            .value_parser(clap::value_parser!(u64))
+            .requires("otlp-keepalive")
+            .long_help("How long to wait for the reply to a keepalive ping before closing the connection, in milliseconds. The default is 20000."),
+        Arg::new("batch-queue-size")
+            .long("batch-queue-size")
+            .value_parser(clap::value_parser!(usize))
+            .long_help("The number of spans which can be held waiting to be exported; beyond this spans are dropped. Overrides OTEL_BSP_MAX_QUEUE_SIZE. The default is 2048, which may need raising when processing many Runs at once."),
+        Arg::new("batch-size")
+            .long("batch-size")
+            .value_parser(clap::value_parser!(usize))
+            .long_help("The maximum number of spans sent in each export. Overrides OTEL_BSP_MAX_EXPORT_BATCH_SIZE. The default is 512."),
+        Arg::new("batch-delay")
+            .long("batch-delay")
+            .value_parser(clap::value_parser!(u64))
+            .long_help("The interval between exports, in milliseconds. Overrides OTEL_BSP_SCHEDULE_DELAY. The default is 5000."),
+    ]
+}
+
+fn select_profile(submatches: &ArgMatches) -> Result<ConfigProfile> {
+    let value = |name: &str| {
+        submatches
+            .try_get_one::<String>(name)
+            .ok()
+            .flatten()
+    };
+
+    settings::load_profile(value("config"), value("profile"))
+}
+
+fn exporter_options(submatches: &ArgMatches, profile: &ConfigProfile) -> Result<ExporterOptions> {
+    let value = |name: &str| {
+        submatches
+            .try_get_one::<String>(name)
+            .ok()
+            .flatten()
+            .cloned()
+    };
+
+    let preset = value("preset")
+        .or_else(|| {
+            profile
+                .preset
+                .clone()
+        })
+        .map(|name| presets::load_preset(&name))
+        .transpose()?;
+
+    let (base_endpoint, preset_protocol, preset_headers) = match preset {
+        Some(preset) => (Some(preset.endpoint), Some(preset.protocol), preset.headers),
+        None => (None, None, Vec::new()),
+    };
+
+    let configured = preset_headers
+        .into_iter()
+        .chain(
+            profile
+                .otlp
+                .headers
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone())),
+        )
+        .map(Result::Ok);
+
+    let headers = submatches
+        .try_get_many::<String>("otlp-header")
+        .ok()
+        .flatten()
+        .into_iter()
+        .flatten()
+        .map(|header| match header.split_once('=') {
+            Some((name, value)) => Result::Ok((
+                name.trim()
+                    .to_string(),
+                value
+                    .trim()
+                    .to_string(),
+            )),
+            None => Err(anyhow!(
+                "Header \"{}\" must be specified in the form \"name=value\"",
+                header
+            )),
+        });
+
+    // headers from the command-line come after (and so replace) those from
+    // the profile, which in turn replace those from the preset.
+    let headers = configured
+        .chain(headers)
+        .collect::<Result<Vec<_>>>()?;
+
+    let millis = |name: &str, configured: Option<u64>| {
+        submatches
+            .try_get_one::<u64>(name)
+            .ok()
+            .flatten()
+            .copied()
+            .or(configured)
+            .map(std::time::Duration::from_millis)
+    };
+
+    let timeout = millis(
+        "otlp-timeout",
+        profile
+            .otlp
+            .timeout,
+    );
+    let connect_timeout = millis(
+        "otlp-connect-timeout",
+        profile
+            .otlp
+            .connect_timeout,
+    );
+    let keepalive = millis(
+        "otlp-keepalive",
+        profile
+            .otlp
+            .keepalive,
+    );
+    let keepalive_timeout = millis(
+        "otlp-keepalive-timeout",
+        profile
+            .otlp
+            .keepalive_timeout,
+    );
+
+    let size = |name: &str| {
+        submatches
+            .try_get_one::<usize>(name)
+            .ok()
+            .flatten()
+            .copied()
+    };
+
+    let batch_delay = submatches
+        .try_get_one::<u64>("batch-delay")
+        .ok()
+        .flatten()
+        .map(|millis| std::time::Duration::from_millis(*millis));
+
+    // the exporter reads further headers from the environment itself; their
+    // values are as likely to be credentials as any we were given.
+    let environment = [
+        "OTEL_EXPORTER_OTLP_HEADERS",
+        "OTEL_EXPORTER_OTLP_TRACES_HEADERS",
+    ]
+    .into_iter()
+    .filter_map(|name| std::env::var(name).ok())
+    .flat_map(|value| {
+        value
+            .split(',')
+            .filter_map(|pair| {
+                pair.split_once('=')
+                    .map(|(_, value)| value.to_string())
+            })
+            .collect::<Vec<_>>()
+    });
+
+    for value in headers
+        .iter()
+        .map(|(_, value)| value.clone())
+        .chain(environment)
+    {
+        redaction::register_secret(&value);
+    }
+
+    Ok(ExporterOptions {
+        endpoint: value("otlp-endpoint").or_else(|| {
+            profile
+                .otlp
+                .endpoint
+                .clone()
+        }),
+        base_endpoint,
+        protocol: value("otlp-protocol")
+            .or_else(|| {
+                profile
+                    .otlp
+                    .protocol
+                    .clone()
+            })
+            .or(preset_protocol),
+        headers,
+        timeout,
+        connect_timeout,
+        keepalive,
+        keepalive_timeout,
+        queue_size: size("batch-queue-size"),
+        batch_size: size("batch-size"),
+        batch_delay,
+    })
+}
+
+fn form_settings(submatches: &ArgMatches) -> Result<Settings> {
+    let flag = |name: &str| {
+        submatches
+            .try_get_one::<bool>(name)
+            .ok()
+            .flatten()
+            .copied()
+            .unwrap_or(false)
+    };
+
+    let redactions = submatches
+        .try_get_many::<String>("redact")
+        .ok()
+        .flatten()
+        .into_iter()
+        .flatten()
+        .map(|pattern| {
+            Regex::new(pattern)
+                .map_err(|error| anyhow!("Invalid --redact pattern \"{}\": {}", pattern, error))
+        })
+        .collect::<Result<Vec<Regex>>>()?;
+
+    let skipped = match submatches
+        .try_get_one::<String>("skipped-steps")
+        .ok()
+        .flatten()
+        .map(String::as_str)
+    {
+        Some("mark") => SkippedSteps::Mark,
+        Some("collapse") => SkippedSteps::Collapse,
+        _ => SkippedSteps::Omit,
+    };
+
+    let file = match submatches
+        .try_get_one::<String>("config")
+        .ok()
+        .flatten()
+    {
+        Some(path) => settings::load_config_file(Path::new(path))?,
+        None => settings::ConfigFile::default(),
+    };
+
+    let patterns = |name: &str, configured: &[String]| {
+        configured
+            .iter()
+            .chain(
+                submatches
+                    .try_get_many::<String>(name)
+                    .ok()
+                    .flatten()
+                    .into_iter()
+                    .flatten(),
+            )
+            .map(|pattern| {
+                settings::parse_pattern(pattern).map_err(|error| {
+                    anyhow!("Invalid --{} pattern \"{}\": {}", name, pattern, error)
+                })
+            })
+            .collect::<Result<Vec<Regex>>>()
+    };
+
+    let steps = StepFilter {
+        include: patterns(
+            "include-step",
+            &file
+                .steps
+                .include,
+        )?,
+        exclude: patterns(
+            "exclude-step",
+            &file
+                .steps
+                .exclude,
+        )?,
+    };
+
+    let mut span_kinds = SpanKinds::default();
+
+    for value in submatches
+        .try_get_many::<String>("span-kind")
+        .ok()
+        .flatten()
+        .into_iter()
+        .flatten()
+    {
+        let (layer, kind) = value
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Span kind \"{}\" must be in the form \"layer=kind\"", value))?;
+
+        let kind = settings::parse_span_kind(kind)
+            .ok_or_else(|| anyhow!("Unrecognized span kind \"{}\"", kind))?;
+
+        match layer {
+            "run" => span_kinds.run = kind,
+            "job" => span_kinds.job = kind,
+            "step" => span_kinds.step = kind,
+            _ => return Err(anyhow!("Unrecognized span layer \"{}\"", layer)),
+        }
+    }
+
+    Ok(Settings {
+        logs: !flag("no-logs"),
+        cache_stats: flag("cache-stats"),
+        log_groups: flag("log-groups"),
+        test_reports: submatches
+            .try_get_one::<String>("test-report")
+            .ok()
+            .flatten()
+            .map(|pattern| settings::parse_pattern(pattern))
+            .transpose()
+            .map_err(|error| anyhow!("Invalid --test-report pattern: {}", error))?,
+        redactions,
+        skipped,
+        only_failures: flag("only-failures"),
+        gaps: flag("gap-spans"),
+        lenient: flag("lenient"),
+        steps,
+        attributes: AttributeMappings::from_config(&file.attributes),
+        service_per_repository: flag("service-per-repository"),
+        span_kinds,
+        trace_url: submatches
+            .try_get_one::<String>("trace-url")
+            .ok()
+            .flatten()
+            .cloned(),
+        fresh_trace_ids: flag("fresh-trace-id"),
+        xray_trace_ids: flag("xray-trace-id"),
+    })
+}
+
+/// Reload the config file, along with the GitHub tokens, each time the
+/// listener receives SIGHUP. The listening socket stays open throughout, and
+/// Runs already being processed carry on with whatever they started with. If
+/// anything is wrong with the new configuration the existing one is kept.
+#[cfg(unix)]
+async fn run_reload(submatches: ArgMatches) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Result::Ok(hangup) => hangup,
+        Err(error) => {
+            warn!("Unable to watch for SIGHUP: {}", error);
+            return;
+        }
+    };
+
+    while hangup
+        .recv()
+        .await
+        .is_some()
+    {
+        info!("Reloading configuration");
+
+        match reload_configuration(&submatches).await {
+            Result::Ok(()) => info!("Configuration reloaded"),
+            Err(error) => warn!(
+                "Unable to reload configuration, keeping existing: {}",
+                error
+            ),
+        }
+    }
+}
+
+// There is no SIGHUP on Windows, so there the listener has to be restarted
+// to pick up a change.
+#[cfg(not(unix))]
+async fn run_reload(_submatches: ArgMatches) {}
+
+#[cfg(unix)]
+async fn reload_configuration(submatches: &ArgMatches) -> Result<()> {
+    let settings = form_settings(submatches)?;
+
+    let tokens = read_api_tokens(&select_profile(submatches)?)?;
+    tokens
+        .validate()
+        .await?;
+
+    set_api_tokens(tokens);
+    settings::set_settings(settings);
+
+    Ok(())
+}
+
+async fn run_listen(host: Ipv4Addr, port: u16) -> Result<()> {
+    webhook::run_webserver(host, port).await
+}
+
+async fn run_runs(config: &Config, count: u32) -> Result<()> {
+    let client = github::setup_api_client(config)?;
+
+    let runs = github::retrieve_workflow_runs(config, &client, count, None).await?;
+
+    let mut rows = vec![[
+        "RUN".to_string(),
+        "ATTEMPT".to_string(),
+        "BRANCH".to_string(),
+        "ACTOR".to_string(),
+        "STATUS".to_string(),
+        "CONCLUSION".to_string(),
+        "DURATION".to_string(),
+        "SENT".to_string(),
+    ]];
+
+    for run in &runs {
+        let duration = if run.status == "completed" {
+            format_duration(run.updated_at - run.created_at)
+        } else {
+            "-".to_string()
+        };
+
+        let sent = if history::is_enabled() {
+            let path = history::form_record_filename(config, run);
+            history::read_record_state(&path)
+        } else {
+            "no"
+        };
+
+        rows.push([
+            run.run_number
+                .to_string(),
+            run.run_attempt
+                .to_string(),
+            run.head_branch
+                .clone(),
+            run.actor
+                .login
+                .clone(),
+            run.status
+                .clone(),
+            run.conclusion
+                .clone()
+                .unwrap_or_else(|| "-".to_string()),
+            duration,
+            sent.to_string(),
+        ]);
+    }
+
+    print_table(&rows);
+
+    Ok(())
+}
+
+fn format_duration(duration: time::Duration) -> String {
+    format!(
+        "{}m{:02}s",
+        duration.whole_minutes(),
+        duration.whole_seconds() % 60
+    )
+}
+
+/// Print rows of cells in columns as wide as the widest cell in each.
+fn print_table<const N: usize>(rows: &[[String; N]]) {
+    let mut widths = [0; N];
+    for row in rows {
+        for (width, cell) in widths
+            .iter_mut()
+            .zip(row)
+        {
+            *width = (*width).max(
+                cell.chars()
+                    .count(),
+            );
+        }
+    }
+
+    for row in rows {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        println!(
+            "{}",
+            line.join("  ")
+                .trim_end()
+        );
+    }
+}
+
+/// Without any names, list what is in the dead-letter directory. Otherwise
+/// send the named Runs (or all of them) again.
+async fn run_resubmit_dead_letter(names: &[&str], all: bool) -> Result<()> {
+    let letters = deadletter::read_dead_letters()?;
+
+    if names.is_empty() && !all {
+        let mut rows = vec![[
+            "NAME".to_string(),
+            "REPOSITORY".to_string(),
+            "WORKFLOW".to_string(),
+            "RUN".to_string(),
+            "FAILED".to_string(),
+            "ERROR".to_string(),
+        ]];
+
+        for letter in &letters {
+            let (repository, workflow, run) = match &letter.item {
+                Some(item) => (
+                    format!("{}/{}", item.owner, item.repository),
+                    item.workflow
+                        .clone(),
+                    item.run
+                        .get("run_number")
+                        .map(|number| format!("#{}", number))
+                        .unwrap_or_else(|| "-".to_string()),
+                ),
+                None => ("-".to_string(), "-".to_string(), "-".to_string()),
+            };
+
+            rows.push([
+                letter
+                    .name
+                    .clone(),
+                repository,
+                workflow,
+                run,
+                letter
+                    .failed_at
+                    .clone(),
+                letter
+                    .error
+                    .clone(),
+            ]);
+        }
+
+        print_table(&rows);
+        return Ok(());
+    }
+
+    for name in names {
+        if !letters
+            .iter()
+            .any(|letter| letter.name == *name)
+        {
+            return Err(anyhow!(
+                "No Run named \"{}\" in the dead-letter directory",
+                name
+            ));
+        }
+    }
+
+    let mut failed = 0;
+
+    for letter in letters
+        .iter()
+        .filter(|letter| {
+            all || names.contains(
+                &letter
+                    .name
+                    .as_str(),
+            )
+        })
+    {
+        match deadletter::resubmit(letter).await {
+            Result::Ok(()) => info!("Sent {}", letter.name),
+            Err(error) => {
+                warn!("Unable to send {}: {:#}", letter.name, error);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(anyhow!("{} Runs could not be sent", failed));
+    }
+
+    Ok(())
+}
+
+async fn run_compare(mut config: Config, run_a: u64, run_b: u64, emit: bool) -> Result<()> {
+    let client = github::setup_api_client(&config)?;
+
+    let mut runs = Vec::new();
+    for run_id in [run_a, run_b] {
+        let run = github::retrieve_run(
+            &client,
+            &config.host,
+            &config.owner,
+            &config.repository,
+            run_id,
+            None,
+        )
+        .await?;
+
+        if run.status != "completed" {
+            return Err(anyhow!("Run {} has not completed", run_id));
+        }
+        runs.push(run);
+    }
+
+    runs.sort_by_key(|run| run.created_at);
+    let [older, newer] = &runs[..] else {
+        unreachable!();
+    };
+
+    let workflow = |run: &WorkflowRun| {
+        run.path
+            .split('@')
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    };
+    if workflow(older) != workflow(newer) {
+        return Err(anyhow!(
+            "Runs {} and {} are of different workflows",
+            older.run_id,
+            newer.run_id
+        ));
+    }
+
+    // as for the workflow_run event, so that --emit finds the trace the
+    // newer Run was sent as.
+    config.workflow = workflow(newer)
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+
+    let mut jobs = Vec::new();
+    for run in [older, newer] {
+        let mut all = Vec::new();
+        let mut pages = github::JobPages::new(&config, run);
+        while let Some(page) = pages
+            .next(&client)
+            .await?
+        {
+            all.extend(page);
+        }
+        jobs.push(all);
+    }
+
+    let differences = compare::compare_jobs(&jobs[0], &jobs[1]);
+
+    let cell = |duration: Option<time::Duration>| {
+        duration
+            .map(format_duration)
+            .unwrap_or_else(|| "-".to_string())
+    };
+    let change = |change: Option<time::Duration>| match change {
+        Some(change) if change.is_negative() => format!("-{}", format_duration(change.abs())),
+        Some(change) => format!("+{}", format_duration(change)),
+        None => "-".to_string(),
+    };
+
+    let mut rows = vec![[
+        "JOB / STEP".to_string(),
+        format!("#{}", older.run_number),
+        format!("#{}", newer.run_number),
+        "CHANGE".to_string(),
+    ]];
+
+    let before = older.updated_at - older.created_at;
+    let after = newer.updated_at - newer.created_at;
+    rows.push([
+        "(Run)".to_string(),
+        cell(Some(before)),
+        cell(Some(after)),
+        change(Some(after - before)),
+    ]);
+
+    for difference in &differences {
+        let label = match &difference.step {
+            Some(step) => format!("  {}", step),
+            None => difference
+                .job
+                .clone(),
+        };
+        rows.push([
+            label,
+            cell(difference.older),
+            cell(difference.newer),
+            change(difference.change()),
+        ]);
+    }
+
+    print_table(&rows);
+
+    if emit {
+        traces::display_comparison(&config, newer, older, &differences);
+    }
+
+    Ok(())
+}
+
+async fn run_logs(config: &Config, job_id: u64, errors: bool) -> Result<()> {
+    let client = github::setup_api_client(config)?;
+
+    github::scan_job_log(config, &client, job_id, |line| {
+        if !errors {
+            println!("{}", line);
+        } else if let Some(message) = github::log_message(line)
+            && github::is_error_message(message)
+        {
+            println!("{}", redaction::redact(message));
+        }
+        std::ops::ControlFlow::Continue(())
+    })
+    .await?;
+
+    Ok(())
+}
+
+fn run_history_export(state_dir: &str, format: &str) -> Result<()> {
+    let mut records = history::read_records(state_dir)?;
+
+    records.sort_by(|a, b| {
+        a.submitted_at
+            .cmp(&b.submitted_at)
+    });
+
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&records)?);
+        return Ok(());
+    }
+
+    println!(
+        "host,owner,repository,workflow,run_id,run_attempt,conclusion,trace_id,suppressed,submitted_at,delivery"
+    );
+
+    for record in &records {
+        let submitted_at = record
+            .submitted_at
+            .format(&time::format_description::well_known::Rfc3339)?;
+
+        let fields = [
+            record
+                .host
+                .clone(),
+            record
+                .owner
+                .clone(),
+            record
+                .repository
+                .clone(),
+            record
+                .workflow
+                .clone(),
+            record
+                .run_id
+                .to_string(),
+            record
+                .run_attempt
+                .to_string(),
+            record
+                .conclusion
+                .clone()
+                .unwrap_or_default(),
+            record
+                .trace_id
+                .clone()
+                .unwrap_or_default(),
+            record
+                .suppressed
+                .to_string(),
+            submitted_at,
+            record
+                .delivery
+                .clone()
+                .unwrap_or_default(),
+        ];
+
+        let line: Vec<String> = fields
+            .iter()
+            .map(|field| csv_field(field))
+            .collect();
+
+        println!("{}", line.join(","));
+    }
+
+    Ok(())
+}
+
+/// Find the TraceId a Run was sent as from the record, and form the URL of
+/// its trace from the template.
+async fn run_open(
+    state_dir: &str,
+    run_id: u64,
+    attempt: Option<u64>,
+    template: &str,
+    browser: bool,
+) -> Result<()> {
+    if !Path::new(state_dir).exists() {
+        return Err(anyhow!("No record of sent Runs in {}", state_dir));
+    }
+
+    let records: Vec<history::Record> = history::read_records(state_dir)?
+        .into_iter()
+        .filter(|record| record.run_id == run_id)
+        .filter(|record| attempt.is_none_or(|attempt| record.run_attempt == attempt))
+        .collect();
+
+    // Run IDs are unique on github.com, but a GitHub Enterprise Server
+    // numbers its Runs independently.
+    let mut places: Vec<String> = records
+        .iter()
+        .map(|record| format!("{} {}/{}", record.host, record.owner, record.repository))
+        .collect();
+    places.sort();
+    places.dedup();
+    if places.len() > 1 {
+        return Err(anyhow!(
+            "Run {} is recorded in more than one repository: {}",
+            run_id,
+            places.join(", ")
+        ));
+    }
+
+    let Some(record) = records
+        .into_iter()
+        .max_by_key(|record| record.run_attempt)
+    else {
+        return Err(match attempt {
+            Some(attempt) => anyhow!("No record of attempt {} of Run {}", attempt, run_id),
+            None => anyhow!("No record of Run {}", run_id),
+        });
+    };
+
+    let Some(trace_id) = &record.trace_id else {
+        return Err(if record.suppressed {
+            anyhow!("Run {} was suppressed rather than sent", run_id)
+        } else {
+            anyhow!("No TraceId was recorded for Run {}", run_id)
+        });
+    };
+
+    let url = if traces::trace_url_needs_times(template) {
+        let config = Config {
+            host: record
+                .host
+                .clone(),
+            owner: record
+                .owner
+                .clone(),
+            repository: record
+                .repository
+                .clone(),
+            workflow: record
+                .workflow
+                .clone(),
+            devel: false,
+        };
+        let client = github::setup_api_client(&config)?;
+
+        let run = github::retrieve_run(
+            &client,
+            &config.host,
+            &config.owner,
+            &config.repository,
+            run_id,
+            Some(record.run_attempt),
+        )
+        .await
+        .map_err(|problem| anyhow!("Unable to retrieve Run {}: {}", run_id, problem))?;
+
+        traces::form_trace_url(template, trace_id, &run)
+    } else {
+        traces::insert_trace_id(template, trace_id)
+    };
+
+    println!("{}", url);
+
+    if browser {
+        open_in_browser(&url)?;
+    }
+
+    Ok(())
+}
+
+// Quote a field only if it needs it, doubling any quotes within.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+async fn run_install_webhook(
+    owner: &str,
+    repository: Option<&str>,
+    target: &str,
+    secret: Option<&str>,
+) -> Result<()> {
+    let host = github::get_enterprise_host().unwrap_or(github::GITHUB_HOST);
+
+    let token = get_api_token(host, owner)
+        .ok_or_else(|| anyhow!("No GitHub token available for owner {} on {}", owner, host))?;
+    let client = github::build_api_client(host, token)?;
+
+    let url = github::hooks_url(host, owner, repository);
+
+    // if there's already a webhook pointing at this listener we update it
+    // rather than adding a second one, so this can safely be run again.
+    let existing = github::retrieve_hooks(&client, &url)
+        .await?
+        .into_iter()
+        .find(|hook| {
+            hook.config
+                .url
+                .as_deref()
+                == Some(target)
+        })
+        .map(|hook| hook.id);
+
+    let id = github::install_hook(&client, &url, existing, target, secret).await?;
+
+    match existing {
+        Some(_) => println!("Updated webhook {} delivering to {}", id, target),
+        None => println!("Created webhook {} delivering to {}", id, target),
+    }
+
+    if secret.is_none() {
+        warn!("No secret given; deliveries will not be signed");
+    }
+
+    Ok(())
+}
+
+async fn run_query(
+    config: &Config,
+    count: u32,
+    incremental: bool,
+    concurrency: usize,
+    all_attempts: bool,
+) -> Result<()> {
+    let client = github::setup_api_client(config)?;
+
+    let since = if incremental {
+        history::read_cursor(config).await?
+    } else {
+        None
+    };
+
+    let runs: Vec<WorkflowRun> =
+        github::retrieve_workflow_runs(config, &client, count, since).await?;
+
+    let earlier = if all_attempts {
+        retrieve_earlier_attempts(config, &client, &runs).await
+    } else {
+        Vec::new()
+    };
+
+    // The Runs are processed several at a time, all within this task rather
+    // than spawned. Their writes to the record of submitted Runs interleave,
+    // but each Run is claimed before it is sent, so no two of them touch the
+    // same record.
+    //
+    // Runs which haven't completed yet are left for a later query rather
+    // than being sent (and recorded as sent) without the Jobs still to
+    // finish, whose Steps GitHub describes with times not filled in yet.
+    // Likewise those which have completed but whose Jobs GitHub still lists
+    // as unfinished once settling gives up on them.
+    let unsettled = std::sync::Mutex::new(Vec::new());
+
+    stream::iter(
+        earlier
+            .iter()
+            .chain(&runs),
+    )
+    .filter(|run| {
+        let completed = run.status == "completed";
+        if !completed {
+            info!("Deferring Run {} ({})", run.run_id, run.status);
+        }
+        future::ready(completed)
+    })
+    .map(Ok)
+    .try_for_each_concurrent(concurrency.max(1), |run| {
+        debug!(run.run_id);
+
+        let client = &client;
+        let unsettled = &unsettled;
+        async move {
+            match submit_run(config, client, run).await {
+                Err(error) if is_unsettled(&error) => {
+                    info!(
+                        "Deferring Run {}; its Jobs haven't all finished",
+                        run.run_id
+                    );
+                    unsettled
+                        .lock()
+                        .unwrap()
+                        .push(run.created_at);
+                    Ok(())
+                }
+                result => result,
+            }
+        }
+    })
+    .await?;
+
+    let unsettled = unsettled
+        .into_inner()
+        .unwrap();
+
+    // Runs still in progress will need to be looked at again next time, so
+    // the cursor can only advance as far as the earliest of them.
+    if incremental {
+        let pending = runs
+            .iter()
+            .filter(|run| run.status != "completed")
+            .map(|run| run.created_at)
+            .chain(unsettled)
+            .min();
+        let newest = runs
+            .iter()
+            .map(|run| run.created_at)
+            .max();
+
+        if let Some(cursor) = pending.or(newest) {
+            history::write_cursor(config, cursor).await?;
+        }
+    }
+
+    Ok(())
+}
+
+fn is_unsettled(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<GitHubProblem>(),
+        Some(GitHubProblem::Unsettled(_))
+    )
+}
+
+/// GitHub lists only the latest attempt of each Run; the earlier attempts of
+/// those which were re-run have to be asked for one at a time. One which
+/// can't be retrieved (GitHub doesn't keep them forever) is skipped rather
+/// than holding up the rest.
+async fn retrieve_earlier_attempts(
+    config: &Config,
+    client: &reqwest::Client,
+    runs: &[WorkflowRun],
+) -> Vec<WorkflowRun> {
+    let mut earlier = Vec::new();
+
+    for run in runs {
+        for attempt in 1..run.run_attempt {
+            match github::retrieve_run(
+                client,
+                &config.host,
+                &config.owner,
+                &config.repository,
+                run.run_id,
+                Some(attempt),
+            )
+            .await
+            {
+                Result::Ok(mut previous) => {
+                    // shifted in time as the latest attempt was
+                    previous.delta = run.delta;
+                    earlier.push(previous);
+                }
+                Err(problem) => warn!(
+                    "Unable to retrieve attempt {} of Run {}: {}",
+                    attempt, run.run_id, problem
+                ),
+            }
+        }
+    }
+
+    earlier
+}
+
+/// Hand the URL to whatever the desktop uses to open links.
+pub(crate) fn open_in_browser(url: &str) -> Result<()> {
+    // on Windows `cmd /C start` would be the obvious choice, but cmd takes
+    // the `&` between query parameters as separating commands.
+    let (program, args): (&str, &[&str]) = if cfg!(windows) {
+        ("rundll32", &["url.dll,FileProtocolHandler"])
+    } else if cfg!(target_os = "macos") {
+        ("open", &[])
+    } else {
+        ("xdg-open", &[])
+    };
+
+    std::process::Command::new(program)
+        .args(args)
+        .arg(url)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .map_err(|error| anyhow!("Unable to run {}: {}", program, error))?;
+
+    Ok(())
+}
@@ -0,0 +1,110 @@
+//! Line up the Jobs and Steps of two Runs of the same workflow against one
+//! another, so that what got slower (or faster) between them stands out.
+
+use time::Duration;
+
+use crate::github::{WorkflowJob, WorkflowStep};
+
+/// A Job, or a Step of one, found in either Run, with how long it took in
+/// each. Something only in one of the Runs has no duration in the other.
+pub(crate) struct Difference {
+    pub(crate) job: String,
+    pub(crate) step: Option<String>,
+    pub(crate) older: Option<Duration>,
+    pub(crate) newer: Option<Duration>,
+}
+
+impl Difference {
+    /// How much longer it took in the newer Run; negative if it was quicker.
+    pub(crate) fn change(&self) -> Option<Duration> {
+        Some(self.newer? - self.older?)
+    }
+}
+
+/// Match up the Jobs of the two Runs, and the Steps within each Job, by
+/// name. They are given in the order of the newer Run, followed by any only
+/// in the older one.
+pub(crate) fn compare_jobs(older: &[WorkflowJob], newer: &[WorkflowJob]) -> Vec<Difference> {
+    let mut differences = Vec::new();
+
+    for (before, after) in pair(older, newer, |job| &job.name) {
+        let Some(job) = after.or(before) else {
+            continue;
+        };
+
+        differences.push(Difference {
+            job: job
+                .name
+                .clone(),
+            step: None,
+            older: before.map(|job| job.completed_at - job.started_at),
+            newer: after.map(|job| job.completed_at - job.started_at),
+        });
+
+        for (before, after) in pair(steps_of(before), steps_of(after), |step| &step.name) {
+            let Some(step) = after.or(before) else {
+                continue;
+            };
+
+            let duration = |step: &WorkflowStep| {
+                step.completed_at
+                    .map(|completed_at| completed_at - step.started_at)
+            };
+
+            differences.push(Difference {
+                job: job
+                    .name
+                    .clone(),
+                step: Some(
+                    step.name
+                        .clone(),
+                ),
+                older: before.and_then(duration),
+                newer: after.and_then(duration),
+            });
+        }
+    }
+
+    differences
+}
+
+fn steps_of(job: Option<&WorkflowJob>) -> &[WorkflowStep] {
+    job.map(|job| {
+        job.steps
+            .as_slice()
+    })
+    .unwrap_or_default()
+}
+
+// A name can appear more than once (the same action used twice in a Job,
+// say), in which case the first in one Run is paired with the first in the
+// other, and so on.
+fn pair<'a, T>(
+    older: &'a [T],
+    newer: &'a [T],
+    name: impl Fn(&T) -> &str,
+) -> Vec<(Option<&'a T>, Option<&'a T>)> {
+    let mut remaining: Vec<Option<&T>> = older
+        .iter()
+        .map(Some)
+        .collect();
+
+    let mut pairs = Vec::new();
+
+    for item in newer {
+        let matched = remaining
+            .iter_mut()
+            .find(|candidate| candidate.is_some_and(|candidate| name(candidate) == name(item)))
+            .and_then(Option::take);
+        pairs.push((matched, Some(item)));
+    }
+
+    pairs.extend(
+        remaining
+            .into_iter()
+            .flatten()
+            .map(|item| (Some(item), None)),
+    );
+
+    pairs
+}
@@ -0,0 +1,150 @@
+//! Runs from the queue which could not be processed, no matter how many
+//! times they were tried. Rather than retrying them forever, or dropping them
+//! on the floor, they are moved to the dead-letter directory along with a
+//! note of what went wrong, so that they can be looked at (and sent again
+//! once whatever was wrong has been fixed).
+
+use anyhow::{Result, anyhow};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use tracing::warn;
+
+use crate::metrics;
+use crate::queue::{self, QueuedRun};
+
+static DEAD_LETTER_DIRECTORY: OnceLock<PathBuf> = OnceLock::new();
+
+pub(crate) fn set_dead_letter_directory(directory: &str) -> Result<()> {
+    let path = Path::new(directory);
+    if !path.exists() {
+        std::fs::create_dir_all(path)?;
+    }
+
+    DEAD_LETTER_DIRECTORY
+        .set(path.to_path_buf())
+        .unwrap();
+
+    Ok(())
+}
+
+/// Whether there is somewhere to put Runs which can't be processed.
+pub(crate) fn is_enabled() -> bool {
+    DEAD_LETTER_DIRECTORY
+        .get()
+        .is_some()
+}
+
+/// Move the given file out of the queue and into the dead-letter directory.
+/// Alongside it goes a file with the same name but an `.error` extension
+/// describing the failure.
+pub(crate) fn bury(path: &Path, error: &anyhow::Error, attempts: u32) -> Result<()> {
+    let directory = DEAD_LETTER_DIRECTORY.wait();
+
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("No filename in {}", path.display()))?;
+    let destination = directory.join(name);
+    let details = destination.with_extension("error");
+
+    let contents = format!(
+        "failed_at: {}\nattempts: {}\nerror: {:#}\n",
+        OffsetDateTime::now_utc().format(&Rfc3339)?,
+        attempts,
+        error
+    );
+    std::fs::write(&details, contents)?;
+
+    // the queue and dead-letter directories may not be on the same
+    // filesystem, in which case the file has to be copied instead.
+    if std::fs::rename(path, &destination).is_err() {
+        std::fs::copy(path, &destination)?;
+        std::fs::remove_file(path)?;
+    }
+
+    warn!(
+        "Moved {} to {} after {} attempts: {:#}",
+        path.display(),
+        directory.display(),
+        attempts,
+        error
+    );
+    metrics::record_dead_letter();
+
+    Ok(())
+}
+
+/// A Run which was given up on, as found in the dead-letter directory.
+pub(crate) struct DeadLetter {
+    pub(crate) name: String,
+    path: PathBuf,
+    /// The Run, if it can be read at all.
+    pub(crate) item: Option<QueuedRun>,
+    pub(crate) failed_at: String,
+    pub(crate) error: String,
+}
+
+/// The Runs in the dead-letter directory, in order of name.
+pub(crate) fn read_dead_letters() -> Result<Vec<DeadLetter>> {
+    let directory = DEAD_LETTER_DIRECTORY.wait();
+
+    let mut letters = Vec::new();
+
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_none_or(|extension| extension != "json")
+        {
+            continue;
+        }
+
+        let name = path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+
+        let details = std::fs::read_to_string(path.with_extension("error")).unwrap_or_default();
+        let detail = |key: &str| {
+            details
+                .lines()
+                .find_map(|line| line.strip_prefix(&format!("{}: ", key)))
+                .unwrap_or("")
+                .to_string()
+        };
+
+        letters.push(DeadLetter {
+            name,
+            item: queue::read_item(&path).ok(),
+            failed_at: detail("failed_at"),
+            error: detail("error"),
+            path,
+        });
+    }
+
+    letters.sort_by(|a, b| {
+        a.name
+            .cmp(&b.name)
+    });
+
+    Ok(letters)
+}
+
+/// Try processing the Run again. If it succeeds the Run is removed from the
+/// dead-letter directory; if not it is left where it is.
+pub(crate) async fn resubmit(letter: &DeadLetter) -> Result<()> {
+    let item = queue::read_item(&letter.path)?;
+
+    queue::resubmit_item(item).await?;
+
+    std::fs::remove_file(&letter.path)?;
+    let _ = std::fs::remove_file(
+        letter
+            .path
+            .with_extension("error"),
+    );
+
+    Ok(())
+}
@@ -0,0 +1,109 @@
+//! Sort failures into broad categories, so that dashboards can show whether
+//! a pipeline is failing because of the code or because of the machinery
+//! running it. This is heuristic: it looks at the error message found in the
+//! log and the lines leading up to it, falling back on the name of the Step.
+
+use crate::github::LogExcerpt;
+
+/// Signs in a log that the runner, the network, or some service the Job
+/// depended on let it down, rather than the code under test.
+const INFRASTRUCTURE: &[&str] = &[
+    "the runner has received a shutdown signal",
+    "lost communication with the server",
+    "the hosted runner encountered an error",
+    "no space left on device",
+    "error response from daemon",
+    "pull access denied",
+    "toomanyrequests",
+    "manifest unknown",
+    "connection reset by peer",
+    "could not resolve host",
+    "temporary failure in name resolution",
+    "503 service unavailable",
+    "502 bad gateway",
+];
+
+const TIMEOUT: &[&str] = &[
+    "has exceeded the maximum execution time",
+    "timed out",
+    "timeout exceeded",
+    "deadline exceeded",
+];
+
+const COMPILE: &[&str] = &[
+    "could not compile",
+    "compilation failed",
+    "compilation error",
+    "build failed",
+    "cannot find symbol",
+    "syntaxerror",
+    "error[e",
+    "error ts",
+    "fatal error:",
+    "undefined reference to",
+];
+
+const TEST: &[&str] = &[
+    "test result: failed",
+    "tests failed",
+    "test failed",
+    "failing tests",
+    "assertionerror",
+    "assertion failed",
+    "expected:",
+    "--- fail:",
+];
+
+fn mentions(text: &str, signals: &[&str]) -> bool {
+    signals
+        .iter()
+        .any(|signal| text.contains(signal))
+}
+
+/// The category of a Step's failure: one of "infrastructure", "timeout",
+/// "compile", "test", or "unknown".
+pub(crate) fn classify_step(name: &str, excerpt: Option<&LogExcerpt>) -> &'static str {
+    if let Some(excerpt) = excerpt {
+        let text = excerpt
+            .context
+            .iter()
+            .chain(std::iter::once(&excerpt.message))
+            .map(|line| line.to_lowercase())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        // infrastructure first: a test run cut short by the runner going away
+        // would otherwise look like a test failure.
+        if mentions(&text, INFRASTRUCTURE) {
+            return "infrastructure";
+        }
+        if mentions(&text, TIMEOUT) {
+            return "timeout";
+        }
+        if mentions(&text, COMPILE) {
+            return "compile";
+        }
+        if mentions(&text, TEST) {
+            return "test";
+        }
+    }
+
+    let name = name.to_lowercase();
+    if name.contains("test") {
+        "test"
+    } else if name.contains("build") || name.contains("compile") {
+        "compile"
+    } else {
+        "unknown"
+    }
+}
+
+/// The category of a Job which failed without any Step failing, which
+/// happens when it times out or never got started.
+pub(crate) fn classify_job(conclusion: &str) -> Option<&'static str> {
+    match conclusion {
+        "timed_out" => Some("timeout"),
+        "startup_failure" => Some("infrastructure"),
+        _ => None,
+    }
+}
@@ -0,0 +1,174 @@
+//! Saved responses from the GitHub API. When recording, every response is
+//! written to the fixtures directory as it is received; when replaying, the
+//! responses are served from that directory instead and GitHub is never
+//! contacted. This makes it possible to work on how spans are built from a
+//! Run without waiting on (or needing access to) the real thing, and to get
+//! the same result every time.
+
+use anyhow::Result;
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tracing::{debug, warn};
+
+enum Mode {
+    Record,
+    Replay,
+}
+
+static FIXTURES: OnceLock<(Mode, PathBuf)> = OnceLock::new();
+
+/// A response as saved to disk.
+#[derive(Serialize, Deserialize)]
+struct Fixture {
+    url: String,
+    status: u16,
+    headers: BTreeMap<String, String>,
+    // base64, as some bodies (artifact archives, say) aren't text
+    body: String,
+}
+
+pub(crate) fn set_record_directory(directory: &str) -> Result<()> {
+    let path = Path::new(directory);
+    if !path.exists() {
+        std::fs::create_dir_all(path)?;
+    }
+
+    FIXTURES
+        .set((Mode::Record, path.to_path_buf()))
+        .ok();
+
+    Ok(())
+}
+
+pub(crate) fn set_replay_directory(directory: &str) {
+    FIXTURES
+        .set((Mode::Replay, PathBuf::from(directory)))
+        .ok();
+}
+
+pub(crate) fn is_replaying() -> bool {
+    matches!(FIXTURES.get(), Some((Mode::Replay, _)))
+}
+
+/// Fixtures are named after the URL they were retrieved from, with anything
+/// that isn't safe in a filename replaced.
+fn fixture_path(directory: &Path, url: &str) -> PathBuf {
+    let url = url
+        .split_once("://")
+        .map(|(_, rest)| rest)
+        .unwrap_or(url);
+
+    let name: String = url
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    directory.join(format!("{}.json", name))
+}
+
+/// If replaying, the saved response for the given URL. A URL for which
+/// nothing was recorded is answered with 404 Not Found.
+pub(crate) fn replay(url: &str) -> Option<reqwest::Response> {
+    let Some((Mode::Replay, directory)) = FIXTURES.get() else {
+        return None;
+    };
+
+    let path = fixture_path(directory, url);
+    debug!("Replaying {}", path.display());
+
+    let fixture = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|text| serde_json::from_str::<Fixture>(&text).ok())
+        .and_then(|fixture| {
+            let body = BASE64_STANDARD
+                .decode(&fixture.body)
+                .ok()?;
+            Some((fixture, body))
+        });
+
+    let Some((fixture, body)) = fixture else {
+        warn!("No fixture for {}", url);
+        let response = http::Response::builder()
+            .status(404)
+            .body(String::new())
+            .ok()?;
+        return Some(response.into());
+    };
+
+    let mut builder = http::Response::builder().status(fixture.status);
+    for (name, value) in &fixture.headers {
+        builder = builder.header(name, value);
+    }
+
+    builder
+        .body(body)
+        .ok()
+        .map(reqwest::Response::from)
+}
+
+/// If recording, save the response for the given URL. Doing so consumes the
+/// body, so an equivalent response is handed back in its place.
+pub(crate) async fn record(
+    url: &str,
+    response: reqwest::Response,
+) -> Result<reqwest::Response, reqwest::Error> {
+    let Some((Mode::Record, directory)) = FIXTURES.get() else {
+        return Ok(response);
+    };
+
+    let status = response.status();
+    let headers = response
+        .headers()
+        .clone();
+    let body = response
+        .bytes()
+        .await?;
+
+    let fixture = Fixture {
+        url: url.to_string(),
+        status: status.as_u16(),
+        headers: headers
+            .iter()
+            .filter_map(|(name, value)| {
+                value
+                    .to_str()
+                    .ok()
+                    .map(|value| (name.to_string(), value.to_string()))
+            })
+            .collect(),
+        body: BASE64_STANDARD.encode(&body),
+    };
+
+    let path = fixture_path(directory, url);
+    debug!("Recording {}", path.display());
+
+    match serde_json::to_string_pretty(&fixture) {
+        Ok(text) => {
+            if let Err(error) = std::fs::write(&path, text) {
+                warn!("Unable to write fixture {}: {}", path.display(), error);
+            }
+        }
+        Err(error) => warn!("Unable to encode fixture for {}: {}", url, error),
+    }
+
+    let mut builder = http::Response::builder().status(status);
+    for (name, value) in &headers {
+        builder = builder.header(name, value);
+    }
+
+    let response = builder
+        .body(body)
+        .expect("Rebuilding a response we already had");
+
+    Ok(response.into())
+}
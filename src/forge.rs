@@ -0,0 +1,291 @@
+//! Forgejo (and Gitea, from which it was forked) run GitHub Actions
+//! workflows too, and offer an API for their Runs and Jobs which is close
+//! enough to GitHub's that most of what we do carries over. What differs is
+//! where the API lives, how the token is presented, and some of the names
+//! and shapes of the fields in the objects returned. A self-hosted instance,
+//! given as the --enterprise-host, can be said to be one of these, and then
+//! the objects it returns are adapted into the form GitHub would have
+//! given them before being decoded.
+
+use serde_json::{Map, Value, json};
+use std::sync::OnceLock;
+
+use crate::github;
+
+pub(crate) const FORGES: [&str; 2] = ["github", "forgejo"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Forge {
+    GitHub,
+    Forgejo,
+}
+
+static SELF_HOSTED: OnceLock<Forge> = OnceLock::new();
+
+/// Say what kind of forge the self-hosted instance is. Without this it is
+/// taken to be a GitHub Enterprise Server.
+pub(crate) fn set_self_hosted_forge(name: &str) {
+    let forge = match name {
+        "forgejo" | "gitea" => Forge::Forgejo,
+        _ => Forge::GitHub,
+    };
+
+    SELF_HOSTED
+        .set(forge)
+        .unwrap();
+}
+
+/// What the self-hosted instance was said to be, if it was.
+pub(crate) fn self_hosted_forge() -> Option<Forge> {
+    SELF_HOSTED
+        .get()
+        .copied()
+}
+
+/// Which kind of forge the given host is. Only the self-hosted instance can
+/// be anything other than GitHub.
+pub(crate) fn forge_of(host: &str) -> Forge {
+    if host == github::GITHUB_HOST {
+        return Forge::GitHub;
+    }
+
+    SELF_HOSTED
+        .get()
+        .copied()
+        .unwrap_or(Forge::GitHub)
+}
+
+impl Forge {
+    /// Where the REST API lives on a self-hosted instance of this forge.
+    pub(crate) fn api_base(&self, host: &str) -> String {
+        match self {
+            Forge::GitHub => format!("https://{}/api/v3", host),
+            Forge::Forgejo => format!("https://{}/api/v1", host),
+        }
+    }
+
+    /// The value of the Authorization header carrying the token.
+    pub(crate) fn authorization(&self, token: &str) -> String {
+        match self {
+            Forge::GitHub => format!("Bearer {}", token),
+            Forge::Forgejo => format!("token {}", token),
+        }
+    }
+
+    /// A cheap request which needs authentication, for checking a token is
+    /// usable. Forgejo has no rate limit endpoint.
+    pub(crate) fn token_check_path(&self) -> &'static str {
+        match self {
+            Forge::GitHub => "/rate_limit",
+            Forge::Forgejo => "/user",
+        }
+    }
+
+    /// As given to --forge.
+    pub(crate) fn key(&self) -> &'static str {
+        match self {
+            Forge::GitHub => "github",
+            Forge::Forgejo => "forgejo",
+        }
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Forge::GitHub => "GitHub",
+            Forge::Forgejo => "Forgejo",
+        }
+    }
+}
+
+/// Adapt a Run from the given host into the form GitHub gives it in.
+pub(crate) fn adapt_run(host: &str, value: &mut Value) {
+    if forge_of(host) != Forge::Forgejo {
+        return;
+    }
+    let Some(object) = value.as_object_mut() else {
+        return;
+    };
+
+    // Forgejo's own API names things after its database columns, whereas
+    // Gitea's newer endpoints follow GitHub (except where they don't).
+    rename(object, "index", "run_number");
+    rename(object, "commit_sha", "head_sha");
+    rename(object, "trigger_event", "event");
+    rename(object, "trigger_user", "actor");
+    rename(object, "trigger_actor", "actor");
+    rename(object, "created", "created_at");
+    rename(object, "updated", "updated_at");
+    rename(object, "completed_at", "updated_at");
+    rename(object, "stopped", "updated_at");
+    rename(object, "started", "run_started_at");
+    rename(object, "started_at", "run_started_at");
+    rename(object, "title", "display_title");
+
+    if !object.contains_key("created_at")
+        && let Some(started) = object
+            .get("run_started_at")
+            .cloned()
+    {
+        object.insert("created_at".to_string(), started);
+    }
+
+    if !object.contains_key("updated_at")
+        && let Some(created) = object
+            .get("created_at")
+            .cloned()
+    {
+        object.insert("updated_at".to_string(), created);
+    }
+
+    for field in ["run_started_at", "updated_at"] {
+        clear_zero_time(object, field);
+    }
+
+    object
+        .entry("run_attempt")
+        .or_insert(json!(1));
+
+    // the path is of the workflow file at the ref it ran from, as in
+    // "ci.yml@refs/heads/main"; GitHub gives just the path.
+    let path = match (object.get("path"), object.get("workflow_id")) {
+        (Some(Value::String(path)), _) => path
+            .split_once('@')
+            .map(|(path, _)| path)
+            .unwrap_or(path)
+            .to_string(),
+        (_, Some(Value::String(filename))) => format!(".forgejo/workflows/{}", filename),
+        _ => String::new(),
+    };
+    let filename = path
+        .split('/')
+        .next_back()
+        .unwrap_or_default()
+        .to_string();
+    object.insert("path".to_string(), json!(path));
+
+    if !object.contains_key("name") {
+        object.insert("name".to_string(), json!(filename));
+    }
+    if !object.contains_key("display_title") {
+        let name = object["name"].clone();
+        object.insert("display_title".to_string(), name);
+    }
+
+    if !object.contains_key("head_branch") {
+        let branch = ["prettyref", "ref"]
+            .iter()
+            .find_map(|field| {
+                object
+                    .get(*field)
+                    .and_then(Value::as_str)
+            })
+            .map(|name| {
+                name.strip_prefix("refs/heads/")
+                    .unwrap_or(name)
+            })
+            .unwrap_or_default()
+            .to_string();
+        object.insert("head_branch".to_string(), json!(branch));
+    }
+
+    adapt_status(object);
+}
+
+/// Adapt a Job from the given host into the form GitHub gives it in.
+pub(crate) fn adapt_job(host: &str, value: &mut Value) {
+    if forge_of(host) != Forge::Forgejo {
+        return;
+    }
+    let Some(object) = value.as_object_mut() else {
+        return;
+    };
+
+    rename(object, "created", "created_at");
+    rename(object, "started", "started_at");
+    rename(object, "stopped", "completed_at");
+
+    for field in ["created_at", "started_at", "completed_at"] {
+        clear_zero_time(object, field);
+    }
+
+    object
+        .entry("head_branch")
+        .or_insert(json!(""));
+
+    if !object.contains_key("html_url") {
+        let url = object
+            .get("url")
+            .cloned()
+            .unwrap_or(json!(""));
+        object.insert("html_url".to_string(), url);
+    }
+
+    adapt_status(object);
+
+    // Steps which never ran have no times at all, rather than GitHub's
+    // skipped Step with times the same as its neighbours. They are left out.
+    if let Some(steps) = object
+        .get_mut("steps")
+        .and_then(Value::as_array_mut)
+    {
+        for step in steps.iter_mut() {
+            if let Some(step) = step.as_object_mut() {
+                for field in ["started_at", "completed_at"] {
+                    clear_zero_time(step, field);
+                }
+                adapt_status(step);
+            }
+        }
+        steps.retain(|step| {
+            step.get("started_at")
+                .is_some_and(|started| !started.is_null())
+        });
+    }
+}
+
+fn rename(object: &mut Map<String, Value>, from: &str, to: &str) {
+    if object.contains_key(to) {
+        return;
+    }
+    if let Some(value) = object.remove(from) {
+        object.insert(to.to_string(), value);
+    }
+}
+
+// Forgejo and Gitea are written in Go, where a time which hasn't happened
+// yet is often given as the zero time rather than as null.
+fn clear_zero_time(object: &mut Map<String, Value>, field: &str) {
+    if object
+        .get(field)
+        .and_then(Value::as_str)
+        .is_some_and(|time| time.starts_with("0001-01-01"))
+    {
+        object.insert(field.to_string(), Value::Null);
+    }
+}
+
+/// Forgejo has a single status which is the conclusion once there is one,
+/// where GitHub has a status of "completed" and a separate conclusion.
+fn adapt_status(object: &mut Map<String, Value>) {
+    if object.contains_key("conclusion") {
+        return;
+    }
+
+    let Some(status) = object
+        .get("status")
+        .and_then(Value::as_str)
+    else {
+        return;
+    };
+
+    let (status, conclusion) = match status {
+        "success" | "failure" | "cancelled" | "skipped" => ("completed", Some(status.to_string())),
+        "running" => ("in_progress", None),
+        "waiting" | "blocked" => ("waiting", None),
+        "unknown" => ("queued", None),
+        _ => return,
+    };
+
+    object.insert("status".to_string(), json!(status));
+    object.insert("conclusion".to_string(), json!(conclusion));
+}
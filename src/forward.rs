@@ -0,0 +1,194 @@
+//! Pass webhook deliveries on to other consumers once we have processed
+//! them. GitHub allows only so many webhooks per repository or organization,
+//! and each has to be set up and kept working separately; with this the
+//! listener can sit in front of the others and they receive the same
+//! deliveries it does, with the body and headers as GitHub sent them so that
+//! signatures still verify.
+
+use anyhow::{Result, anyhow};
+use axum::body::{Body, Bytes};
+use axum::http::{HeaderMap, Method, Request, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::{info, warn};
+
+// GitHub caps payloads at 25 MB.
+const MAX_PAYLOAD: usize = 25 * 1024 * 1024;
+
+// Consumers are given this long to accept a delivery, the same as GitHub
+// gives us.
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Forwarding {
+    targets: Vec<String>,
+    client: reqwest::Client,
+}
+
+static FORWARDING: OnceLock<Forwarding> = OnceLock::new();
+
+pub(crate) fn set_forward_targets(targets: Vec<String>) -> Result<()> {
+    for target in &targets {
+        reqwest::Url::parse(target)
+            .map_err(|error| anyhow!("Invalid URL given to --forward-to: {}", error))?;
+    }
+
+    let client = reqwest::Client::builder()
+        .timeout(FORWARD_TIMEOUT)
+        .build()?;
+
+    info!(
+        "Forwarding deliveries to {}",
+        targets
+            .iter()
+            .map(|target| describe_target(target))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    FORWARDING
+        .set(Forwarding { targets, client })
+        .map_err(|_| anyhow!("Forwarding already configured"))?;
+
+    Ok(())
+}
+
+/// How many consumers deliveries are being passed on to. Their URLs aren't
+/// given out, as they may carry credentials.
+pub(crate) fn target_count() -> usize {
+    FORWARDING
+        .get()
+        .map_or(0, |forwarding| {
+            forwarding
+                .targets
+                .len()
+        })
+}
+
+/// The host a target is on, which is all of its URL that gets logged; the
+/// rest may carry credentials, in the userinfo or the query or in the path
+/// itself.
+fn describe_target(target: &str) -> String {
+    let Ok(url) = reqwest::Url::parse(target) else {
+        return "(invalid URL)".to_string();
+    };
+
+    match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{}://{}:{}", url.scheme(), host, port),
+        (Some(host), None) => format!("{}://{}", url.scheme(), host),
+        (None, _) => url
+            .scheme()
+            .to_string(),
+    }
+}
+
+/// Headers which describe the connection a delivery arrived on rather than
+/// the delivery itself, and so aren't passed on.
+fn is_hop_by_hop(name: &header::HeaderName) -> bool {
+    name == header::HOST
+        || name == header::CONTENT_LENGTH
+        || name == header::CONNECTION
+        || name == header::TRANSFER_ENCODING
+        || name == header::TE
+        || name == header::TRAILER
+        || name == header::UPGRADE
+        || name == header::PROXY_AUTHORIZATION
+        || name.as_str() == "keep-alive"
+        || name.as_str() == "proxy-connection"
+}
+
+/// Hold onto the delivery while it is processed, then send it on to each of
+/// the targets. Anything other than a POST is let through.
+pub(crate) async fn forward_delivery(request: Request<Body>, next: Next) -> Response {
+    if FORWARDING
+        .get()
+        .is_none()
+        || request.method() != Method::POST
+    {
+        return next
+            .run(request)
+            .await;
+    }
+
+    let (parts, body) = request.into_parts();
+
+    let body = match axum::body::to_bytes(body, MAX_PAYLOAD).await {
+        Ok(body) => body,
+        Err(error) => {
+            warn!("Unable to read delivery: {}", error);
+            return (StatusCode::BAD_REQUEST, "Unable to read delivery").into_response();
+        }
+    };
+
+    let headers = parts
+        .headers
+        .clone();
+
+    let response = next
+        .run(Request::from_parts(parts, Body::from(body.clone())))
+        .await;
+
+    // the consumers shouldn't hold up our response to GitHub
+    tokio::spawn(send_to_targets(headers, body));
+
+    response
+}
+
+async fn send_to_targets(headers: HeaderMap, body: Bytes) {
+    let Some(forwarding) = FORWARDING.get() else {
+        return;
+    };
+
+    let mut outgoing = reqwest::header::HeaderMap::new();
+    for (name, value) in headers.iter() {
+        if !is_hop_by_hop(name) {
+            outgoing.append(name.clone(), value.clone());
+        }
+    }
+
+    let delivery = headers
+        .get("X-GitHub-Delivery")
+        .and_then(|value| {
+            value
+                .to_str()
+                .ok()
+        })
+        .unwrap_or("unknown");
+
+    let sends = forwarding
+        .targets
+        .iter()
+        .map(|target| {
+            let request = forwarding
+                .client
+                .post(target)
+                .headers(outgoing.clone())
+                .body(body.clone());
+            async move {
+                match request
+                    .send()
+                    .await
+                {
+                    Ok(response)
+                        if response
+                            .status()
+                            .is_success() => {}
+                    Ok(response) => warn!(
+                        "Forwarding delivery {} to {} got {}",
+                        delivery,
+                        describe_target(target),
+                        response.status()
+                    ),
+                    Err(error) => warn!(
+                        "Unable to forward delivery {} to {}: {}",
+                        delivery,
+                        describe_target(target),
+                        error.without_url()
+                    ),
+                }
+            }
+        });
+
+    futures::future::join_all(sends).await;
+}
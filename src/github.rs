@@ -1,22 +1,108 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
+use base64::prelude::{BASE64_STANDARD, Engine};
+use opentelemetry::trace::{Span, SpanKind, Tracer};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_semantic_conventions::attribute::{
+    ERROR_TYPE, HTTP_REQUEST_METHOD, HTTP_REQUEST_RESEND_COUNT, HTTP_RESPONSE_STATUS_CODE,
+    SERVER_ADDRESS, URL_FULL,
+};
 use reqwest::StatusCode;
 use reqwest::header::{HeaderMap, HeaderValue};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::ops::ControlFlow;
+use std::sync::{Mutex, OnceLock};
 use time::Duration;
-use time::OffsetDateTime;
+use time::format_description;
 use time::serde::rfc3339;
+use time::{OffsetDateTime, PrimitiveDateTime, UtcOffset};
 use tracing::{debug, info, warn};
 
 use crate::VERSION;
+use crate::forge::{self, Forge};
+use crate::settings::get_settings;
+use crate::{fixtures, metrics, redaction, schema, summary, traces};
 use crate::{get_api_token, get_program_start};
 
 /// A struct holding the configuration being used to retrieve information from
 /// GitHub's API.
-pub(crate) struct Config {
-    pub(crate) owner: String,
-    pub(crate) repository: String,
-    pub(crate) workflow: String,
-    pub(crate) devel: bool,
+pub struct Config {
+    pub host: String,
+    pub owner: String,
+    pub repository: String,
+    pub workflow: String,
+    pub devel: bool,
+}
+
+impl Config {
+    /// A workflow in a repository on github.com, identified by its filename.
+    pub fn new(owner: &str, repository: &str, workflow: &str) -> Config {
+        Config {
+            host: GITHUB_HOST.to_string(),
+            owner: owner.to_string(),
+            repository: repository.to_string(),
+            workflow: workflow.to_string(),
+            devel: false,
+        }
+    }
+}
+
+/// The host of the public GitHub service. Any other host is taken to be a
+/// GitHub Enterprise Server instance.
+pub(crate) const GITHUB_HOST: &str = "github.com";
+
+static ENTERPRISE_HOST: OnceLock<Option<String>> = OnceLock::new();
+
+pub(crate) fn set_enterprise_host(host: Option<String>) {
+    ENTERPRISE_HOST
+        .set(host)
+        .unwrap();
+}
+
+/// The GitHub Enterprise Server instance, if any, which we are permitted to
+/// send the enterprise token to.
+pub(crate) fn get_enterprise_host() -> Option<&'static str> {
+    ENTERPRISE_HOST
+        .wait()
+        .as_deref()
+}
+
+/// Where the REST API for the given host lives. GitHub Enterprise Server
+/// puts it under a path rather than on a separate api. subdomain, as does
+/// Forgejo (under a different path). Setting
+/// HERO_GITHUB_API_URL sends every request somewhere else entirely, such as
+/// to `hero mock-github`.
+pub(crate) fn api_base(host: &str) -> String {
+    if let Result::Ok(url) = std::env::var("HERO_GITHUB_API_URL") {
+        return url
+            .trim_end_matches('/')
+            .to_string();
+    }
+
+    if host == GITHUB_HOST {
+        "https://api.github.com".to_string()
+    } else {
+        forge::forge_of(host).api_base(host)
+    }
+}
+
+/// Extract the host from a URL such as a repository's html_url.
+pub(crate) fn host_of(url: &str) -> Option<&str> {
+    url.split_once("://")
+        .map(|(_, rest)| rest)
+        .and_then(|rest| {
+            rest.split('/')
+                .next()
+        })
+        .filter(|host| !host.is_empty())
+}
+
+impl Config {
+    fn api_base(&self) -> String {
+        api_base(&self.host)
+    }
 }
 
 // We have structs for all the relevant objects in the GitHub API. This was
@@ -25,13 +111,14 @@ pub(crate) struct Config {
 // object, so we were able to re-use this.
 
 #[derive(Debug, Deserialize)]
-pub(crate) struct WorkflowRun {
+pub struct WorkflowRun {
     pub(crate) actor: WorkflowActor,
     #[serde(rename = "id")]
     pub(crate) run_id: u64,
     pub(crate) run_number: u64,
     pub(crate) run_attempt: u64,
     pub(crate) head_branch: String,
+    pub(crate) head_sha: String,
     pub(crate) name: String,
     pub(crate) display_title: String,
     pub(crate) event: String, // what caused the workflow to run
@@ -41,13 +128,61 @@ pub(crate) struct WorkflowRun {
     pub(crate) created_at: OffsetDateTime,
     #[serde(with = "rfc3339")]
     pub(crate) updated_at: OffsetDateTime,
+    #[serde(with = "rfc3339::option", default)]
+    pub(crate) run_started_at: Option<OffsetDateTime>, // when this attempt began
     pub(crate) html_url: String,
     pub(crate) path: String, // the full path and version of the workflow code
+    #[serde(default)]
+    pub(crate) referenced_workflows: Vec<ReferencedWorkflow>,
 
     // and now our fields that are NOT in the response object
     #[serde(default)]
     pub(crate) delta: Duration,
+    #[serde(default)]
+    pub(crate) inputs: Map<String, Value>, // from the workflow_dispatch event, if any
+    #[serde(skip)]
+    pub(crate) payload: Value, // the object as received, for attribute mappings
+}
+
+impl WorkflowRun {
+    /// Decode a Run as GitHub describes it, whether in a response from its
+    /// API or in the payload of a workflow_run webhook.
+    pub fn from_json(value: Value) -> Result<WorkflowRun, serde_json::Error> {
+        decode_run(value)
+    }
+
+    pub fn id(&self) -> u64 {
+        self.run_id
+    }
+
+    pub fn attempt(&self) -> u64 {
+        self.run_attempt
+    }
+
+    pub fn status(&self) -> &str {
+        &self.status
+    }
+
+    pub fn conclusion(&self) -> Option<&str> {
+        self.conclusion
+            .as_deref()
+    }
+
+    pub fn html_url(&self) -> &str {
+        &self.html_url
+    }
+}
+
+/// A reusable workflow called via `uses:` from the Run's workflow. The path
+/// is of the form "owner/repo/.github/workflows/file.yaml@ref".
+#[derive(Debug, Deserialize)]
+pub(crate) struct ReferencedWorkflow {
+    pub(crate) path: String,
+    pub(crate) sha: String,
+    #[serde(rename = "ref", default)]
+    pub(crate) git_ref: Option<String>,
 }
+
 #[derive(Debug, Deserialize)]
 pub(crate) struct WorkflowActor {
     pub(crate) login: String,
@@ -55,34 +190,192 @@ pub(crate) struct WorkflowActor {
 
 #[derive(Deserialize)]
 struct ResponseRuns {
-    workflow_runs: Vec<WorkflowRun>,
+    workflow_runs: Vec<Value>,
+}
+
+/// Decode an object from GitHub, holding onto the original JSON so that
+/// fields we don't otherwise model can be lifted into span attributes by the
+/// mappings in the config file.
+pub(crate) fn decode_run(mut value: Value) -> Result<WorkflowRun, serde_json::Error> {
+    if get_settings().lenient {
+        schema::tolerate("run", &mut value, schema::RUN_FIELDS);
+    }
+
+    let mut run = WorkflowRun::deserialize(&value)?;
+    run.payload = value;
+
+    schema::check_value("run", "status", &run.status, schema::STATUSES);
+    if let Some(conclusion) = &run.conclusion {
+        schema::check_value("run", "conclusion", conclusion, schema::CONCLUSIONS);
+    }
+
+    Ok(run)
+}
+
+pub(crate) fn decode_job(mut value: Value) -> Result<WorkflowJob, serde_json::Error> {
+    if get_settings().lenient {
+        schema::tolerate("job", &mut value, schema::JOB_FIELDS);
+
+        // a Step we can't make sense of is dropped rather than losing the
+        // whole Job.
+        if let Some(steps) = value
+            .get_mut("steps")
+            .and_then(Value::as_array_mut)
+        {
+            for step in steps.iter_mut() {
+                schema::tolerate("step", step, schema::STEP_FIELDS);
+            }
+            steps.retain(|step| match WorkflowStep::deserialize(step) {
+                Ok(_) => true,
+                Err(error) => {
+                    warn!("Dropping Step: {}", error);
+                    false
+                }
+            });
+        }
+    }
+
+    let mut job = WorkflowJob::deserialize(&value)?;
+    job.payload = value;
+
+    schema::check_value("job", "status", &job.status, schema::STATUSES);
+    schema::check_value("job", "conclusion", &job.conclusion, schema::CONCLUSIONS);
+    for step in &job.steps {
+        schema::check_value("step", "status", &step.status, schema::STATUSES);
+        schema::check_value("step", "conclusion", &step.conclusion, schema::CONCLUSIONS);
+    }
+
+    Ok(job)
+}
+
+/// How a conclusion counts when deciding whether something failed. Anything
+/// not known to be a success or a failure (including conclusions GitHub has
+/// added since this was written) counts as neither.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Success,
+    Failure,
+    Neutral,
+}
+
+pub(crate) fn classify_conclusion(conclusion: &str) -> Outcome {
+    match conclusion {
+        "success" => Outcome::Success,
+        "failure" | "timed_out" | "cancelled" | "startup_failure" => Outcome::Failure,
+        _ => Outcome::Neutral,
+    }
+}
+
+/// Jobs and Steps which haven't finished have a null conclusion, which is
+/// taken as an empty one rather than failing to decode.
+fn null_as_empty<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer).map(Option::unwrap_or_default)
+}
+
+/// Decode each of a list of objects. Normally any failure is an error, but
+/// in lenient mode the objects that can't be decoded are skipped over.
+fn decode_all<T>(
+    values: Vec<Value>,
+    decode: fn(Value) -> Result<T, serde_json::Error>,
+) -> Result<Vec<T>, serde_json::Error> {
+    if !get_settings().lenient {
+        return values
+            .into_iter()
+            .map(decode)
+            .collect();
+    }
+
+    let decoded = values
+        .into_iter()
+        .filter_map(|value| match decode(value) {
+            Ok(decoded) => Some(decoded),
+            Err(error) => {
+                warn!("Skipping object which could not be decoded: {}", error);
+                None
+            }
+        })
+        .collect();
+
+    Ok(decoded)
 }
 
+/// GitHub lists at most this many Runs at a time, whatever is asked for.
+const RUNS_PER_PAGE: u32 = 100;
+
 pub(crate) async fn retrieve_workflow_runs(
     config: &Config,
     client: &reqwest::Client,
     count: u32,
+    since: Option<OffsetDateTime>,
 ) -> Result<Vec<WorkflowRun>> {
     // use token to retrieve runs for the given workflow from GitHub API
     info!("List Runs for Workflow {}", config.workflow);
 
-    let url = format!(
-        "https://api.github.com/repos/{}/{}/actions/workflows/{}/runs?per_page={}&page=1",
-        config.owner, config.repository, config.workflow, count
+    let per_page = count.clamp(1, RUNS_PER_PAGE);
+
+    let mut base = format!(
+        "{}/repos/{}/{}/actions/workflows/{}/runs?per_page={}",
+        config.api_base(),
+        config.owner,
+        config.repository,
+        config.workflow,
+        per_page
     );
-    debug!(?url);
 
-    let response = client
-        .get(&url)
-        .send()
-        .await?;
+    // only those created at or after the given time
+    if let Some(since) = since {
+        base.push_str(&format!(
+            "&created=%3E%3D{}",
+            since.format(&time::format_description::well_known::Rfc3339)?
+        ));
+    }
 
-    // retrieve the run ID of the most recent 10 runs
-    let body: ResponseRuns = response
-        .json()
-        .await?;
+    let mut runs = Vec::new();
+    let mut page = 1;
+
+    loop {
+        let url = format!("{}&page={}", base, page);
+        debug!(?url);
+
+        let response = send_request(client, &url).await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(workflow_not_found(config, client).await);
+        }
+
+        let mut body: ResponseRuns = response
+            .json()
+            .await?;
 
-    let mut runs: Vec<WorkflowRun> = body.workflow_runs;
+        for value in body
+            .workflow_runs
+            .iter_mut()
+        {
+            forge::adapt_run(&config.host, value);
+        }
+
+        let listed = body
+            .workflow_runs
+            .len();
+
+        runs.extend(decode_all(body.workflow_runs, decode_run)?);
+
+        // without a starting time we want only the most recent count, but
+        // with one every Run since then is needed; were more created than
+        // fit on a page, stopping at the first would lose the older ones for
+        // good once the cursor moved past them.
+        if listed < per_page as usize || (since.is_none() && runs.len() >= count as usize) {
+            break;
+        }
+        page += 1;
+    }
+
+    if since.is_none() {
+        runs.truncate(count as usize);
+    }
 
     for run in runs.iter_mut() {
         // calculate the change to the origin time if we are in development
@@ -100,6 +393,161 @@ pub(crate) async fn retrieve_workflow_runs(
     Ok(runs)
 }
 
+#[derive(Deserialize)]
+struct ResponseWorkflows {
+    workflows: Vec<Workflow>,
+}
+
+#[derive(Deserialize)]
+struct Workflow {
+    path: String,
+}
+
+/// Look up the filename of the workflow with the given numeric ID.
+pub(crate) async fn retrieve_workflow_filename(
+    config: &Config,
+    client: &reqwest::Client,
+) -> Result<String> {
+    let url = format!(
+        "{}/repos/{}/{}/actions/workflows/{}",
+        config.api_base(),
+        config.owner,
+        config.repository,
+        config.workflow
+    );
+    debug!(?url);
+
+    let response = send_request(client, &url).await?;
+
+    if response.status() == StatusCode::NOT_FOUND {
+        return Err(workflow_not_found(config, client).await);
+    }
+
+    let workflow: Workflow = response
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let filename = workflow
+        .path
+        .rsplit('/')
+        .next()
+        .unwrap_or(&workflow.path)
+        .to_string();
+
+    Ok(filename)
+}
+
+/// GitHub gives a bare 404 if the workflow named doesn't exist, which isn't
+/// much help if the problem is a typo. Look up what workflows the repository
+/// does have and suggest the closest; if none are close, list them all.
+async fn workflow_not_found(config: &Config, client: &reqwest::Client) -> anyhow::Error {
+    let url = format!(
+        "{}/repos/{}/{}/actions/workflows?per_page=100",
+        config.api_base(),
+        config.owner,
+        config.repository
+    );
+
+    let workflows = match send_request(client, &url).await {
+        Ok(response) if response.status() == StatusCode::OK => response
+            .json::<ResponseWorkflows>()
+            .await
+            .ok(),
+        _ => None,
+    };
+
+    let Some(workflows) = workflows else {
+        return anyhow!(
+            "repository '{}/{}' not found, or not accessible with the token supplied",
+            config.owner,
+            config.repository
+        );
+    };
+
+    let mut names: Vec<(usize, &str)> = workflows
+        .workflows
+        .iter()
+        .map(|workflow| {
+            let name = workflow
+                .path
+                .rsplit('/')
+                .next()
+                .unwrap_or(&workflow.path);
+            (edit_distance(&config.workflow, name), name)
+        })
+        .collect();
+
+    if names.is_empty() {
+        return anyhow!(
+            "workflow '{}' not found; repository {}/{} has no workflows",
+            config.workflow,
+            config.owner,
+            config.repository
+        );
+    }
+
+    names.sort();
+
+    let threshold = (config
+        .workflow
+        .len()
+        / 3)
+    .max(3);
+    let close: Vec<&str> = names
+        .iter()
+        .filter(|(distance, _)| *distance <= threshold)
+        .map(|(_, name)| *name)
+        .collect();
+
+    let suggestions = if close.is_empty() {
+        names
+            .iter()
+            .map(|(_, name)| *name)
+            .collect()
+    } else {
+        close
+    };
+
+    anyhow!(
+        "workflow '{}' not found; did you mean: {}?",
+        config.workflow,
+        suggestions.join(", ")
+    )
+}
+
+/// The number of single character insertions, deletions, or substitutions
+/// needed to turn one string into the other.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b
+        .chars()
+        .collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, x) in a
+        .chars()
+        .enumerate()
+    {
+        let mut current = vec![i + 1];
+        for (j, y) in b
+            .iter()
+            .enumerate()
+        {
+            let substitution = previous[j] + usize::from(x != *y);
+            let insertion = current[j] + 1;
+            let deletion = previous[j + 1] + 1;
+            current.push(
+                substitution
+                    .min(insertion)
+                    .min(deletion),
+            );
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct WorkflowJob {
     #[serde(rename = "id")]
@@ -107,29 +555,57 @@ pub(crate) struct WorkflowJob {
     pub(crate) name: String,
     pub(crate) head_branch: String,
     pub(crate) status: String,
+    #[serde(default, deserialize_with = "null_as_empty")]
     pub(crate) conclusion: String,
+    #[serde(with = "rfc3339::option", default)]
+    pub(crate) created_at: Option<OffsetDateTime>, // when the job was queued
     #[serde(with = "rfc3339")]
     pub(crate) started_at: OffsetDateTime,
     #[serde(with = "rfc3339")]
     pub(crate) completed_at: OffsetDateTime,
     pub(crate) steps: Vec<WorkflowStep>,
     pub(crate) html_url: String,
+    #[serde(default)]
+    pub(crate) labels: Vec<String>, // the runs-on labels a runner must match
+
+    // not in the response object
+    #[serde(skip)]
+    pub(crate) payload: Value,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct WorkflowStep {
     pub(crate) name: String,
     pub(crate) status: String,
+    #[serde(default, deserialize_with = "null_as_empty")]
     pub(crate) conclusion: String,
     #[serde(with = "rfc3339")]
     pub(crate) started_at: OffsetDateTime,
-    #[serde(with = "rfc3339")]
-    pub(crate) completed_at: OffsetDateTime,
+    #[serde(with = "rfc3339::option", default)]
+    pub(crate) completed_at: Option<OffsetDateTime>, // null while still running
+}
+
+impl WorkflowJob {
+    /// Jobs which come from a called reusable workflow are named by GitHub
+    /// as "caller / job", where the caller is the name of the job in the
+    /// calling workflow that has the `uses:` clause.
+    pub(crate) fn caller_job(&self) -> Option<&str> {
+        self.name
+            .split_once(" / ")
+            .map(|(caller, _)| caller)
+    }
 }
 
 #[derive(Deserialize)]
 struct ResponseJobs {
-    jobs: Vec<WorkflowJob>,
+    #[serde(default)]
+    total_count: Option<u64>,
+    jobs: Vec<Value>,
+}
+
+#[derive(Deserialize)]
+struct ResponseCheckRuns {
+    total_count: u64,
 }
 
 // an error to convey any serde_json decoding problem.
@@ -138,6 +614,7 @@ pub(crate) enum GitHubProblem {
     RemoteFailure(reqwest::Error),
     ApiError(StatusCode),
     DecodeFailure(serde_json::Error),
+    Unsettled(u64),
 }
 
 impl From<reqwest::Error> for GitHubProblem {
@@ -160,6 +637,9 @@ impl std::fmt::Display for GitHubProblem {
                 write!(f, "Error response from GitHub API: {} ", status)
             }
             GitHubProblem::DecodeFailure(e) => write!(f, "Decode failure: {:?}", e),
+            GitHubProblem::Unsettled(run_id) => {
+                write!(f, "Jobs of Run {} haven't all finished", run_id)
+            }
         }
     }
 }
@@ -170,27 +650,235 @@ impl std::error::Error for GitHubProblem {
             GitHubProblem::RemoteFailure(e) => Some(e),
             GitHubProblem::ApiError(_) => None,
             GitHubProblem::DecodeFailure(e) => Some(e),
+            GitHubProblem::Unsettled(_) => None,
         }
     }
 }
 
-pub(crate) async fn retrieve_run_jobs(
-    config: &Config,
+// How many times we will wait out a rate limit on a single request before
+// giving up and returning the error response to the caller.
+const MAX_RATE_LIMIT_RETRIES: u32 = 5;
+
+/// Make a GET request of the GitHub API. If GitHub says we have hit a rate
+/// limit (either the primary limit or one of the secondary "abuse detection"
+/// limits that trip during bursts of activity) we sleep for the time
+/// indicated and then try again, rather than losing the Run. If fixtures are
+/// being replayed the response comes from those instead.
+async fn send_request(
     client: &reqwest::Client,
-    run: &WorkflowRun,
-) -> Result<Vec<WorkflowJob>, GitHubProblem> {
-    info!("List Jobs in Run {}", run.run_id);
-    let url = format!(
-        "https://api.github.com/repos/{}/{}/actions/runs/{}/jobs",
-        config.owner, config.repository, run.run_id
-    );
+    url: &str,
+) -> Result<reqwest::Response, reqwest::Error> {
+    if let Some(response) = fixtures::replay(url) {
+        return Ok(response);
+    }
 
-    debug!(?url);
+    let mut attempt = 0;
 
-    let response = client
+    loop {
+        let response = send_attempt(client, url, attempt).await?;
+
+        if let Some(remaining) = response
+            .headers()
+            .get("x-ratelimit-remaining")
+            .and_then(|value| {
+                value
+                    .to_str()
+                    .ok()
+            })
+            .and_then(|value| {
+                value
+                    .trim()
+                    .parse::<i64>()
+                    .ok()
+            })
+        {
+            summary::record_rate_limit(remaining);
+        }
+
+        match rate_limit_delay(&response) {
+            Some(delay) if attempt < MAX_RATE_LIMIT_RETRIES => {
+                warn!(
+                    "Rate limited by GitHub ({}); retrying in {} seconds",
+                    response.status(),
+                    delay.as_secs()
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            _ => {
+                check_deprecation(&response);
+                return fixtures::record(url, response).await;
+            }
+        }
+    }
+}
+
+/// The version of the REST API we ask for. GitHub supports each version for
+/// at least two years after its successor is released.
+const API_VERSION: &str = "2022-11-28";
+
+/// The headers with which GitHub signals that an endpoint, or the API
+/// version we are pinned to, is going away.
+const DEPRECATION_HEADERS: [&str; 3] = ["deprecation", "sunset", "warning"];
+
+// Which deprecation notices have already been logged; the same one comes
+// back on every request of the affected endpoint.
+static DEPRECATIONS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Look for notice that something we depend on is being retired, so that we
+/// find out ahead of it breaking. Each distinct notice is logged once, and
+/// every response carrying one counted.
+fn check_deprecation(response: &reqwest::Response) {
+    let headers = response.headers();
+
+    for name in DEPRECATION_HEADERS {
+        let Some(value) = headers
+            .get(name)
+            .and_then(|value| {
+                value
+                    .to_str()
+                    .ok()
+            })
+        else {
+            continue;
+        };
+
+        metrics::record_api_deprecation(name);
+
+        let notice = format!("{}: {}", name, value);
+        let first = DEPRECATIONS
+            .lock()
+            .unwrap()
+            .insert(notice);
+
+        if first {
+            let path = response
+                .url()
+                .path();
+            warn!(
+                "GitHub signals API version {} or {} is being retired ({}: {})",
+                API_VERSION, path, name, value
+            );
+        }
+    }
+}
+
+/// If the response indicates we have been rate limited, work out how long
+/// GitHub wants us to wait before trying again.
+fn rate_limit_delay(response: &reqwest::Response) -> Option<std::time::Duration> {
+    let status = response.status();
+    if status != StatusCode::FORBIDDEN && status != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+
+    let headers = response.headers();
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| {
+                value
+                    .to_str()
+                    .ok()
+            })
+            .and_then(|value| {
+                value
+                    .trim()
+                    .parse::<i64>()
+                    .ok()
+            })
+    };
+
+    // secondary rate limits say how long to wait directly,
+    if let Some(seconds) = header("retry-after") {
+        return Some(std::time::Duration::from_secs(seconds.max(1) as u64));
+    }
+
+    // whereas exhausting the primary rate limit tells us when it resets.
+    if header("x-ratelimit-remaining") == Some(0)
+        && let Some(reset) = header("x-ratelimit-reset")
+    {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        return Some(std::time::Duration::from_secs((reset - now).max(1) as u64));
+    }
+
+    None
+}
+
+/// Each attempt at a request is wrapped in a client span sent to our own
+/// telemetry so that we can see when it is GitHub's latency (rather than the
+/// workflow's) that explains a slow export.
+async fn send_attempt(
+    client: &reqwest::Client,
+    url: &str,
+    attempt: u32,
+) -> Result<reqwest::Response, reqwest::Error> {
+    let tracer = traces::self_tracer();
+
+    let mut attributes = vec![
+        KeyValue::new(HTTP_REQUEST_METHOD, "GET"),
+        KeyValue::new(URL_FULL, url.to_string()),
+    ];
+    if let Some(host) = reqwest::Url::parse(url)
+        .ok()
+        .and_then(|parsed| {
+            parsed
+                .host_str()
+                .map(str::to_string)
+        })
+    {
+        attributes.push(KeyValue::new(SERVER_ADDRESS, host));
+    }
+    if attempt > 0 {
+        attributes.push(KeyValue::new(HTTP_REQUEST_RESEND_COUNT, attempt as i64));
+    }
+
+    let mut span = tracer
+        .span_builder("GET")
+        .with_kind(SpanKind::Client)
+        .with_attributes(attributes)
+        .start_with_context(&tracer, &Context::current());
+
+    let result = client
         .get(url)
         .send()
-        .await?;
+        .await;
+
+    match &result {
+        Ok(response) => {
+            let status = response.status();
+            span.set_attribute(KeyValue::new(
+                HTTP_RESPONSE_STATUS_CODE,
+                status.as_u16() as i64,
+            ));
+            if status.is_client_error() || status.is_server_error() {
+                span.set_attribute(KeyValue::new(
+                    ERROR_TYPE,
+                    status
+                        .as_str()
+                        .to_string(),
+                ));
+                span.set_status(opentelemetry::trace::Status::error(status.to_string()));
+            }
+        }
+        Err(error) => {
+            span.set_attribute(KeyValue::new(ERROR_TYPE, "request_failure"));
+            span.set_status(opentelemetry::trace::Status::error(error.to_string()));
+        }
+    }
+
+    span.end();
+
+    result
+}
+
+/// Make a GET request of the GitHub API and decode the JSON response.
+async fn retrieve_json<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    url: String,
+) -> Result<T, GitHubProblem> {
+    debug!(?url);
+
+    let response = send_request(client, &url).await?;
 
     // we get the whole body, then attempt to deserialize it. This allows us
     // to trap error responses coming from their API rather than just breaking
@@ -207,96 +895,1409 @@ pub(crate) async fn retrieve_run_jobs(
         return Err(GitHubProblem::ApiError(status));
     }
 
-    let json: ResponseJobs = serde_json::from_str(&body)?;
+    let json: T = serde_json::from_str(&body)?;
 
-    Ok(json.jobs)
+    Ok(json)
 }
 
-pub(crate) async fn retrieve_job_log(
-    config: &Config,
-    client: &reqwest::Client,
-    job_id: u64,
-) -> Result<Option<String>, GitHubProblem> {
-    info!("Retrieve logs for jobs {}", job_id);
-    let url = format!(
-        "https://api.github.com/repos/{}/{}/actions/jobs/{}/logs",
-        config.owner, config.repository, job_id
-    );
-
-    debug!(?url);
+/// The Jobs of a Run, a page at a time. A large matrix can give a Run
+/// thousands of Jobs, so rather than gathering them all up front each page
+/// is handed over as it is retrieved.
+pub(crate) struct JobPages {
+    host: String,
+    base: String,
+    url: String,
+    run_id: u64,
+    page: u32,
+    done: bool,
+    settled: VecDeque<ResponseJobs>,
+}
 
-    let response = client
-        .get(url)
-        .send()
-        .await?;
+const JOBS_PER_PAGE: usize = 100;
 
-    // astonishingly, the request crate follows redirections for you by
-    // default. So we don't need to worry about the 302 Found that the GitHub
-    // API documentation describes at length, and instead just let the client
-    // follow the redirect (and there appears to be more than one).
+fn any_unfinished(jobs: &[Value]) -> bool {
+    jobs.iter()
+        .any(|job| {
+            job.get("status")
+                .and_then(Value::as_str)
+                != Some("completed")
+        })
+}
 
-    let status = response.status();
+// For a little while after a Run completes GitHub can list it with no Jobs,
+// or without the last of them, or with some still in progress. Runs which
+// completed within this long ago are checked for that.
+const SETTLE_WINDOW: Duration = Duration::minutes(10);
 
-    if status != StatusCode::OK {
-        warn!("{}", status);
+// How long to wait before retrieving the Jobs again, and how many times.
+const SETTLE_DELAY: std::time::Duration = std::time::Duration::from_secs(5);
+const SETTLE_RETRIES: u32 = 3;
 
-        let body = response
-            .text()
-            .await?;
-        debug!(body);
+impl JobPages {
+    pub(crate) fn new(config: &Config, run: &WorkflowRun) -> JobPages {
+        info!("List Jobs in Run {}", run.run_id);
+        let base = format!(
+            "{}/repos/{}/{}",
+            config.api_base(),
+            config.owner,
+            config.repository
+        );
+        let url = format!("{}/actions/runs/{}/jobs", base, run.run_id);
 
-        return Err(GitHubProblem::ApiError(status));
+        JobPages {
+            host: config
+                .host
+                .clone(),
+            base,
+            url,
+            run_id: run.run_id,
+            page: 0,
+            done: false,
+            settled: VecDeque::new(),
+        }
     }
 
-    let body = response
-        .text()
-        .await?; // FIXME we need to make this streaming
-
-    let possible = body
-        .lines()
-        .filter_map(|line| {
-            // trim off the timestamp
-            line.split_once(' ')
-                .map(|(_, message)| message)
-        })
-        .find(|message| {
-            // see if an error marker is present
-            message
-                .to_lowercase()
-                .contains("error:")
-        });
+    async fn retrieve_page(
+        &self,
+        client: &reqwest::Client,
+        page: u32,
+    ) -> Result<ResponseJobs, GitHubProblem> {
+        let url = format!("{}?per_page={}&page={}", self.url, JOBS_PER_PAGE, page);
 
-    if let Some(message) = possible {
-        debug!(?message);
-        Ok(Some(message.to_string()))
-    } else {
-        Ok(None)
-    }
-}
+        let mut json: ResponseJobs = retrieve_json(client, url).await?;
 
-pub(crate) fn setup_api_client() -> Result<reqwest::Client> {
-    // get GITHUB_TOKEN value passed in from environment variable
-    let token = get_api_token();
+        for value in json
+            .jobs
+            .iter_mut()
+        {
+            forge::adapt_job(&self.host, value);
+        }
 
-    // Initialize a request Client as we will be making many requests of
-    // the GitHub API.
-    let mut headers = HeaderMap::new();
+        Ok(json)
+    }
 
-    // .parse() is needed here and below to get from &str to HeaderValue.
+    /// Wait for the Jobs of a Run which has only just completed to all be
+    /// listed, so as not to send a visibly truncated trace. The pages are
+    /// checked against the Run, and against the number of check runs in its
+    /// check suite (each Job is one of these underneath) if that can be had,
+    /// and retrieved again after a pause if they fall short. If they still do
+    /// after a few tries, what there is gets sent, unless some of the Jobs
+    /// haven't finished, in which case the Run is refused as unsettled.
+    pub(crate) async fn settle(
+        &mut self,
+        client: &reqwest::Client,
+        run: &WorkflowRun,
+    ) -> Result<(), GitHubProblem> {
+        if run.status != "completed" || OffsetDateTime::now_utc() - run.updated_at > SETTLE_WINDOW {
+            return Ok(());
+        }
 
-    let mut auth: HeaderValue = format!("Bearer {}", token).parse()?;
-    auth.set_sensitive(true);
-    headers.insert("Authorization", auth);
+        // a re-run attempt has a check suite of its own but can list Jobs
+        // carried over from the attempt before, so only the first attempt
+        // can be compared.
+        let expected = match run
+            .payload
+            .get("check_suite_id")
+            .and_then(Value::as_u64)
+        {
+            Some(suite) if run.run_attempt == 1 => {
+                let url = format!("{}/check-suites/{}/check-runs?per_page=1", self.base, suite);
+                match retrieve_json::<ResponseCheckRuns>(client, url).await {
+                    Ok(response) => Some(response.total_count),
+                    Err(problem) => {
+                        debug!("Unable to retrieve Check Runs: {}", problem);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
 
-    headers.insert("Accept", "application/vnd.github+json".parse()?);
+        for retry in 0..=SETTLE_RETRIES {
+            // every page is retrieved, as an unfinished Job on a later one
+            // would otherwise only be found once the earlier ones had been
+            // made into spans.
+            let mut pages = VecDeque::new();
+            loop {
+                let json = self
+                    .retrieve_page(client, pages.len() as u32 + 1)
+                    .await?;
+                let more = json
+                    .jobs
+                    .len()
+                    == JOBS_PER_PAGE;
+                pages.push_back(json);
+                if !more {
+                    break;
+                }
+            }
+
+            let listed = pages[0]
+                .total_count
+                .unwrap_or(
+                    pages
+                        .iter()
+                        .map(|json| {
+                            json.jobs
+                                .len() as u64
+                        })
+                        .sum(),
+                );
+            let unfinished = pages
+                .iter()
+                .any(|json| any_unfinished(&json.jobs));
+
+            // a Run which failed before starting any Jobs legitimately has
+            // none, which only the check suite can tell us.
+            let short = unfinished
+                || match expected {
+                    Some(expected) => listed < expected,
+                    None => listed == 0,
+                };
+
+            if !short {
+                self.settled = pages;
+                return Ok(());
+            }
+
+            if retry == SETTLE_RETRIES && unfinished {
+                return Err(GitHubProblem::Unsettled(run.run_id));
+            }
+
+            if retry == SETTLE_RETRIES {
+                warn!(
+                    "Jobs of Run {} still not all listed; sending the {} there are",
+                    run.run_id, listed
+                );
+                self.settled = pages;
+                return Ok(());
+            }
+
+            info!(
+                "Jobs of Run {} not all listed yet; retrieving again in {} seconds",
+                run.run_id,
+                SETTLE_DELAY.as_secs()
+            );
+            tokio::time::sleep(SETTLE_DELAY).await;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) async fn next(
+        &mut self,
+        client: &reqwest::Client,
+    ) -> Result<Option<Vec<WorkflowJob>>, GitHubProblem> {
+        if self.done {
+            return Ok(None);
+        }
+
+        self.page += 1;
+
+        let json = match self
+            .settled
+            .pop_front()
+        {
+            Some(json) => json,
+            None => {
+                self.retrieve_page(client, self.page)
+                    .await?
+            }
+        };
+
+        // a short page is the last one
+        if json
+            .jobs
+            .len()
+            < JOBS_PER_PAGE
+        {
+            self.done = true;
+        }
+
+        if json
+            .jobs
+            .is_empty()
+        {
+            return Ok(None);
+        }
+
+        // a Job still in progress has no completion time and can't be
+        // made into a span. settle() will have caught this for a Run which
+        // only just completed, but not for one it didn't look at.
+        if any_unfinished(&json.jobs) {
+            return Err(GitHubProblem::Unsettled(self.run_id));
+        }
+
+        let jobs = decode_all(json.jobs, decode_job)?;
+
+        Ok(Some(jobs))
+    }
+}
+
+// Deployments are not directly attached to a Run; rather they are made
+// against a commit, and the Run that performed them is only discoverable
+// via the log_url (or target_url) of the statuses posted as the deployment
+// progresses.
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Deployment {
+    pub(crate) id: u64,
+    pub(crate) environment: String,
+    #[serde(with = "rfc3339")]
+    pub(crate) created_at: OffsetDateTime,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct DeploymentStatus {
+    pub(crate) state: String, // queued, in_progress, success, failure, etc
+    #[serde(with = "rfc3339")]
+    pub(crate) created_at: OffsetDateTime,
+    #[serde(default)]
+    pub(crate) log_url: Option<String>,
+    #[serde(default)]
+    pub(crate) target_url: Option<String>,
+}
+
+impl DeploymentStatus {
+    /// The Job which posted this status, from the link to its log.
+    pub(crate) fn job_id(&self) -> Option<u64> {
+        [&self.log_url, &self.target_url]
+            .into_iter()
+            .flatten()
+            .find_map(|url| {
+                let (_, rest) = url.split_once("/job/")?;
+                rest.split(['/', '?', '#'])
+                    .next()?
+                    .parse()
+                    .ok()
+            })
+    }
+
+    fn is_from_run(&self, run_id: u64) -> bool {
+        let marker = format!("/actions/runs/{}", run_id);
+
+        [&self.log_url, &self.target_url]
+            .into_iter()
+            .flatten()
+            .any(|url| match url.split_once(&marker) {
+                Some((_, rest)) => rest.is_empty() || rest.starts_with('/'),
+                None => false,
+            })
+    }
+}
+
+/// A Deployment along with the history of statuses it went through, in
+/// chronological order.
+#[derive(Debug)]
+pub(crate) struct RunDeployment {
+    pub(crate) deployment: Deployment,
+    pub(crate) statuses: Vec<DeploymentStatus>,
+}
+
+#[derive(Deserialize)]
+struct FileContents {
+    sha: String,
+    #[serde(default)]
+    content: String,
+    #[serde(default)]
+    encoding: String,
+}
+
+/// A workflow's file as it was at a Run's commit.
+pub(crate) struct WorkflowSource {
+    /// The git blob SHA of the file, which changes whenever the workflow's
+    /// definition does.
+    pub(crate) hash: String,
+    pub(crate) text: Option<String>,
+}
+
+pub(crate) async fn retrieve_workflow_source(
+    config: &Config,
+    client: &reqwest::Client,
+    path: &str,
+    run: &WorkflowRun,
+) -> Result<WorkflowSource, GitHubProblem> {
+    info!("Get workflow file {} at {}", path, run.head_sha);
+    let url = format!(
+        "{}/repos/{}/{}/contents/{}?ref={}",
+        config.api_base(),
+        config.owner,
+        config.repository,
+        path,
+        run.head_sha
+    );
+
+    let contents: FileContents = retrieve_json(client, url).await?;
+
+    // the content comes base64 encoded, wrapped over many lines. Files too
+    // large to be included are left without it.
+    let text = if contents.encoding == "base64" {
+        let encoded: String = contents
+            .content
+            .split_whitespace()
+            .collect();
+        BASE64_STANDARD
+            .decode(encoded)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+    } else {
+        None
+    };
+
+    Ok(WorkflowSource {
+        hash: contents.sha,
+        text,
+    })
+}
+
+#[derive(Deserialize)]
+struct CheckAnnotation {
+    #[serde(default)]
+    message: String,
+}
+
+/// Why a Run was cancelled, as far as can be told.
+pub(crate) struct Cancellation {
+    /// One of "user", "concurrency", or "timeout".
+    pub(crate) reason: &'static str,
+    /// Who cancelled it, if it was a person.
+    pub(crate) by: Option<String>,
+}
+
+/// GitHub doesn't say who or what cancelled a Run anywhere in the Run
+/// itself, but a cancelled Job carries an annotation explaining it. The Job
+/// ID doubles as the ID of its check run.
+pub(crate) async fn retrieve_cancellation(
+    config: &Config,
+    client: &reqwest::Client,
+    job_id: u64,
+) -> Result<Option<Cancellation>, GitHubProblem> {
+    info!("List Annotations for job {}", job_id);
+    let url = format!(
+        "{}/repos/{}/{}/check-runs/{}/annotations",
+        config.api_base(),
+        config.owner,
+        config.repository,
+        job_id
+    );
+
+    let annotations: Vec<CheckAnnotation> = retrieve_json(client, url).await?;
+
+    Ok(annotations
+        .iter()
+        .find_map(|annotation| classify_cancellation(&annotation.message)))
+}
+
+fn classify_cancellation(message: &str) -> Option<Cancellation> {
+    if let Some((_, rest)) = message.split_once("canceled by @") {
+        let login = rest
+            .trim_end()
+            .trim_end_matches('.')
+            .to_string();
+        return Some(Cancellation {
+            reason: "user",
+            by: Some(login),
+        });
+    }
+
+    if message.contains("higher priority waiting request") {
+        return Some(Cancellation {
+            reason: "concurrency",
+            by: None,
+        });
+    }
+
+    if message.contains("exceeded the maximum execution time") {
+        return Some(Cancellation {
+            reason: "timeout",
+            by: None,
+        });
+    }
+
+    None
+}
+
+pub(crate) async fn retrieve_run_deployments(
+    config: &Config,
+    client: &reqwest::Client,
+    run: &WorkflowRun,
+) -> Result<Vec<RunDeployment>, GitHubProblem> {
+    info!("List Deployments for commit {}", run.head_sha);
+    let url = format!(
+        "{}/repos/{}/{}/deployments?sha={}&per_page=100",
+        config.api_base(),
+        config.owner,
+        config.repository,
+        run.head_sha
+    );
+
+    let deployments: Vec<Deployment> = retrieve_json(client, url).await?;
+
+    let mut result = Vec::new();
+
+    for deployment in deployments {
+        let url = format!(
+            "{}/repos/{}/{}/deployments/{}/statuses?per_page=100",
+            config.api_base(),
+            config.owner,
+            config.repository,
+            deployment.id
+        );
+
+        let mut statuses: Vec<DeploymentStatus> = retrieve_json(client, url).await?;
+
+        // the same commit can be deployed by many Runs; only keep the
+        // deployments that this Run was responsible for.
+        if !statuses
+            .iter()
+            .any(|status| status.is_from_run(run.run_id))
+        {
+            continue;
+        }
+
+        // GitHub returns statuses newest first
+        statuses.sort_by_key(|status| status.created_at);
+
+        result.push(RunDeployment {
+            deployment,
+            statuses,
+        });
+    }
+
+    Ok(result)
+}
+
+// When a job targets an environment with required reviewers the Run sits in
+// the "waiting" status until someone approves it. The approvals endpoint
+// tells us who resolved each wait (but not when; for that we rely on the
+// "waiting" status of the corresponding Deployment), while the pending
+// deployments endpoint describes waits that are still outstanding.
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ApprovalEnvironment {
+    pub(crate) name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct DeploymentApproval {
+    pub(crate) state: String, // approved or rejected
+    pub(crate) user: WorkflowActor,
+    pub(crate) environments: Vec<ApprovalEnvironment>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct PendingDeployment {
+    pub(crate) environment: ApprovalEnvironment,
+    #[serde(with = "rfc3339::option", default)]
+    pub(crate) wait_timer_started_at: Option<OffsetDateTime>,
+}
+
+/// A file uploaded by a Run with actions/upload-artifact.
+#[derive(Debug, Deserialize)]
+pub(crate) struct Artifact {
+    pub(crate) id: u64,
+    pub(crate) name: String,
+    pub(crate) size_in_bytes: u64,
+    pub(crate) archive_download_url: String,
+    #[serde(default)]
+    pub(crate) expired: bool,
+    #[serde(with = "rfc3339")]
+    pub(crate) created_at: OffsetDateTime,
+}
+
+#[derive(Deserialize)]
+struct ResponseArtifacts {
+    artifacts: Vec<Artifact>,
+}
+
+pub(crate) async fn retrieve_run_artifacts(
+    config: &Config,
+    client: &reqwest::Client,
+    run: &WorkflowRun,
+) -> Result<Vec<Artifact>, GitHubProblem> {
+    info!("List Artifacts of Run {}", run.run_id);
+    let url = format!(
+        "{}/repos/{}/{}/actions/runs/{}/artifacts?per_page=100",
+        config.api_base(),
+        config.owner,
+        config.repository,
+        run.run_id
+    );
+
+    let response: ResponseArtifacts = retrieve_json(client, url).await?;
+
+    Ok(response.artifacts)
+}
+
+/// Download an Artifact, which GitHub serves as a zip archive of the files
+/// that were uploaded.
+pub(crate) async fn retrieve_artifact_archive(
+    client: &reqwest::Client,
+    artifact: &Artifact,
+) -> Result<Vec<u8>, GitHubProblem> {
+    info!("Download Artifact {} ({})", artifact.id, artifact.name);
+
+    let url = &artifact.archive_download_url;
+    debug!(?url);
+
+    // as with logs, the API redirects to where the archive is actually
+    // stored, which the client follows for us.
+    let response = send_request(client, url).await?;
+
+    let status = response.status();
+
+    if status != StatusCode::OK {
+        warn!("{}", status);
+        return Err(GitHubProblem::ApiError(status));
+    }
+
+    let bytes = response
+        .bytes()
+        .await?;
+
+    Ok(bytes.to_vec())
+}
+
+/// Retrieve a single Run, as of the given attempt or otherwise its latest.
+/// Events about individual Jobs say which Run they belong to but not which
+/// workflow, so this is where that comes from.
+pub(crate) async fn retrieve_run(
+    client: &reqwest::Client,
+    host: &str,
+    owner: &str,
+    repository: &str,
+    run_id: u64,
+    attempt: Option<u64>,
+) -> Result<WorkflowRun, GitHubProblem> {
+    info!("Retrieve Run {}", run_id);
+    let mut url = format!(
+        "{}/repos/{}/{}/actions/runs/{}",
+        api_base(host),
+        owner,
+        repository,
+        run_id
+    );
+
+    if let Some(attempt) = attempt {
+        url.push_str(&format!("/attempts/{}", attempt));
+    }
+
+    let mut value: Value = retrieve_json(client, url).await?;
+    forge::adapt_run(host, &mut value);
+
+    Ok(decode_run(value)?)
+}
+
+pub(crate) async fn retrieve_run_approvals(
+    config: &Config,
+    client: &reqwest::Client,
+    run: &WorkflowRun,
+) -> Result<Vec<DeploymentApproval>, GitHubProblem> {
+    info!("List Approvals for Run {}", run.run_id);
+    let url = format!(
+        "{}/repos/{}/{}/actions/runs/{}/approvals",
+        config.api_base(),
+        config.owner,
+        config.repository,
+        run.run_id
+    );
+
+    retrieve_json(client, url).await
+}
+
+pub(crate) async fn retrieve_pending_deployments(
+    config: &Config,
+    client: &reqwest::Client,
+    run: &WorkflowRun,
+) -> Result<Vec<PendingDeployment>, GitHubProblem> {
+    info!("List Pending Deployments for Run {}", run.run_id);
+    let url = format!(
+        "{}/repos/{}/{}/actions/runs/{}/pending_deployments",
+        config.api_base(),
+        config.owner,
+        config.repository,
+        run.run_id
+    );
+
+    retrieve_json(client, url).await
+}
+
+/// How many lines leading up to an error message are kept to go with it.
+const CONTEXT_LINES: usize = 5;
+
+/// Lines longer than this (a minified bundle echoed to the log, say) are
+/// truncated, so that one line can't take an unbounded amount of memory.
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// The first error message found in a Job's log, along with the lines
+/// immediately before it.
+#[derive(Debug)]
+pub(crate) struct LogExcerpt {
+    pub(crate) message: String,
+    pub(crate) context: Vec<String>,
+}
+
+/// A Step's process exiting unsuccessfully, which GitHub notes in the log
+/// as the Step finishes.
+#[derive(Debug)]
+pub(crate) struct ProcessExit {
+    pub(crate) at: OffsetDateTime,
+    pub(crate) code: i64,
+}
+
+/// What was learned from the log of a Job: for a failed Job, the first
+/// error message in it and the exit codes of the Steps whose processes
+/// failed; and the caches restored and groups the output was divided into.
+#[derive(Debug, Default)]
+pub(crate) struct JobLog {
+    pub(crate) excerpt: Option<LogExcerpt>,
+    pub(crate) exits: Vec<ProcessExit>,
+    pub(crate) outline: LogOutline,
+}
+
+/// Read the log of a Job once, gathering whichever of its outline (the
+/// caches and groups) and its explanation of a failure are asked for. Logs
+/// can be large, and both are wanted of a failing Job when --cache-stats or
+/// --log-groups is given, so they are looked for in the same pass.
+pub(crate) async fn retrieve_job_log(
+    config: &Config,
+    client: &reqwest::Client,
+    job_id: u64,
+    outline: bool,
+    failure: bool,
+) -> Result<JobLog, GitHubProblem> {
+    let mut outline = outline.then(OutlineScan::default);
+    let mut failure = failure.then(FailureScan::default);
+
+    scan_job_log(config, client, job_id, |line| {
+        let Some((at, message)) = line.split_once(' ') else {
+            return ControlFlow::Continue(());
+        };
+        let at = OffsetDateTime::parse(at, &format_description::well_known::Rfc3339).ok();
+
+        if let Some(failure) = &mut failure {
+            failure.visit(at, message);
+        }
+        if let Some(outline) = &mut outline
+            && let Some(at) = at
+        {
+            outline.visit(at, message);
+        }
+
+        ControlFlow::Continue(())
+    })
+    .await?;
+
+    let mut found = JobLog::default();
+
+    if let Some(failure) = failure {
+        found.excerpt = failure.excerpt;
+        found.exits = failure.exits;
+    }
+    if let Some(outline) = outline {
+        found.outline = outline.finish();
+    }
+
+    debug!(?found);
+    Ok(found)
+}
+
+/// Looking through a log for the first error message and the lines before
+/// it, and for the exit codes of failing Steps.
+#[derive(Default)]
+struct FailureScan {
+    // the lines before the current one, oldest first; only the last few are
+    // ever held, however long the log is.
+    context: VecDeque<String>,
+    excerpt: Option<LogExcerpt>,
+    exits: Vec<ProcessExit>,
+}
+
+impl FailureScan {
+    fn visit(&mut self, at: Option<OffsetDateTime>, message: &str) {
+        // the exit code of the failing Step comes after its error messages,
+        // so the log is still read after the first error is found.
+        if let Some(code) = exit_code(message)
+            && let Some(at) = at
+        {
+            self.exits
+                .push(ProcessExit { at, code });
+        }
+
+        if self
+            .excerpt
+            .is_some()
+        {
+            return;
+        }
+
+        // only the few lines kept with the error are redacted; doing so for
+        // every line of a long log, nearly all of them thrown away, is costly.
+        if is_error_message(message) {
+            self.excerpt = Some(LogExcerpt {
+                message: redaction::redact(message),
+                context: self
+                    .context
+                    .drain(..)
+                    .map(|line| redaction::redact(&line))
+                    .collect(),
+            });
+            return;
+        }
+
+        // the oldest line's buffer is reused once there are enough of them
+        let mut line = if self
+            .context
+            .len()
+            == CONTEXT_LINES
+        {
+            self.context
+                .pop_front()
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+        line.clear();
+        line.push_str(message);
+        self.context
+            .push_back(line);
+    }
+}
+
+/// The exit code from a line like "##[error]Process completed with exit code
+/// 1.", as written when a Step's process fails.
+fn exit_code(message: &str) -> Option<i64> {
+    message
+        .strip_prefix("##[error]")
+        .unwrap_or(message)
+        .strip_prefix("Process completed with exit code ")?
+        .trim_end_matches('.')
+        .parse()
+        .ok()
+}
+
+/// Retrieve the log of a Job, handing each line to the given function as it
+/// arrives rather than holding the whole log in memory. Logs of long Jobs can
+/// run to hundreds of megabytes. If the function returns Break the rest of
+/// the log isn't retrieved.
+pub(crate) async fn scan_job_log(
+    config: &Config,
+    client: &reqwest::Client,
+    job_id: u64,
+    mut visit: impl FnMut(&str) -> ControlFlow<()>,
+) -> Result<(), GitHubProblem> {
+    info!("Retrieve logs for jobs {}", job_id);
+    let url = format!(
+        "{}/repos/{}/{}/actions/jobs/{}/logs",
+        config.api_base(),
+        config.owner,
+        config.repository,
+        job_id
+    );
+
+    debug!(?url);
+
+    let mut response = send_request(client, &url).await?;
+
+    // astonishingly, the request crate follows redirections for you by
+    // default. So we don't need to worry about the 302 Found that the GitHub
+    // API documentation describes at length, and instead just let the client
+    // follow the redirect (and there appears to be more than one).
+
+    let status = response.status();
+
+    if status != StatusCode::OK {
+        warn!("{}", status);
 
-    headers.insert("User-Agent", format!("action-hero/{}", VERSION).parse()?);
+        let body = response
+            .text()
+            .await?;
+        debug!(body);
+
+        return Err(GitHubProblem::ApiError(status));
+    }
+
+    // a chunk can end part way through a line (or even a character), so the
+    // start of a line is held until the rest of it arrives.
+    let mut partial: Vec<u8> = Vec::new();
+
+    while let Some(chunk) = response
+        .chunk()
+        .await?
+    {
+        let mut rest = &chunk[..];
+
+        while let Some(index) = rest
+            .iter()
+            .position(|b| *b == b'\n')
+        {
+            append_bounded(&mut partial, &rest[..index]);
+            rest = &rest[index + 1..];
+
+            let line = String::from_utf8_lossy(&partial);
+            let flow = visit(line.trim_end_matches('\r'));
+            partial.clear();
+
+            if flow.is_break() {
+                return Ok(());
+            }
+        }
+
+        append_bounded(&mut partial, rest);
+    }
+
+    if !partial.is_empty() {
+        let line = String::from_utf8_lossy(&partial);
+        let _ = visit(line.trim_end_matches('\r'));
+    }
+
+    Ok(())
+}
+
+fn append_bounded(line: &mut Vec<u8>, bytes: &[u8]) {
+    let room = MAX_LINE_LENGTH.saturating_sub(line.len());
+    line.extend_from_slice(
+        &bytes[..bytes
+            .len()
+            .min(room)],
+    );
+}
+
+/// Each line of a log starts with a timestamp, which this trims off.
+pub(crate) fn log_message(line: &str) -> Option<&str> {
+    line.split_once(' ')
+        .map(|(_, message)| message)
+}
+
+/// Whether a line of a log is an error message, as far as we're concerned.
+pub(crate) fn is_error_message(message: &str) -> bool {
+    // see if an error marker is present
+    message
+        .to_lowercase()
+        .contains("error:")
+}
+
+/// What a Step restoring a cache reported in the log: actions/cache itself,
+/// or one of the setup-* actions with caching turned on, all of which use the
+/// same toolkit and so describe the outcome the same way.
+#[derive(Debug)]
+pub(crate) struct CacheReport {
+    /// When the outcome was logged, which places it within a Step.
+    pub(crate) at: OffsetDateTime,
+    pub(crate) hit: bool,
+    pub(crate) key: String,
+    pub(crate) restored_bytes: Option<i64>,
+}
+
+/// A section of a log marked with `::group::`, or by the header GitHub
+/// writes at the start of each Step.
+#[derive(Debug)]
+pub(crate) struct LogGroup {
+    pub(crate) title: String,
+    pub(crate) start: OffsetDateTime,
+    pub(crate) finish: OffsetDateTime,
+}
+
+/// What can be learned about the Steps of a Job from its log, beyond the
+/// error messages of a failing one.
+#[derive(Debug, Default)]
+pub(crate) struct LogOutline {
+    pub(crate) caches: Vec<CacheReport>,
+    pub(crate) groups: Vec<LogGroup>,
+}
+
+/// Looking through a log for caches being restored (or not), and for the
+/// groups its output was divided into.
+#[derive(Default)]
+struct OutlineScan {
+    outline: LogOutline,
+
+    // the size of the cache is logged as it is downloaded, before the line
+    // saying which key it was restored from.
+    size: Option<i64>,
+
+    // the group currently open, and when the last line was written. GitHub
+    // doesn't nest groups; starting one ends any that was open.
+    open: Option<(String, OffsetDateTime)>,
+    latest: Option<OffsetDateTime>,
+}
+
+impl OutlineScan {
+    fn visit(&mut self, at: OffsetDateTime, message: &str) {
+        if let Some(title) = message.strip_prefix("##[group]") {
+            self.size = None;
+            if let Some((title, start)) = self
+                .open
+                .take()
+            {
+                self.outline
+                    .groups
+                    .push(LogGroup {
+                        title,
+                        start,
+                        finish: self
+                            .latest
+                            .unwrap_or(start),
+                    });
+            }
+            self.open = Some((redaction::redact(title.trim()), at));
+        } else if message.starts_with("##[endgroup]") {
+            if let Some((title, start)) = self
+                .open
+                .take()
+            {
+                self.outline
+                    .groups
+                    .push(LogGroup {
+                        title,
+                        start,
+                        finish: at,
+                    });
+            }
+        } else if let Some(value) = message.strip_prefix("Cache Size: ") {
+            // "~14 MB (14582793 B)"
+            self.size = value
+                .rsplit_once('(')
+                .and_then(|(_, bytes)| bytes.strip_suffix(" B)"))
+                .and_then(|bytes| {
+                    bytes
+                        .parse::<i64>()
+                        .ok()
+                });
+        } else {
+            let outcome = if let Some(key) = message.strip_prefix("Cache restored from key: ") {
+                Some((true, key))
+            } else {
+                message
+                    .strip_prefix("Cache not found for input keys: ")
+                    .map(|keys| {
+                        // the primary key comes first, then the restore keys
+                        let key = keys
+                            .split(", ")
+                            .next()
+                            .unwrap_or(keys);
+                        (false, key)
+                    })
+            };
+
+            if let Some((hit, key)) = outcome {
+                self.outline
+                    .caches
+                    .push(CacheReport {
+                        at,
+                        hit,
+                        key: redaction::redact(key.trim()),
+                        restored_bytes: if hit {
+                            self.size
+                                .take()
+                        } else {
+                            None
+                        },
+                    });
+            }
+        }
+
+        self.latest = Some(at);
+    }
+
+    fn finish(mut self) -> LogOutline {
+        if let Some((title, start)) = self.open {
+            self.outline
+                .groups
+                .push(LogGroup {
+                    title,
+                    start,
+                    finish: self
+                        .latest
+                        .unwrap_or(start),
+                });
+        }
+
+        self.outline
+    }
+}
+
+/// Make a cheap authenticated request to check the token is actually
+/// usable. The rate limit endpoint doesn't count against the rate limit, and
+/// its response headers tell us about the token's expiry (for fine-grained
+/// tokens) and granted scopes (for classic tokens).
+pub(crate) async fn validate_api_token(client: &reqwest::Client, host: &str) -> Result<()> {
+    let forge = forge::forge_of(host);
+    let url = format!("{}{}", api_base(host), forge.token_check_path());
+
+    debug!(?url);
+
+    let response = send_request(client, &url).await?;
+
+    let status = response.status();
+
+    if status == StatusCode::UNAUTHORIZED {
+        return Err(anyhow!(
+            "{} rejected the supplied token (401 Unauthorized); check that it is valid and has not expired",
+            forge.name()
+        ));
+    }
+
+    if status != StatusCode::OK {
+        warn!("Unable to validate token: {}", status);
+        return Ok(());
+    }
+
+    let headers = response.headers();
+
+    if let Some(scopes) = headers
+        .get("x-oauth-scopes")
+        .and_then(|value| {
+            value
+                .to_str()
+                .ok()
+        })
+    {
+        info!("Token scopes: {}", scopes);
+    }
+
+    if let Some(expiry) = headers
+        .get("github-authentication-token-expiration")
+        .and_then(|value| {
+            value
+                .to_str()
+                .ok()
+        })
+    {
+        match parse_token_expiry(expiry) {
+            Some(expiry) => {
+                let remaining = expiry - OffsetDateTime::now_utc();
+                if remaining < Duration::days(7) {
+                    warn!("Token expires at {} (in {})", expiry, remaining);
+                } else {
+                    info!("Token expires at {}", expiry);
+                }
+            }
+            None => info!("Token expires at {}", expiry),
+        }
+    }
+
+    Ok(())
+}
+
+// GitHub formats this header as "2023-06-30 08:00:00 UTC" or with a numeric
+// offset as in "2023-06-30 08:00:00 -0700".
+fn parse_token_expiry(value: &str) -> Option<OffsetDateTime> {
+    let (datetime, zone) = value.rsplit_once(' ')?;
+
+    let format = format_description::parse("[year]-[month]-[day] [hour]:[minute]:[second]").ok()?;
+    let datetime = PrimitiveDateTime::parse(datetime, &format).ok()?;
+
+    let offset = if zone == "UTC" {
+        UtcOffset::UTC
+    } else {
+        let format =
+            format_description::parse("[offset_hour sign:mandatory][offset_minute]").ok()?;
+        UtcOffset::parse(zone, &format).ok()?
+    };
+
+    Some(datetime.assume_offset(offset))
+}
 
-    headers.insert("X-GitHub-Api-Version", "2022-11-28".parse()?);
+// Webhooks, so that `hero listen` can be sent events without someone having
+// to click through the settings of every repository.
+
+/// The events a webhook needs to send for everything we do with them.
+pub(crate) const HOOK_EVENTS: &[&str] = &["workflow_run", "workflow_job", "workflow_dispatch"];
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Hook {
+    pub(crate) id: u64,
+    #[serde(default)]
+    pub(crate) config: HookConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct HookConfig {
+    #[serde(default)]
+    pub(crate) url: Option<String>,
+}
+
+/// The webhooks of a repository, or of a whole organization if no
+/// repository is given.
+pub(crate) fn hooks_url(host: &str, owner: &str, repository: Option<&str>) -> String {
+    match repository {
+        Some(repository) => format!("{}/repos/{}/{}/hooks", api_base(host), owner, repository),
+        None => format!("{}/orgs/{}/hooks", api_base(host), owner),
+    }
+}
+
+pub(crate) async fn retrieve_hooks(
+    client: &reqwest::Client,
+    url: &str,
+) -> Result<Vec<Hook>, GitHubProblem> {
+    info!("List Webhooks");
+    retrieve_json(client, url.to_string()).await
+}
+
+/// Create a webhook delivering to the given URL, or if there is an existing
+/// one then update it to match. Returns the ID of the webhook.
+pub(crate) async fn install_hook(
+    client: &reqwest::Client,
+    url: &str,
+    existing: Option<u64>,
+    target: &str,
+    secret: Option<&str>,
+) -> Result<u64> {
+    let mut config = serde_json::json!({
+        "url": target,
+        "content_type": "json",
+        "insecure_ssl": "0",
+    });
+    if let Some(secret) = secret {
+        config["secret"] = Value::from(secret);
+    }
+
+    let mut body = serde_json::json!({
+        "active": true,
+        "events": HOOK_EVENTS,
+        "config": config,
+    });
+
+    let request = match existing {
+        Some(id) => {
+            info!("Update Webhook {}", id);
+            client.patch(format!("{}/{}", url, id))
+        }
+        None => {
+            info!("Create Webhook");
+            body["name"] = Value::from("web");
+            client.post(url)
+        }
+    };
+
+    let response = request
+        .json(&body)
+        .send()
+        .await?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let text = response
+            .text()
+            .await
+            .unwrap_or_default();
+        return Err(anyhow!(
+            "GitHub refused to install webhook ({}): {}",
+            status,
+            text
+        ));
+    }
+
+    let hook: Hook = response
+        .json()
+        .await?;
+
+    Ok(hook.id)
+}
+
+/// A record of GitHub attempting to deliver an event to a webhook. A status
+/// code of 0 means the listener couldn't be reached at all.
+#[derive(Debug, Deserialize)]
+pub(crate) struct HookDelivery {
+    pub(crate) id: u64,
+    pub(crate) guid: String,
+    #[serde(with = "rfc3339")]
+    pub(crate) delivered_at: OffsetDateTime,
+    pub(crate) status_code: u16,
+    pub(crate) event: String,
+}
+
+impl HookDelivery {
+    pub(crate) fn succeeded(&self) -> bool {
+        (200..300).contains(&self.status_code)
+    }
+}
+
+#[derive(Deserialize)]
+struct Meta {
+    #[serde(default)]
+    hooks: Vec<String>,
+}
+
+/// The address ranges, in CIDR notation, that the given host sends webhook
+/// deliveries from.
+pub(crate) async fn retrieve_hook_ranges(
+    client: &reqwest::Client,
+    host: &str,
+) -> Result<Vec<String>, GitHubProblem> {
+    info!("Get webhook address ranges for {}", host);
+    let url = format!("{}/meta", api_base(host));
+
+    let meta: Meta = retrieve_json(client, url).await?;
+
+    Ok(meta.hooks)
+}
+
+/// The most recent deliveries made by the webhook at the given URL (as
+/// formed by hooks_url() with the webhook's ID appended).
+pub(crate) async fn retrieve_hook_deliveries(
+    client: &reqwest::Client,
+    hook_url: &str,
+) -> Result<Vec<HookDelivery>, GitHubProblem> {
+    info!("List Deliveries");
+    let url = format!("{}/deliveries?per_page=100", hook_url);
+
+    retrieve_json(client, url).await
+}
+
+/// Ask GitHub to send a delivery again.
+pub(crate) async fn request_redelivery(
+    client: &reqwest::Client,
+    hook_url: &str,
+    delivery: &HookDelivery,
+) -> Result<()> {
+    info!("Redeliver {}", delivery.guid);
+    let url = format!("{}/deliveries/{}/attempts", hook_url, delivery.id);
+
+    let response = client
+        .post(url)
+        .send()
+        .await?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(anyhow!(
+            "GitHub refused to redeliver {} ({})",
+            delivery.guid,
+            status
+        ));
+    }
+
+    Ok(())
+}
+
+/// Setup a client for accessing the repository described by the given
+/// Config, using the token appropriate for its owner.
+pub(crate) fn setup_api_client(config: &Config) -> Result<reqwest::Client> {
+    let token = get_api_token(&config.host, &config.owner).ok_or_else(|| {
+        anyhow!(
+            "No GitHub token available for owner {} on {}",
+            config.owner,
+            config.host
+        )
+    })?;
+
+    build_api_client(&config.host, token)
+}
+
+/// How connections to the GitHub API are made and kept. When backfilling
+/// many Runs the cost of setting up new TCP and TLS connections adds up, and
+/// GitHub limits how many connections a client may have open at once.
+#[derive(Debug, Default)]
+pub(crate) struct ClientOptions {
+    /// Offer HTTP/2 when connecting, rather than only HTTP/1.1.
+    pub(crate) http2: bool,
+
+    /// How long an unused connection is kept open.
+    pub(crate) pool_idle_timeout: Option<std::time::Duration>,
+
+    /// The most unused connections to each host kept open for reuse.
+    pub(crate) max_idle: Option<usize>,
+
+    /// Appended to our User-Agent, identifying this deployment to GitHub.
+    pub(crate) user_agent_suffix: Option<String>,
+}
+
+static CLIENT_OPTIONS: OnceLock<ClientOptions> = OnceLock::new();
+
+pub(crate) fn set_client_options(options: ClientOptions) {
+    CLIENT_OPTIONS
+        .set(options)
+        .unwrap();
+}
+
+/// Clients by the token they carry (and the kind of forge they talk to). A
+/// Client holds its pool of connections, so it is built once for each token
+/// and then reused, rather than every Run starting over with connections of
+/// its own.
+static CLIENTS: Mutex<Option<HashMap<(Forge, String), reqwest::Client>>> = Mutex::new(None);
+
+pub(crate) fn build_api_client(host: &str, token: &str) -> Result<reqwest::Client> {
+    let forge = forge::forge_of(host);
+
+    let mut clients = CLIENTS
+        .lock()
+        .unwrap();
+    let clients = clients.get_or_insert_with(HashMap::new);
+
+    let key = (forge, token.to_string());
+    if let Some(client) = clients.get(&key) {
+        return Ok(client.clone());
+    }
+
+    let client = new_api_client(forge, token)?;
+    clients.insert(key, client.clone());
+
+    Ok(client)
+}
+
+fn new_api_client(forge: Forge, token: &str) -> Result<reqwest::Client> {
+    // Initialize a request Client as we will be making many requests of
+    // the GitHub API.
+    let mut headers = HeaderMap::new();
+
+    // .parse() is needed here and below to get from &str to HeaderValue.
+
+    let mut auth: HeaderValue = forge
+        .authorization(token)
+        .parse()?;
+    auth.set_sensitive(true);
+    headers.insert("Authorization", auth);
+
+    match forge {
+        Forge::GitHub => {
+            headers.insert("Accept", "application/vnd.github+json".parse()?);
+            headers.insert("X-GitHub-Api-Version", API_VERSION.parse()?);
+        }
+        Forge::Forgejo => {
+            headers.insert("Accept", "application/json".parse()?);
+        }
+    }
+
+    let options = CLIENT_OPTIONS.get();
+
+    let mut agent = format!("action-hero/{}", VERSION);
+    if let Some(suffix) = options.and_then(|options| {
+        options
+            .user_agent_suffix
+            .as_deref()
+    }) {
+        agent.push(' ');
+        agent.push_str(suffix);
+    }
+    headers.insert(
+        "User-Agent",
+        agent
+            .parse()
+            .map_err(|_| anyhow!("Invalid User-Agent \"{}\"", agent))?,
+    );
+
+    let mut builder = reqwest::Client::builder().default_headers(headers);
+
+    // HTTP/2 is negotiated as each TLS connection is made, so hosts which
+    // don't speak it (or plain HTTP, as a stand-in for the API might be)
+    // carry on with HTTP/1.1. That includes the storage logs and artifacts
+    // are redirected to, which we don't know in advance.
+    if !options.is_some_and(|options| options.http2) {
+        builder = builder.http1_only();
+    }
+
+    if let Some(options) = options {
+        if let Some(timeout) = options.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(timeout);
+        }
+        if let Some(max) = options.max_idle {
+            builder = builder.pool_max_idle_per_host(max);
+        }
+    }
 
-    let client = reqwest::Client::builder()
-        .default_headers(headers)
-        .build()?;
+    let client = builder.build()?;
 
     Ok(client)
 }
@@ -1,55 +1,602 @@
+//! A record of which Runs have been sent, kept on the filesystem. This is
+//! what allows `hero query` to be run repeatedly, and it is shared with any
+//! listener given the same directory so that a Run arriving by webhook isn't
+//! sent again by a later query, or vice versa.
+
 use anyhow::{Result, anyhow};
+use serde::Serialize;
 use std::{
     self,
+    collections::BTreeSet,
+    fs::{File, OpenOptions, TryLockError},
+    io::{Read, Seek, Write},
     path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::Duration,
 };
-use tracing::{debug, info};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use tracing::{debug, info, warn};
+
+use crate::github::{Config, GITHUB_HOST, WorkflowRun};
 
-use crate::github::{Config, WorkflowRun};
+static RECORD_DIRECTORY: OnceLock<String> = OnceLock::new();
 
-pub(crate) fn ensure_record_directory(prefix: &str) -> Result<()> {
+/// Where records are kept if not told otherwise: "record" under the current
+/// directory, as it always has been. On Windows, where nothing is run from a
+/// working directory set aside for it, they are kept with the user's
+/// application data instead.
+pub(crate) fn default_record_directory() -> String {
+    if cfg!(windows)
+        && let Ok(appdata) = std::env::var("APPDATA")
+    {
+        return Path::new(&appdata)
+            .join("hero")
+            .join("record")
+            .to_string_lossy()
+            .to_string();
+    }
+
+    "record".to_string()
+}
+
+pub(crate) fn set_record_directory(prefix: &str) -> Result<()> {
     let path = Path::new(prefix);
     if !path.exists() {
-        std::fs::create_dir(path)?;
+        std::fs::create_dir_all(path)?;
     }
+
+    RECORD_DIRECTORY
+        .set(prefix.to_string())
+        .unwrap();
+
     Ok(())
 }
 
-pub(crate) fn form_record_filename(prefix: &str, config: &Config, run: &WorkflowRun) -> PathBuf {
-    let id = format!("{}", run.run_id);
+// Held for as long as the program runs; the lock goes away with the process,
+// however it exits.
+static INSTANCE: OnceLock<File> = OnceLock::new();
+
+/// Ensure this is the only process of its kind using the record directory.
+/// Two queries run from cron against the same directory (because the
+/// previous one is still going when the next starts) would otherwise race
+/// each other; the claims on individual Runs stop them sending the same Run
+/// twice, but not from making a mess of the cursor. A listener and a query
+/// sharing the directory is fine, so each kind of process has a lock of its
+/// own. The PID of the holder is written into the lock file so that the
+/// error can say who it is.
+pub(crate) fn lock_record_directory(kind: &str) -> Result<()> {
+    let prefix = RECORD_DIRECTORY.wait();
+    let path = Path::new(prefix).join(format!("{}.pid", kind));
+
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)?;
+
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            let mut holder = String::new();
+            let _ = file.read_to_string(&mut holder);
+            return Err(anyhow!(
+                "Another `hero {}` (PID {}) is already using the state directory {}",
+                kind,
+                holder.trim(),
+                prefix
+            ));
+        }
+        Err(TryLockError::Error(error)) => return Err(error.into()),
+    }
+
+    file.set_len(0)?;
+    file.rewind()?;
+    writeln!(file, "{}", std::process::id())?;
+
+    INSTANCE
+        .set(file)
+        .unwrap();
+
+    Ok(())
+}
+
+/// Whether a record is being kept. It always is when querying, but only when
+/// asked for when listening.
+pub(crate) fn is_enabled() -> bool {
+    RECORD_DIRECTORY
+        .get()
+        .is_some()
+}
+
+/// The directory in which the records for the workflow described by the
+/// Config are kept.
+fn form_record_directory(config: &Config) -> PathBuf {
+    let prefix = RECORD_DIRECTORY.wait();
+
+    // records for repositories on a GitHub Enterprise Server are kept apart
+    // from those on github.com, whose layout is left as it always was.
+    let mut directory = PathBuf::from(prefix);
+    if config.host != GITHUB_HOST {
+        directory.push(host_directory(&config.host));
+    }
+
+    directory
+        .join(&config.owner)
+        .join(&config.repository)
+        .join(&config.workflow)
+}
+
+// A host given with its port has a colon in it, which Windows doesn't allow
+// in the name of a directory (or of a file, which the queue names after the
+// host too).
+pub(crate) fn host_directory(host: &str) -> String {
+    if cfg!(windows) {
+        host.replace(':', "_")
+    } else {
+        host.to_string()
+    }
+}
+
+// and back again, for reading the records. Hostnames can't have underscores
+// in them, so one followed by nothing but digits was the colon before a port.
+fn host_from_directory(name: &str) -> String {
+    if cfg!(windows)
+        && let Some((host, port)) = name.rsplit_once('_')
+        && !port.is_empty()
+        && port
+            .chars()
+            .all(|c| c.is_ascii_digit())
+    {
+        format!("{}:{}", host, port)
+    } else {
+        name.to_string()
+    }
+}
+
+pub(crate) fn form_record_filename(config: &Config, run: &WorkflowRun) -> PathBuf {
+    // each attempt of a Run is a trace of its own and so is recorded
+    // separately. The first attempt is recorded under the Run ID alone, as
+    // it always was.
+    let id = if run.run_attempt > 1 {
+        format!("{}.{}", run.run_id, run.run_attempt)
+    } else {
+        format!("{}", run.run_id)
+    };
+
+    form_record_directory(config).join(id)
+}
+
+// The cursor for a workflow is the creation time of the earliest Run which
+// still might need processing. Runs created before it have all been dealt
+// with, so a query need only ask GitHub for those created since.
+
+const CURSOR: &str = "cursor";
+
+pub(crate) async fn read_cursor(config: &Config) -> Result<Option<OffsetDateTime>> {
+    let path = form_record_directory(config).join(CURSOR);
+
+    let contents = match tokio::fs::read_to_string(&path).await {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error.into()),
+    };
+
+    let cursor = OffsetDateTime::parse(contents.trim(), &Rfc3339)
+        .map_err(|error| anyhow!("Invalid cursor in {}: {}", path.display(), error))?;
+
+    Ok(Some(cursor))
+}
+
+pub(crate) async fn write_cursor(config: &Config, cursor: OffsetDateTime) -> Result<()> {
+    let directory = form_record_directory(config);
+    ensure_directory(&directory).await?;
+
+    let contents = format!("{}\n", cursor.format(&Rfc3339)?);
+
+    let _guard = WRITES
+        .lock()
+        .await;
+    tokio::fs::write(directory.join(CURSOR), contents.as_bytes()).await?;
+
+    Ok(())
+}
+
+// Every Run of a workflow is recorded in the same directory, so having
+// created it once there's no need to go to the filesystem to check it
+// exists for each Run after that.
+static CREATED: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+async fn ensure_directory(directory: &Path) -> Result<()> {
+    if CREATED
+        .lock()
+        .unwrap()
+        .contains(directory)
+    {
+        return Ok(());
+    }
+
+    tokio::fs::create_dir_all(directory).await?;
+
+    CREATED
+        .lock()
+        .unwrap()
+        .insert(directory.to_path_buf());
+
+    Ok(())
+}
+
+// Writes to the record are made one at a time, even when several Runs are
+// being processed at once.
+static WRITES: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+// A claim older than this was left behind by a process which died while
+// processing the Run; nothing takes anywhere near this long.
+const STALE_CLAIM: Duration = Duration::from_secs(3600);
+
+/// Held while a Run is being processed so that any other instance sharing
+/// the record directory leaves it alone. The claim is a lock file alongside
+/// where the record will be, removed when this is dropped.
+pub(crate) struct Claim {
+    lock: PathBuf,
+}
+
+impl Drop for Claim {
+    // dropping can't wait on anything, so the lock file is removed on the
+    // blocking pool rather than holding up the runtime here.
+    fn drop(&mut self) {
+        let lock = std::mem::take(&mut self.lock);
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn_blocking(move || std::fs::remove_file(lock));
+            }
+            Err(_) => {
+                let _ = std::fs::remove_file(lock);
+            }
+        }
+    }
+}
+
+/// Remove claims left behind by processes which died while holding them.
+pub(crate) fn collect_garbage() -> Result<()> {
+    let prefix = RECORD_DIRECTORY.wait();
+    remove_stale_claims(Path::new(prefix))
+}
+
+fn remove_stale_claims(directory: &Path) -> Result<()> {
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if entry
+            .file_type()?
+            .is_dir()
+        {
+            remove_stale_claims(&path)?;
+            continue;
+        }
 
-    let name = format!(
-        "{}/{}/{}/{}",
-        prefix, config.owner, config.repository, config.workflow
-    );
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "lock")
+            && entry
+                .metadata()?
+                .modified()?
+                .elapsed()
+                .unwrap_or_default()
+                > STALE_CLAIM
+        {
+            info!("Removing stale claim {}", path.display());
+            std::fs::remove_file(&path)?;
+        }
+    }
 
-    let directory = Path::new(&name);
-    let path = directory.join(id);
-    path
+    Ok(())
 }
 
-pub(crate) fn check_is_submitted(path: &Path) -> Result<bool> {
+/// Claim the Run for processing. Returns None if it has already been
+/// submitted, or if someone else is in the middle of doing so.
+pub(crate) async fn claim_run(path: &Path) -> Result<Option<Claim>> {
     let directory = path
         .parent()
         .ok_or(anyhow!("Could not get Path"))?;
 
     debug!(?path);
 
-    if !directory.exists() {
-        std::fs::create_dir_all(&directory)?;
+    ensure_directory(directory).await?;
+
+    if tokio::fs::try_exists(path).await? {
+        return Ok(None);
     }
 
-    let probe = path.exists();
-    Ok(probe)
+    let mut lock = path
+        .as_os_str()
+        .to_owned();
+    lock.push(".lock");
+    let lock = PathBuf::from(lock);
+
+    if let Ok(metadata) = tokio::fs::metadata(&lock).await
+        && metadata
+            .modified()?
+            .elapsed()
+            .unwrap_or_default()
+            > STALE_CLAIM
+    {
+        warn!("Removing stale claim {}", lock.display());
+        tokio::fs::remove_file(&lock).await?;
+    }
+
+    // creating the file only if it doesn't already exist is atomic, so only
+    // one of several contenders will succeed.
+    match tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&lock)
+        .await
+    {
+        Ok(_) => {}
+        Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => {
+            info!("Run is being processed elsewhere");
+            return Ok(None);
+        }
+        Err(error) => return Err(error.into()),
+    }
+
+    let claim = Claim { lock };
+
+    // the other party may have finished between our looking and claiming
+    if tokio::fs::try_exists(path).await? {
+        return Ok(None);
+    }
+
+    Ok(Some(claim))
 }
 
-pub(crate) fn mark_run_submitted(path: &Path, trace_id: String) -> Result<()> {
-    if !path.exists() {
-        // create empty file
-        info!("Recording Run completion");
-        let trace_id = format!("{}\n", trace_id);
-        std::fs::write(&path, trace_id.as_bytes())?;
+// The record for a Run is a file whose first line is the TraceId it was
+// sent as, whose second line is the Run's conclusion, and whose third line
+// (if it came in a webhook) is the GUID of the delivery. Records made by
+// earlier versions have fewer lines.
+
+fn form_record(first: &str, conclusion: &str, delivery: Option<&str>) -> String {
+    match delivery {
+        Some(delivery) => format!("{}\n{}\n{}\n", first, conclusion, delivery),
+        None => format!("{}\n{}\n", first, conclusion),
+    }
+}
+
+pub(crate) async fn mark_run_submitted(
+    path: &Path,
+    trace_id: String,
+    conclusion: &str,
+    delivery: Option<&str>,
+) -> Result<()> {
+    let contents = form_record(&trace_id, conclusion, delivery);
+    write_record(path, contents, "Recording Run completion").await
+}
+
+/// The marker recorded in place of a TraceId for Runs which were processed
+/// but deliberately not sent.
+pub(crate) const SUPPRESSED: &str = "suppressed";
+
+pub(crate) async fn mark_run_suppressed(
+    path: &Path,
+    conclusion: &str,
+    delivery: Option<&str>,
+) -> Result<()> {
+    let contents = form_record(SUPPRESSED, conclusion, delivery);
+    write_record(path, contents, "Recording Run suppressed").await
+}
+
+async fn write_record(path: &Path, contents: String, message: &str) -> Result<()> {
+    let _guard = WRITES
+        .lock()
+        .await;
+
+    if !tokio::fs::try_exists(path).await? {
+        info!("{}", message);
+        tokio::fs::write(path, contents.as_bytes()).await?;
     }
 
     Ok(())
 }
+
+/// Remove the record of a Run, so that it will be sent again. Returns
+/// whether there was a record to remove.
+pub(crate) async fn forget_run(path: &Path) -> Result<bool> {
+    let _guard = WRITES
+        .lock()
+        .await;
+
+    match tokio::fs::remove_file(path).await {
+        Ok(()) => {
+            info!("Forgetting Run record {}", path.display());
+            Ok(true)
+        }
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Describe whether the Run with the given record has been sent: "yes",
+/// "suppressed", or "no".
+pub(crate) fn read_record_state(path: &Path) -> &'static str {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => match contents
+            .lines()
+            .next()
+        {
+            Some(SUPPRESSED) => "suppressed",
+            _ => "yes",
+        },
+        Err(_) => "no",
+    }
+}
+
+/// The TraceId the Run with the given record was sent as, if it was sent.
+#[cfg(feature = "tui")]
+pub(crate) fn read_record_trace_id(path: &Path) -> Option<String> {
+    let contents = std::fs::read_to_string(path).ok()?;
+    let first = contents
+        .lines()
+        .next()?
+        .trim();
+
+    if first.is_empty() || first == SUPPRESSED {
+        return None;
+    }
+
+    Some(first.to_string())
+}
+
+/// A Run as recorded in the record directory.
+#[derive(Debug, Serialize)]
+pub struct Record {
+    pub host: String,
+    pub owner: String,
+    pub repository: String,
+    pub workflow: String,
+    pub run_id: u64,
+    pub run_attempt: u64,
+    pub conclusion: Option<String>,
+    pub trace_id: Option<String>,
+    pub suppressed: bool,
+    pub delivery: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub submitted_at: OffsetDateTime,
+}
+
+/// The record of which Runs have been sent, for those embedding the
+/// pipeline. While one is open, Runs submitted are recorded in it and those
+/// already recorded are skipped. Only one can be opened in a process.
+pub struct HistoryStore {
+    directory: String,
+}
+
+impl HistoryStore {
+    pub fn open(directory: &str) -> Result<HistoryStore> {
+        if is_enabled() {
+            return Err(anyhow!("A record directory has already been opened"));
+        }
+
+        set_record_directory(directory)?;
+
+        Ok(HistoryStore {
+            directory: directory.to_string(),
+        })
+    }
+
+    /// Whether the Run has been sent (or suppressed) already.
+    pub fn has_sent(&self, config: &Config, run: &WorkflowRun) -> bool {
+        read_record_state(&form_record_filename(config, run)) != "no"
+    }
+
+    /// Every Run recorded so far.
+    pub fn records(&self) -> Result<Vec<Record>> {
+        read_records(&self.directory)
+    }
+}
+
+/// Read every record in the given directory.
+pub(crate) fn read_records(prefix: &str) -> Result<Vec<Record>> {
+    let mut records = Vec::new();
+    let mut segments = Vec::new();
+    walk_records(Path::new(prefix), &mut segments, &mut records)?;
+    Ok(records)
+}
+
+// Records are laid out as owner/repository/workflow/run, with those for a
+// GitHub Enterprise Server having an extra level for its host in front.
+fn walk_records(
+    directory: &Path,
+    segments: &mut Vec<String>,
+    records: &mut Vec<Record>,
+) -> Result<()> {
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        let name = entry
+            .file_name()
+            .to_string_lossy()
+            .to_string();
+
+        if entry
+            .file_type()?
+            .is_dir()
+        {
+            segments.push(name);
+            walk_records(&entry.path(), segments, records)?;
+            segments.pop();
+            continue;
+        }
+
+        if let Some(record) = read_record(&entry.path(), &name, segments)? {
+            records.push(record);
+        }
+    }
+
+    Ok(())
+}
+
+fn read_record(path: &Path, name: &str, segments: &[String]) -> Result<Option<Record>> {
+    let (host, owner, repository, workflow) = match segments {
+        [owner, repository, workflow] => (GITHUB_HOST.to_string(), owner, repository, workflow),
+        [host, owner, repository, workflow] => {
+            (host_from_directory(host), owner, repository, workflow)
+        }
+        _ => return Ok(None),
+    };
+
+    // anything other than a record, such as a claim, is not of interest
+    let (run_id, run_attempt) = match name.split_once('.') {
+        Some((id, attempt)) => match (id.parse(), attempt.parse()) {
+            (Ok(id), Ok(attempt)) => (id, attempt),
+            _ => return Ok(None),
+        },
+        None => match name.parse() {
+            Ok(id) => (id, 1),
+            Err(_) => return Ok(None),
+        },
+    };
+
+    let contents = std::fs::read_to_string(path)?;
+    let mut lines = contents.lines();
+
+    let first = lines
+        .next()
+        .unwrap_or_default()
+        .trim();
+    let suppressed = first == SUPPRESSED;
+    let trace_id = if suppressed || first.is_empty() {
+        None
+    } else {
+        Some(first.to_string())
+    };
+
+    let conclusion = lines
+        .next()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string);
+
+    let delivery = lines
+        .next()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string);
+
+    let submitted_at = OffsetDateTime::from(std::fs::metadata(path)?.modified()?);
+
+    Ok(Some(Record {
+        host,
+        owner: owner.clone(),
+        repository: repository.clone(),
+        workflow: workflow.clone(),
+        run_id,
+        run_attempt,
+        conclusion,
+        trace_id,
+        suppressed,
+        delivery,
+        submitted_at,
+    }))
+}
@@ -0,0 +1,274 @@
+//! Test results, from JUnit XML reports uploaded by a Run as an Artifact.
+//! Almost every test framework can write this format, and the Artifact
+//! takes us from knowing which Step failed to knowing which tests did.
+
+use anyhow::Result;
+use regex::Regex;
+use std::io::{Cursor, Read};
+use time::OffsetDateTime;
+use tracing::{info, warn};
+
+use crate::github::{self, Artifact, Config, WorkflowRun};
+use crate::redaction;
+
+/// Archives bigger than this are more than test reports, and aren't
+/// downloaded.
+const MAX_ARCHIVE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Likewise a report which inflates to more than this is skipped. The size
+/// an archive gives for a file can't be trusted, since the archive was made
+/// by the workflow, so no more than this is read of it either way.
+const MAX_REPORT_SIZE: u64 = 32 * 1024 * 1024;
+
+/// A report can have many thousands of tests. Beyond this many the passing
+/// ones are left out, so the trace doesn't drown in them.
+const MAX_TEST_CASES: usize = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TestOutcome {
+    Passed,
+    Failed,
+    Skipped,
+}
+
+impl TestOutcome {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            TestOutcome::Passed => "pass",
+            TestOutcome::Failed => "fail",
+            TestOutcome::Skipped => "skipped",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct TestCase {
+    pub(crate) suite: Option<String>,
+    pub(crate) classname: Option<String>,
+    pub(crate) name: String,
+    pub(crate) seconds: f64,
+    pub(crate) outcome: TestOutcome,
+    pub(crate) message: Option<String>,
+}
+
+/// The tests found in one Artifact.
+#[derive(Debug)]
+pub(crate) struct TestReport {
+    pub(crate) artifact: String,
+
+    /// When the Artifact was uploaded, which tells us which Job it came from
+    /// and roughly when its tests finished.
+    pub(crate) uploaded: OffsetDateTime,
+
+    pub(crate) cases: Vec<TestCase>,
+}
+
+/// Retrieve the Artifacts of the Run whose names match the pattern, and read
+/// the JUnit reports in them. Test results are an addition to the trace, so
+/// any problem getting them is logged rather than failing the Run.
+pub(crate) async fn retrieve_test_reports(
+    config: &Config,
+    client: &reqwest::Client,
+    run: &WorkflowRun,
+    pattern: &Regex,
+) -> Vec<TestReport> {
+    let artifacts = match github::retrieve_run_artifacts(config, client, run).await {
+        Ok(artifacts) => artifacts,
+        Err(problem) => {
+            warn!("Unable to retrieve Artifacts: {}", problem);
+            return Vec::new();
+        }
+    };
+
+    let mut reports = Vec::new();
+
+    for artifact in artifacts
+        .iter()
+        .filter(|artifact| pattern.is_match(&artifact.name))
+    {
+        if artifact.expired {
+            info!("Artifact {} has expired", artifact.name);
+            continue;
+        }
+        if artifact.size_in_bytes > MAX_ARCHIVE_SIZE {
+            warn!(
+                "Artifact {} is too large ({} bytes) to be a test report",
+                artifact.name, artifact.size_in_bytes
+            );
+            continue;
+        }
+
+        match read_artifact(client, artifact).await {
+            Ok(cases) => reports.push(TestReport {
+                artifact: artifact
+                    .name
+                    .clone(),
+                uploaded: artifact.created_at,
+                cases: limit_cases(&artifact.name, cases),
+            }),
+            Err(error) => warn!("Unable to read test report {}: {}", artifact.name, error),
+        }
+    }
+
+    reports
+}
+
+async fn read_artifact(client: &reqwest::Client, artifact: &Artifact) -> Result<Vec<TestCase>> {
+    let bytes = github::retrieve_artifact_archive(client, artifact)
+        .await
+        .map_err(|problem| anyhow::anyhow!("{}", problem))?;
+
+    parse_archive(&bytes)
+}
+
+/// Read every XML file in the archive as a JUnit report.
+fn parse_archive(bytes: &[u8]) -> Result<Vec<TestCase>> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+    let mut cases = Vec::new();
+
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index)?;
+        if !file.is_file()
+            || !file
+                .name()
+                .ends_with(".xml")
+        {
+            continue;
+        }
+
+        let name = file
+            .name()
+            .to_string();
+
+        if file.size() > MAX_REPORT_SIZE {
+            warn!("Skipping {}, which is {} bytes", name, file.size());
+            continue;
+        }
+
+        let mut text = String::new();
+        (&mut file)
+            .take(MAX_REPORT_SIZE + 1)
+            .read_to_string(&mut text)?;
+
+        if text.len() as u64 > MAX_REPORT_SIZE {
+            warn!("Skipping {}, which is larger than it claims", name);
+            continue;
+        }
+
+        match parse_report(&text) {
+            Ok(found) => cases.extend(found),
+            Err(error) => warn!("Unable to parse {} as a JUnit report: {}", name, error),
+        }
+    }
+
+    Ok(cases)
+}
+
+/// Find the test cases in a JUnit report. Reports vary in whether the
+/// suites are wrapped in a <testsuites> element, and in how deeply suites are
+/// nested, so we look for <testcase> elements wherever they are.
+fn parse_report(text: &str) -> Result<Vec<TestCase>> {
+    let document = roxmltree::Document::parse(text)?;
+
+    let cases = document
+        .descendants()
+        .filter(|node| node.has_tag_name("testcase"))
+        .map(|node| {
+            let suite = node
+                .ancestors()
+                .find(|ancestor| ancestor.has_tag_name("testsuite"))
+                .and_then(|suite| suite.attribute("name"))
+                .map(str::to_string);
+
+            let problem = node
+                .children()
+                .find(|child| child.has_tag_name("failure") || child.has_tag_name("error"));
+            let skipped = node
+                .children()
+                .any(|child| child.has_tag_name("skipped"));
+
+            let outcome = if problem.is_some() {
+                TestOutcome::Failed
+            } else if skipped {
+                TestOutcome::Skipped
+            } else {
+                TestOutcome::Passed
+            };
+
+            // the message attribute is the summary; failing that the first
+            // line of the body, which is usually a stack trace.
+            let message = problem.and_then(|problem| {
+                problem
+                    .attribute("message")
+                    .or_else(|| {
+                        problem
+                            .text()
+                            .and_then(|text| {
+                                text.trim()
+                                    .lines()
+                                    .next()
+                            })
+                    })
+                    .map(redaction::redact)
+            });
+
+            TestCase {
+                suite,
+                classname: node
+                    .attribute("classname")
+                    .map(str::to_string),
+                name: node
+                    .attribute("name")
+                    .unwrap_or("unnamed")
+                    .to_string(),
+                seconds: node
+                    .attribute("time")
+                    .and_then(|time| {
+                        time.trim()
+                            .parse::<f64>()
+                            .ok()
+                    })
+                    .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+                    .unwrap_or(0.0),
+                outcome,
+                message,
+            }
+        })
+        .collect();
+
+    Ok(cases)
+}
+
+/// Keep every failure, and as many of the rest as fit.
+fn limit_cases(artifact: &str, cases: Vec<TestCase>) -> Vec<TestCase> {
+    if cases.len() <= MAX_TEST_CASES {
+        return cases;
+    }
+
+    warn!(
+        "Test report {} has {} tests; only sending {}",
+        artifact,
+        cases.len(),
+        MAX_TEST_CASES
+    );
+
+    let failures = cases
+        .iter()
+        .filter(|case| case.outcome == TestOutcome::Failed)
+        .count();
+    let mut room = MAX_TEST_CASES.saturating_sub(failures);
+
+    cases
+        .into_iter()
+        .filter(|case| {
+            if case.outcome == TestOutcome::Failed {
+                return true;
+            }
+            if room > 0 {
+                room -= 1;
+                return true;
+            }
+            false
+        })
+        .collect()
+}
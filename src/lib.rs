@@ -0,0 +1,754 @@
+//! Retrieve the history of GitHub Actions workflows and send it to
+//! OpenTelemetry as traces. This is what the `hero` program is built on, and
+//! it can equally be embedded in a service of your own rather than having to
+//! run `hero` alongside it:
+//!
+//! ```no_run
+//! use action_hero::{ApiTokens, Config, ExporterOptions, HistoryStore, Pipeline, TraceBuilder};
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let pipeline = Pipeline::install(&ExporterOptions::default(), ApiTokens::new("ghp_..."), None)?;
+//! let history = HistoryStore::open("record")?;
+//!
+//! let builder = TraceBuilder::new(Config::new("octocat", "hello-world", "check.yaml"))?;
+//! for run in builder
+//!     .retrieve_runs(10)
+//!     .await?
+//! {
+//!     if !history.has_sent(builder.config(), &run) {
+//!         builder
+//!             .submit(&run)
+//!             .await?;
+//!     }
+//! }
+//!
+//! pipeline.shutdown()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Much of the machinery is held globally (the OpenTelemetry providers, the
+//! tokens, the record directory), so these are set up once for the life of
+//! the process.
+
+use anyhow::{Ok, Result, anyhow};
+use opentelemetry::context::FutureExt;
+use opentelemetry::trace::{Span, Status, TraceContextExt, Tracer};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use std::collections::{HashMap, hash_map::Entry};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{OnceLock, RwLock};
+use time::OffsetDateTime;
+use tracing::{info, warn};
+
+const VERSION: &str = concat!("v", env!("CARGO_PKG_VERSION"));
+
+/// The commit this was built from, if it was built from a git checkout.
+const GIT_SHA: Option<&str> = option_env!("HERO_GIT_SHA");
+
+static PROGRAM_START: OnceLock<OffsetDateTime> = OnceLock::new();
+
+fn set_program_start() {
+    PROGRAM_START
+        .set(OffsetDateTime::now_utc())
+        .unwrap();
+}
+
+fn get_program_start() -> &'static OffsetDateTime {
+    PROGRAM_START.wait()
+}
+
+/// The GitHub API tokens available to this program. A single Personal Access
+/// Token rarely has access to every organization we might receive events
+/// from, so in addition to the default token there can be tokens specific to
+/// a given owner. Repositories on a GitHub Enterprise Server instance are
+/// accessed with a token of their own.
+#[derive(Debug, Default)]
+pub struct ApiTokens {
+    pub default: Option<String>,
+    /// Tokens by the (lower case) name of the owner they are for.
+    pub owners: HashMap<String, String>,
+    pub enterprise: Option<String>,
+}
+
+// The tokens can be replaced while the listener is running, so rather than
+// being set once they are held behind a lock. Each set of tokens is leaked
+// so that those handed out remain valid; reloads are rare enough that this
+// doesn't amount to anything.
+static GITHUB_TOKENS: RwLock<Option<&'static ApiTokens>> = RwLock::new(None);
+
+/// Where the default token was explicitly said to come from, in place of
+/// the environment variable or credential. A file is read again each time the
+/// tokens are (so that one rotated by a secret manager is picked up on
+/// reload) whereas a token given on stdin can only be read the once.
+pub(crate) enum TokenSource {
+    File(PathBuf),
+    Given(String),
+}
+
+pub(crate) static TOKEN_SOURCE: OnceLock<TokenSource> = OnceLock::new();
+
+const TOKEN_VARIABLE: &str = "GITHUB_TOKEN";
+const TOKEN_CREDENTIAL: &str = "receiver-github-token";
+
+// Tokens for a specific owner have names of their own, so that unrelated
+// variables (or a profile's token) which happen to start with GITHUB_TOKEN
+// aren't mistaken for one.
+const OWNER_TOKEN_VARIABLE: &str = "GITHUB_TOKEN_FOR_";
+const OWNER_TOKEN_CREDENTIAL: &str = "receiver-github-token-for-";
+const ENTERPRISE_VARIABLE: &str = "GITHUB_ENTERPRISE_TOKEN";
+const ENTERPRISE_CREDENTIAL: &str = "receiver-github-enterprise-token";
+
+// get GITHUB_TOKEN value, either from the system credentials store of
+// directly from an environment variable. We go to the trouble of having this
+// in a global variable so we can ensure to check for it at program start.
+//
+// Tokens for a specific owner are picked up from GITHUB_TOKEN_FOR_<OWNER>
+// environment variables or receiver-github-token-for-<owner> credential
+// files.
+// The token for a GitHub Enterprise Server is similarly either the
+// GITHUB_ENTERPRISE_TOKEN variable or a receiver-github-enterprise-token
+// credential.
+//
+// A profile can name a different variable (or credential) to take the
+// default token from, and --token-file or --token-stdin override both.
+fn read_api_tokens(profile: &ConfigProfile) -> Result<ApiTokens> {
+    let (variable, credential) = match &profile.token {
+        Some(name) => (name.as_str(), name.as_str()),
+        None => (TOKEN_VARIABLE, TOKEN_CREDENTIAL),
+    };
+
+    let mut default = match TOKEN_SOURCE.get() {
+        Some(TokenSource::File(path)) => Some(read_credential(path)?),
+        Some(TokenSource::Given(token)) => Some(token.clone()),
+        None => std::env::var(variable).ok(),
+    };
+    let mut owners = HashMap::new();
+    let mut enterprise = std::env::var(ENTERPRISE_VARIABLE).ok();
+
+    for (key, value) in std::env::vars() {
+        if let Some(owner) = key.strip_prefix(OWNER_TOKEN_VARIABLE) {
+            // environment variable names can't contain '-' so we allow '_'
+            // in its place. Owner names are not case sensitive.
+            let owner = owner
+                .replace('_', "-")
+                .to_lowercase();
+            owners.insert(owner, value);
+        }
+    }
+
+    if let Some(directory) = credentials_directory() {
+        let entries = std::fs::read_dir(&directory).map_err(|_| {
+            anyhow!(
+                "Failed to read credentials directory {}",
+                directory.display()
+            )
+        })?;
+
+        for entry in entries.flatten() {
+            let name = entry
+                .file_name()
+                .to_string_lossy()
+                .to_string();
+
+            if name == credential {
+                if default.is_none() {
+                    default = Some(read_credential(&entry.path())?);
+                }
+            } else if name == ENTERPRISE_CREDENTIAL {
+                if enterprise.is_none() {
+                    enterprise = Some(read_credential(&entry.path())?);
+                }
+            } else if let Some(owner) = name.strip_prefix(OWNER_TOKEN_CREDENTIAL)
+                && let Entry::Vacant(vacant) = owners.entry(owner.to_lowercase())
+            {
+                vacant.insert(read_credential(&entry.path())?);
+            }
+        }
+    }
+
+    if default.is_none() && owners.is_empty() && enterprise.is_none() {
+        if cfg!(windows) {
+            return Err(anyhow!(
+                "Either the {} environment variable must be set or a {} credential be in %APPDATA%\\hero\\credentials.",
+                variable,
+                credential
+            ));
+        }
+        return Err(anyhow!(
+            "Either a CREDENTIALS_DIRECTORY or {} environment variable must be set.",
+            variable
+        ));
+    }
+
+    Ok(ApiTokens {
+        default,
+        owners,
+        enterprise,
+    })
+}
+
+/// Where credentials are kept as files. systemd hands a service its
+/// credentials in the CREDENTIALS_DIRECTORY, but there is nothing like that
+/// on Windows; there they are looked for in hero\credentials under the
+/// user's application data, if that exists.
+fn credentials_directory() -> Option<PathBuf> {
+    if let Result::Ok(directory) = std::env::var("CREDENTIALS_DIRECTORY") {
+        return Some(PathBuf::from(directory));
+    }
+
+    if cfg!(windows)
+        && let Result::Ok(appdata) = std::env::var("APPDATA")
+    {
+        let directory = Path::new(&appdata)
+            .join("hero")
+            .join("credentials");
+        if directory.is_dir() {
+            return Some(directory);
+        }
+    }
+
+    None
+}
+
+fn read_credential(path: &Path) -> Result<String> {
+    // read the credential file
+    let contents = std::fs::read_to_string(path)
+        .map_err(|_| anyhow!("Failed to read token file at {}", path.display()))?;
+
+    // trim pesky trailing newlines that humans leave in their files
+    Ok(contents
+        .trim()
+        .to_string())
+}
+
+/// Read a secret from the given environment variable or, failing that,
+/// from the named credential in the credentials directory.
+fn lookup_secret(variable: &str, credential: &str) -> Result<Option<String>> {
+    if let Result::Ok(value) = std::env::var(variable) {
+        return Ok(Some(value));
+    }
+
+    if let Some(directory) = credentials_directory() {
+        let path = directory.join(credential);
+        if path.exists() {
+            return read_credential(&path).map(Some);
+        }
+    }
+
+    Ok(None)
+}
+
+fn set_api_tokens(tokens: ApiTokens) {
+    for (_, _, token) in tokens.all() {
+        redaction::register_secret(token);
+    }
+
+    let tokens = Box::leak(Box::new(tokens));
+
+    *GITHUB_TOKENS
+        .write()
+        .unwrap() = Some(tokens);
+}
+
+fn current_api_tokens() -> &'static ApiTokens {
+    GITHUB_TOKENS
+        .read()
+        .unwrap()
+        .expect("API tokens not set")
+}
+
+/// Get the token to be used when accessing repositories belonging to the
+/// given owner, falling back to the default token if there isn't one
+/// specific to that owner. Only the configured GitHub Enterprise Server host
+/// is given the enterprise token; any other host gets nothing at all.
+fn get_api_token(host: &str, owner: &str) -> Option<&'static String> {
+    let tokens = current_api_tokens();
+
+    if host != github::GITHUB_HOST {
+        return match github::get_enterprise_host() {
+            Some(enterprise) if enterprise.eq_ignore_ascii_case(host) => tokens
+                .enterprise
+                .as_ref(),
+            _ => None,
+        };
+    }
+
+    tokens
+        .owners
+        .get(&owner.to_lowercase())
+        .or(tokens
+            .default
+            .as_ref())
+}
+
+impl ApiTokens {
+    /// Just the one token, used for everything on github.com.
+    pub fn new(token: impl Into<String>) -> ApiTokens {
+        ApiTokens {
+            default: Some(token.into()),
+            ..ApiTokens::default()
+        }
+    }
+
+    /// Stand-in tokens for when responses are replayed from fixtures and
+    /// GitHub never sees them.
+    fn placeholder() -> ApiTokens {
+        ApiTokens {
+            default: Some("fixtures".to_string()),
+            owners: HashMap::new(),
+            enterprise: Some("fixtures".to_string()),
+        }
+    }
+
+    /// All the tokens available, along with the host they are for and the
+    /// owner they are specific to (if any), for the purpose of checking them.
+    /// The enterprise token is only checked if there is a host to check it
+    /// against.
+    fn all(&self) -> impl Iterator<Item = (&'static str, Option<&str>, &String)> {
+        self.default
+            .iter()
+            .map(|token| (github::GITHUB_HOST, None, token))
+            .chain(
+                self.owners
+                    .iter()
+                    .map(|(owner, token)| (github::GITHUB_HOST, Some(owner.as_str()), token)),
+            )
+            .chain(
+                github::get_enterprise_host()
+                    .zip(
+                        self.enterprise
+                            .as_ref(),
+                    )
+                    .map(|(host, token)| (host, None, token)),
+            )
+    }
+
+    /// Check each of the tokens against the GitHub API.
+    async fn validate(&self) -> Result<()> {
+        for (host, owner, token) in self.all() {
+            if let Some(owner) = owner {
+                info!("Checking token for {}", owner);
+            } else if host != github::GITHUB_HOST {
+                info!("Checking token for {}", host);
+            }
+            let client = github::build_api_client(host, token)?;
+            github::validate_api_token(&client, host).await?;
+        }
+
+        Ok(())
+    }
+}
+
+mod admin;
+mod allowlist;
+pub mod cli;
+mod compare;
+mod deadletter;
+mod failure;
+mod fixtures;
+mod forge;
+mod forward;
+mod github;
+mod history;
+mod junit;
+mod live;
+mod metrics;
+mod mock;
+mod presets;
+mod queue;
+mod recovery;
+mod redaction;
+mod schema;
+mod settings;
+mod signature;
+mod simulate;
+mod summary;
+mod traces;
+#[cfg(feature = "tui")]
+mod tui;
+mod webhook;
+mod workflow;
+
+use settings::ConfigProfile;
+
+pub use github::{Config, WorkflowRun};
+pub use history::{HistoryStore, Record};
+pub use traces::ExporterOptions;
+
+// The machinery the Pipeline sets up is global to the process, and can only
+// be set up once.
+static PIPELINE_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// The telemetry machinery, and the GitHub tokens, for processing Runs. This
+/// can only be installed once, and trying again is an error; call shutdown()
+/// before exiting to be sure all the spans have been sent.
+pub struct Pipeline {
+    provider: SdkTracerProvider,
+    self_provider: SdkTracerProvider,
+    meter_provider: SdkMeterProvider,
+}
+
+impl Pipeline {
+    /// Set up exporting to the collector described by the options, with
+    /// the given tokens for accessing GitHub. If there is a GitHub Enterprise
+    /// Server instance its host is given too.
+    pub fn install(
+        options: &ExporterOptions,
+        tokens: ApiTokens,
+        enterprise_host: Option<String>,
+    ) -> Result<Pipeline> {
+        if PIPELINE_INSTALLED.swap(true, Ordering::SeqCst) {
+            return Err(anyhow!("Pipeline already installed"));
+        }
+
+        PROGRAM_START.get_or_init(OffsetDateTime::now_utc);
+
+        github::set_enterprise_host(enterprise_host);
+        set_api_tokens(tokens);
+
+        let provider = traces::setup_telemetry_machinery(options)?;
+        let self_provider = traces::setup_self_telemetry(options)?;
+        let meter_provider = metrics::setup_metrics_machinery(options)?;
+
+        Ok(Pipeline {
+            provider,
+            self_provider,
+            meter_provider,
+        })
+    }
+
+    pub fn shutdown(self) -> Result<()> {
+        self.provider
+            .shutdown()?;
+        traces::shutdown_repository_providers()?;
+        self.self_provider
+            .shutdown()?;
+        self.meter_provider
+            .shutdown()?;
+
+        Ok(())
+    }
+}
+
+/// Turns the Runs of a workflow into traces.
+pub struct TraceBuilder {
+    config: Config,
+    client: reqwest::Client,
+}
+
+impl TraceBuilder {
+    pub fn new(config: Config) -> Result<TraceBuilder> {
+        let client = github::setup_api_client(&config)?;
+
+        Ok(TraceBuilder { config, client })
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The most recent Runs of the workflow, newest first.
+    pub async fn retrieve_runs(&self, count: u32) -> Result<Vec<WorkflowRun>> {
+        github::retrieve_workflow_runs(&self.config, &self.client, count, None).await
+    }
+
+    /// Send the Run's trace, unless the HistoryStore (if one has been
+    /// opened) shows it has already been sent, recording it there if so.
+    pub async fn submit(&self, run: &WorkflowRun) -> Result<()> {
+        submit_run(&self.config, &self.client, run).await
+    }
+
+    /// Send the Run's trace regardless of whether it has been sent before,
+    /// returning its TraceId.
+    pub async fn build(&self, run: &WorkflowRun) -> Result<String> {
+        process_run(&self.config, &self.client, run).await
+    }
+}
+
+/// Process the Run and send its telemetry, unless the record of submitted
+/// Runs (if one is being kept) shows it has already been done. The Run is
+/// claimed in the record while it is being processed so that another query
+/// or listener sharing the record doesn't send it at the same time.
+pub(crate) async fn submit_run(
+    config: &Config,
+    client: &reqwest::Client,
+    run: &WorkflowRun,
+) -> Result<()> {
+    let record = if history::is_enabled() {
+        let path = history::form_record_filename(config, run);
+        match history::claim_run(&path).await? {
+            Some(claim) => Some((path, claim)),
+            None => return Ok(()),
+        }
+    } else {
+        None
+    };
+
+    if !should_export(run) {
+        info!(
+            "Suppressing Run {} ({})",
+            run.run_id,
+            describe_conclusion(run)
+        );
+        metrics::record_run_outcome(config, run);
+        if let Some((path, _claim)) = &record {
+            history::mark_run_suppressed(
+                path,
+                describe_conclusion(run),
+                webhook::current_delivery().as_deref(),
+            )
+            .await?;
+        }
+        return Ok(());
+    }
+
+    let trace_id = process_run(config, client, run).await;
+    metrics::record_run_processed(trace_id.is_ok());
+    summary::record_run_processed(trace_id.is_ok());
+    let trace_id = trace_id?;
+
+    if let Some((path, _claim)) = &record {
+        history::mark_run_submitted(
+            path,
+            trace_id,
+            describe_conclusion(run),
+            webhook::current_delivery().as_deref(),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// When only exporting failures, Runs which didn't fail are processed (and
+/// recorded as such) but their traces are not sent.
+fn should_export(run: &WorkflowRun) -> bool {
+    if !settings::get_settings().only_failures {
+        return true;
+    }
+
+    run.conclusion
+        .as_deref()
+        .map(github::classify_conclusion)
+        == Some(github::Outcome::Failure)
+}
+
+fn describe_conclusion(run: &WorkflowRun) -> &str {
+    run.conclusion
+        .as_deref()
+        .unwrap_or(&run.status)
+}
+
+async fn process_run(
+    config: &Config,
+    client: &reqwest::Client,
+    run: &WorkflowRun,
+) -> Result<String> {
+    // group the requests we make of GitHub while processing this Run under a
+    // span in our own telemetry.
+    let tracer = traces::self_tracer();
+    let mut span = tracer
+        .span_builder("process run")
+        .with_attributes([
+            KeyValue::new(
+                "owner",
+                config
+                    .owner
+                    .clone(),
+            ),
+            KeyValue::new(
+                "repository",
+                config
+                    .repository
+                    .clone(),
+            ),
+            KeyValue::new(
+                "workflow",
+                config
+                    .workflow
+                    .clone(),
+            ),
+            KeyValue::new("run_id", run.run_id as i64),
+        ])
+        .start(&tracer);
+    if let Some(delivery) = webhook::current_delivery() {
+        span.set_attribute(KeyValue::new("github.delivery", delivery));
+    }
+    let context = Context::current_with_span(span);
+
+    let result = process_run_inner(config, client, run)
+        .with_context(context.clone())
+        .await;
+
+    let span = context.span();
+    if let Result::Err(error) = &result {
+        span.set_status(Status::error(error.to_string()));
+    }
+    span.end();
+
+    result
+}
+
+async fn process_run_inner(
+    config: &Config,
+    client: &reqwest::Client,
+    run: &WorkflowRun,
+) -> Result<String> {
+    info!("Processing Run {}", run.run_id);
+
+    let context = traces::establish_root_context(config, run);
+
+    // the path can carry the ref it was loaded from, as in "file.yaml@main",
+    // and workflows which aren't files in the repository (such as the
+    // dynamic ones GitHub runs for Dependabot) have nothing to fetch.
+    let path = run
+        .path
+        .split('@')
+        .next()
+        .unwrap_or_default();
+
+    let mut graph = None;
+
+    if path.starts_with(".github/workflows/") {
+        match github::retrieve_workflow_source(config, client, path, run).await {
+            Result::Ok(source) => {
+                traces::annotate_workflow_hash(&context, path, &source.hash);
+
+                // the dependencies between jobs are a nicety; a workflow
+                // we can't make sense of still gets its trace.
+                graph = source
+                    .text
+                    .and_then(|text| match workflow::parse_job_graph(&text) {
+                        Result::Ok(graph) => Some(graph),
+                        Err(error) => {
+                            warn!("Unable to parse workflow file {}: {}", path, error);
+                            None
+                        }
+                    });
+
+                if let Some((group, cancel_in_progress)) = graph
+                    .as_ref()
+                    .and_then(|graph| graph.concurrency_of(config, run))
+                {
+                    traces::annotate_concurrency(&context, group, cancel_in_progress);
+                }
+            }
+            Result::Err(problem) => warn!("Unable to retrieve workflow file: {}", problem),
+        }
+    }
+
+    let schedules = graph
+        .as_ref()
+        .map(|graph| graph.schedules())
+        .unwrap_or_default();
+    traces::annotate_trigger(&context, run, schedules);
+
+    // not every token will have been granted access to Deployments, so
+    // failing to get them is not a reason to abandon the whole Run.
+    let deployments = match github::retrieve_run_deployments(config, client, run).await {
+        Result::Ok(deployments) => deployments,
+        Result::Err(problem) => {
+            warn!("Unable to retrieve Deployments: {}", problem);
+            Vec::new()
+        }
+    };
+
+    // only bother asking who approved deployments if any of them actually
+    // had to wait for approval.
+    let waited = deployments
+        .iter()
+        .flat_map(|deployment| &deployment.statuses)
+        .any(|status| status.state == "waiting");
+
+    let approvals = if waited {
+        match github::retrieve_run_approvals(config, client, run).await {
+            Result::Ok(approvals) => approvals,
+            Result::Err(problem) => {
+                warn!("Unable to retrieve Approvals: {}", problem);
+                Vec::new()
+            }
+        }
+    } else {
+        Vec::new()
+    };
+
+    // Deployments are retrieved first so that Jobs which deployed to an
+    // environment can be annotated with it. The Jobs themselves are turned
+    // into spans a page at a time, as they arrive.
+    let reports = match &settings::get_settings().test_reports {
+        Some(pattern) => junit::retrieve_test_reports(config, client, run, pattern).await,
+        None => Vec::new(),
+    };
+
+    let mut spans =
+        traces::JobSpans::new(config, client, &context, run, graph.as_ref(), &deployments)
+            .with_test_reports(reports);
+    let mut pages = github::JobPages::new(config, run);
+    pages
+        .settle(client, run)
+        .await?;
+
+    let mut explained = run
+        .conclusion
+        .as_deref()
+        != Some("cancelled");
+
+    while let Some(jobs) = pages
+        .next(client)
+        .await?
+    {
+        for job in jobs
+            .iter()
+            .filter(|job| job.conclusion == "cancelled")
+        {
+            if explained {
+                break;
+            }
+            match github::retrieve_cancellation(config, client, job.job_id).await {
+                Result::Ok(Some(cancellation)) => {
+                    traces::annotate_cancellation(&context, cancellation);
+                    explained = true;
+                }
+                Result::Ok(None) => {}
+                Result::Err(problem) => {
+                    warn!("Unable to retrieve Annotations: {}", problem);
+                    explained = true;
+                }
+            }
+        }
+
+        spans
+            .display(jobs)
+            .await?;
+    }
+
+    spans.display_remaining_test_reports();
+    spans.display_calls();
+
+    traces::display_deployments(config, &context, run, deployments, &approvals);
+
+    if run.status == "waiting" {
+        match github::retrieve_pending_deployments(config, client, run).await {
+            Result::Ok(pending) => {
+                traces::display_pending_approvals(config, &context, run, pending)
+            }
+            Result::Err(problem) => warn!("Unable to retrieve Pending Deployments: {}", problem),
+        }
+    }
+
+    let trace_id = traces::finalize_root_span(&context, run);
+
+    metrics::record_run_outcome(config, run);
+
+    if let Some(template) = &settings::get_settings().trace_url {
+        let url = traces::form_trace_url(template, &trace_id, run);
+        info!("Submitted Run {} as {}", run.run_id, url);
+        println!("{}", url);
+    }
+
+    Ok(trace_id)
+}
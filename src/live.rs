@@ -0,0 +1,136 @@
+//! Sending the spans of a Job as soon as the `workflow_job` event saying it
+//! has completed arrives, rather than waiting for the whole Run to complete.
+//! A nightly Run can take hours; this way its Jobs appear in the backend as
+//! they finish. When the Run does complete, the Jobs already sent are left
+//! out of what is sent for it.
+
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, OnceLock};
+use time::{Duration, OffsetDateTime};
+use tracing::info;
+
+use crate::github::{Config, WorkflowJob, WorkflowRun};
+use crate::traces;
+
+struct LiveJobs {
+    provisional: bool,
+}
+
+static LIVE_JOBS: OnceLock<LiveJobs> = OnceLock::new();
+
+pub(crate) fn enable(provisional: bool) {
+    LIVE_JOBS
+        .set(LiveJobs { provisional })
+        .unwrap_or_else(|_| panic!("Live Jobs already enabled"));
+}
+
+pub(crate) fn is_enabled() -> bool {
+    LIVE_JOBS
+        .get()
+        .is_some()
+}
+
+/// Whether Jobs starting should also be sent, as provisional spans.
+pub(crate) fn is_provisional() -> bool {
+    LIVE_JOBS
+        .get()
+        .is_some_and(|live| live.provisional)
+}
+
+/// A Job whose spans have been sent, along with the category of its failure
+/// if it failed, so that the Run's span can be given it.
+pub(crate) struct SentJob {
+    received: OffsetDateTime,
+    pub(crate) category: Option<&'static str>,
+}
+
+// Jobs sent, by ID, which the Run they belong to hasn't yet been sent.
+static SENT: Mutex<BTreeMap<u64, SentJob>> = Mutex::new(BTreeMap::new());
+
+// Runs taking longer than this are not expected, so if a Job is still here
+// after this long its Run is not going to arrive.
+const SENT_RETENTION: Duration = Duration::days(3);
+
+/// Note that a Job is being sent. This is done before its spans are built so
+/// that the Run completing meanwhile doesn't send it a second time.
+fn mark_sent(job_id: u64) {
+    let now = OffsetDateTime::now_utc();
+
+    let mut sent = SENT
+        .lock()
+        .unwrap();
+
+    sent.retain(|_, job| now - job.received < SENT_RETENTION);
+
+    sent.insert(
+        job_id,
+        SentJob {
+            received: now,
+            category: None,
+        },
+    );
+}
+
+/// Claim the record of a Job having been sent, if it was.
+pub(crate) fn take_sent(job_id: u64) -> Option<SentJob> {
+    if !is_enabled() {
+        return None;
+    }
+
+    SENT.lock()
+        .unwrap()
+        .remove(&job_id)
+}
+
+/// Send the spans of a completed Job, under the span its Run will have.
+pub(crate) async fn send_job(
+    config: &Config,
+    client: &reqwest::Client,
+    run: &WorkflowRun,
+    job: WorkflowJob,
+) -> Result<()> {
+    let job_id = job.job_id;
+    info!("Sending Job {} of Run {}", job_id, run.run_id);
+
+    mark_sent(job_id);
+
+    let context = traces::establish_remote_root_context(config, run);
+    let mut spans = traces::JobSpans::new(config, client, &context, run, None, &[]).live();
+
+    let result = spans
+        .display(vec![job])
+        .await;
+
+    let mut sent = SENT
+        .lock()
+        .unwrap();
+
+    match result {
+        Ok(()) => {
+            if let Some(entry) = sent.get_mut(&job_id) {
+                entry.category = spans.category();
+            }
+            Ok(())
+        }
+        Err(problem) => {
+            // leave the Job to be sent along with its Run instead
+            sent.remove(&job_id);
+            Err(anyhow::anyhow!("{}", problem))
+        }
+    }
+}
+
+/// Send a provisional span for a Job which has just started.
+pub(crate) fn send_started(
+    config: &Config,
+    run: &WorkflowRun,
+    job_id: u64,
+    name: String,
+    started_at: OffsetDateTime,
+) {
+    info!("Job {} of Run {} has started", job_id, run.run_id);
+
+    let context = traces::establish_remote_root_context(config, run);
+    traces::display_provisional_job(config, &context, run, job_id, name, started_at);
+}
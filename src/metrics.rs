@@ -0,0 +1,445 @@
+//! Metrics derived from the Runs we process, sent alongside the traces. These
+//! allow dashboards to show the health of pipelines without having to
+//! aggregate over traces.
+
+use anyhow::Result;
+use opentelemetry::KeyValue;
+use opentelemetry::metrics::{Counter, Histogram, MeterProvider, ObservableGauge};
+use opentelemetry_otlp::{
+    MetricExporter, Protocol, WithExportConfig, WithHttpConfig, WithTonicConfig,
+};
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Mutex, OnceLock};
+
+use crate::github::{Config, Outcome, WorkflowRun, classify_conclusion};
+use crate::queue;
+use crate::traces::{self, ExporterOptions};
+
+// How many of the most recent Runs of each workflow the success rate is
+// calculated over.
+const SUCCESS_WINDOW: usize = 20;
+
+/// The outcomes of recent Runs of a workflow, oldest first. True if the Run
+/// succeeded.
+static OUTCOMES: Mutex<BTreeMap<(String, String, String), VecDeque<bool>>> =
+    Mutex::new(BTreeMap::new());
+
+// the gauge is observed via its callback, but we hold onto the instrument
+// for the life of the program.
+static SUCCESS_GAUGE: OnceLock<ObservableGauge<f64>> = OnceLock::new();
+
+// How long each Step took, so that trends can be charted without having to
+// aggregate over traces.
+static STEP_DURATIONS: OnceLock<Histogram<f64>> = OnceLock::new();
+
+// How long Jobs waited before starting, split into the time spent waiting on
+// the workflow (for the Jobs they need, or a concurrency group) and the time
+// spent waiting for a runner to pick them up.
+static QUEUE_WAITS: OnceLock<Histogram<f64>> = OnceLock::new();
+static RUNNER_WAITS: OnceLock<Histogram<f64>> = OnceLock::new();
+
+// Runs given up on and moved to the dead-letter directory. This is always
+// present, so that it can be alerted on.
+static DEAD_LETTERS: OnceLock<Counter<u64>> = OnceLock::new();
+
+// Responses from GitHub warning that an endpoint or the API version we use
+// is deprecated. Also always present.
+static API_DEPRECATIONS: OnceLock<Counter<u64>> = OnceLock::new();
+
+// Metrics about this program itself. These are only present if asked for.
+static SELF_GAUGES: OnceLock<Vec<ObservableGauge<u64>>> = OnceLock::new();
+static RUNS_PROCESSED: OnceLock<Counter<u64>> = OnceLock::new();
+
+fn build_metric_exporter(options: &ExporterOptions) -> Result<MetricExporter> {
+    let protocol = traces::resolve_protocol(options)?;
+
+    let exporter = match protocol {
+        Protocol::Grpc => {
+            let mut builder = MetricExporter::builder()
+                .with_tonic()
+                .with_metadata(traces::grpc_metadata(options)?);
+            if let Some(channel) = traces::grpc_channel(options)? {
+                builder = builder.with_channel(channel);
+            } else if let Some(endpoint) = &options.endpoint {
+                builder = builder.with_endpoint(endpoint);
+            } else if let Some(endpoint) = &options.base_endpoint {
+                builder = builder.with_endpoint(endpoint);
+            }
+            if let Some(timeout) = options.timeout {
+                builder = builder.with_timeout(timeout);
+            }
+            builder.build()?
+        }
+        Protocol::HttpBinary | Protocol::HttpJson => {
+            let mut builder = MetricExporter::builder()
+                .with_http()
+                .with_protocol(protocol)
+                .with_headers(traces::http_headers(options));
+            if let Some(path) = traces::unix_socket(options) {
+                builder = builder
+                    .with_http_client(traces::unix_http_client(path)?)
+                    .with_endpoint("http://localhost/v1/metrics");
+            } else if let Some(endpoint) = &options.endpoint {
+                builder = builder.with_endpoint(endpoint);
+            } else if let Some(base) = &options.base_endpoint {
+                builder = builder.with_endpoint(format!("{}/v1/metrics", base));
+            }
+            if let Some(timeout) = options.timeout {
+                builder = builder.with_timeout(timeout);
+            }
+            builder.build()?
+        }
+    };
+
+    Ok(exporter)
+}
+
+pub(crate) fn setup_metrics_machinery(options: &ExporterOptions) -> Result<SdkMeterProvider> {
+    let exporter = build_metric_exporter(options)?;
+
+    let provider = SdkMeterProvider::builder()
+        .with_periodic_exporter(exporter)
+        .with_resource(traces::workflow_resource(traces::get_service_name()))
+        .build();
+
+    let meter = provider.meter(module_path!());
+
+    let gauge = meter
+        .f64_observable_gauge("workflow.success_rate")
+        .with_description(format!(
+            "Proportion of the most recent {} Runs of the workflow which succeeded",
+            SUCCESS_WINDOW
+        ))
+        .with_unit("1")
+        .with_callback(|observer| {
+            let outcomes = OUTCOMES
+                .lock()
+                .unwrap();
+
+            for ((owner, repository, workflow), recent) in outcomes.iter() {
+                if recent.is_empty() {
+                    continue;
+                }
+
+                let successes = recent
+                    .iter()
+                    .filter(|success| **success)
+                    .count();
+                let rate = successes as f64 / recent.len() as f64;
+
+                observer.observe(
+                    rate,
+                    &[
+                        KeyValue::new("owner", owner.clone()),
+                        KeyValue::new("repository", repository.clone()),
+                        KeyValue::new("workflow", workflow.clone()),
+                    ],
+                );
+            }
+        })
+        .build();
+
+    SUCCESS_GAUGE
+        .set(gauge)
+        .unwrap_or_else(|_| panic!("Metrics already setup"));
+
+    let durations = meter
+        .f64_histogram("workflow.step.duration")
+        .with_description("Time taken by each Step of a workflow's Jobs")
+        .with_unit("s")
+        .build();
+
+    STEP_DURATIONS
+        .set(durations)
+        .unwrap_or_else(|_| panic!("Metrics already setup"));
+
+    let queue_waits = meter
+        .f64_histogram("workflow.job.queue_wait")
+        .with_description(
+            "Time from a Run starting until each of its Jobs was queued, by runner label",
+        )
+        .with_unit("s")
+        .build();
+
+    QUEUE_WAITS
+        .set(queue_waits)
+        .unwrap_or_else(|_| panic!("Metrics already setup"));
+
+    let runner_waits = meter
+        .f64_histogram("workflow.job.runner_wait")
+        .with_description("Time from a Job being queued until a runner started it, by runner label")
+        .with_unit("s")
+        .build();
+
+    RUNNER_WAITS
+        .set(runner_waits)
+        .unwrap_or_else(|_| panic!("Metrics already setup"));
+
+    let dead_letters = meter
+        .u64_counter("hero.runs.dead_lettered")
+        .with_description("Number of Runs which could not be processed and were moved to the dead-letter directory")
+        .build();
+
+    DEAD_LETTERS
+        .set(dead_letters)
+        .unwrap_or_else(|_| panic!("Metrics already setup"));
+
+    let deprecations = meter
+        .u64_counter("hero.github.deprecations")
+        .with_description(
+            "Number of responses from GitHub carrying a deprecation or sunset notice, by header",
+        )
+        .build();
+
+    API_DEPRECATIONS
+        .set(deprecations)
+        .unwrap_or_else(|_| panic!("Metrics already setup"));
+
+    Ok(provider)
+}
+
+/// Note the outcome of a Run that has been processed. Runs which have not
+/// concluded, or whose conclusion is neither a success nor a failure (such
+/// as being skipped), don't count either way.
+pub(crate) fn record_run_outcome(config: &Config, run: &WorkflowRun) {
+    let success = match run
+        .conclusion
+        .as_deref()
+        .map(classify_conclusion)
+    {
+        Some(Outcome::Success) => true,
+        Some(Outcome::Failure) => false,
+        Some(Outcome::Neutral) | None => return,
+    };
+
+    let key = (
+        config
+            .owner
+            .clone(),
+        config
+            .repository
+            .clone(),
+        config
+            .workflow
+            .clone(),
+    );
+
+    let mut outcomes = OUTCOMES
+        .lock()
+        .unwrap();
+
+    let recent = outcomes
+        .entry(key)
+        .or_default();
+
+    recent.push_back(success);
+    while recent.len() > SUCCESS_WINDOW {
+        recent.pop_front();
+    }
+}
+
+/// Note how long a Step took. Steps which were skipped or which haven't
+/// finished shouldn't be recorded, as they would skew the distribution.
+pub(crate) fn record_step_duration(config: &Config, job: &str, step: &str, seconds: f64) {
+    if let Some(histogram) = STEP_DURATIONS.get() {
+        histogram.record(
+            seconds,
+            &[
+                KeyValue::new(
+                    "owner",
+                    config
+                        .owner
+                        .clone(),
+                ),
+                KeyValue::new(
+                    "repository",
+                    config
+                        .repository
+                        .clone(),
+                ),
+                KeyValue::new(
+                    "workflow",
+                    config
+                        .workflow
+                        .clone(),
+                ),
+                KeyValue::new("job", job.to_string()),
+                KeyValue::new("step", step.to_string()),
+            ],
+        );
+    }
+}
+
+/// Note how long a Job waited before it started. The two waits have
+/// different remedies: a long queue wait is the shape of the workflow, while
+/// a long runner wait means the pool of runners with those labels is too
+/// small.
+pub(crate) fn record_job_waits(
+    config: &Config,
+    labels: &str,
+    queue_wait: Option<f64>,
+    runner_wait: Option<f64>,
+) {
+    let attributes = [
+        KeyValue::new(
+            "owner",
+            config
+                .owner
+                .clone(),
+        ),
+        KeyValue::new(
+            "repository",
+            config
+                .repository
+                .clone(),
+        ),
+        KeyValue::new(
+            "workflow",
+            config
+                .workflow
+                .clone(),
+        ),
+        KeyValue::new("runner.labels", labels.to_string()),
+    ];
+
+    if let Some(seconds) = queue_wait
+        && let Some(histogram) = QUEUE_WAITS.get()
+    {
+        histogram.record(seconds, &attributes);
+    }
+    if let Some(seconds) = runner_wait
+        && let Some(histogram) = RUNNER_WAITS.get()
+    {
+        histogram.record(seconds, &attributes);
+    }
+}
+
+/// Setup the metrics describing how this program is coping: the state of the
+/// async runtime, its memory use, how many Runs are waiting in the queue, and
+/// how many have been processed. These are sent as the same service as the
+/// spans about this program. This must be called from within the runtime.
+pub(crate) fn setup_self_metrics(options: &ExporterOptions) -> Result<SdkMeterProvider> {
+    let exporter = build_metric_exporter(options)?;
+
+    let provider = SdkMeterProvider::builder()
+        .with_periodic_exporter(exporter)
+        .with_resource(traces::self_resource())
+        .build();
+
+    let meter = provider.meter(module_path!());
+
+    // the callbacks are run from the exporter's own thread, not from within
+    // the runtime, so we take a handle to it now.
+    let runtime = tokio::runtime::Handle::current();
+
+    let tasks = {
+        let runtime = runtime.clone();
+        meter
+            .u64_observable_gauge("hero.runtime.tasks")
+            .with_description("Number of tasks alive in the async runtime")
+            .with_callback(move |observer| {
+                let metrics = runtime.metrics();
+                observer.observe(metrics.num_alive_tasks() as u64, &[]);
+            })
+            .build()
+    };
+
+    let workers = {
+        let runtime = runtime.clone();
+        meter
+            .u64_observable_gauge("hero.runtime.workers")
+            .with_description("Number of worker threads in the async runtime")
+            .with_callback(move |observer| {
+                let metrics = runtime.metrics();
+                observer.observe(metrics.num_workers() as u64, &[]);
+            })
+            .build()
+    };
+
+    let backlog = meter
+        .u64_observable_gauge("hero.runtime.queue_depth")
+        .with_description("Number of tasks waiting to be run by the async runtime")
+        .with_callback(move |observer| {
+            let metrics = runtime.metrics();
+            observer.observe(metrics.global_queue_depth() as u64, &[]);
+        })
+        .build();
+
+    let memory = meter
+        .u64_observable_gauge("process.memory.usage")
+        .with_description("Resident memory of this process")
+        .with_unit("By")
+        .with_callback(|observer| {
+            if let Some(bytes) = resident_memory() {
+                observer.observe(bytes, &[]);
+            }
+        })
+        .build();
+
+    let queued = meter
+        .u64_observable_gauge("hero.queue.depth")
+        .with_description("Number of Runs waiting in the --queue-dir to be processed")
+        .with_callback(|observer| {
+            if let Some(depth) = queue::depth() {
+                observer.observe(depth as u64, &[]);
+            }
+        })
+        .build();
+
+    let processed = meter
+        .u64_counter("hero.runs.processed")
+        .with_description("Number of Runs processed, by whether their telemetry was sent")
+        .build();
+
+    SELF_GAUGES
+        .set(vec![tasks, workers, backlog, memory, queued])
+        .unwrap_or_else(|_| panic!("Self metrics already setup"));
+
+    RUNS_PROCESSED
+        .set(processed)
+        .unwrap_or_else(|_| panic!("Self metrics already setup"));
+
+    Ok(provider)
+}
+
+/// Count a Run as having been processed, successfully or otherwise. Does
+/// nothing unless self metrics were asked for.
+pub(crate) fn record_run_processed(sent: bool) {
+    if let Some(counter) = RUNS_PROCESSED.get() {
+        let outcome = if sent { "sent" } else { "failed" };
+        counter.add(1, &[KeyValue::new("outcome", outcome)]);
+    }
+}
+
+/// Count a Run as having been given up on.
+pub(crate) fn record_dead_letter() {
+    if let Some(counter) = DEAD_LETTERS.get() {
+        counter.add(1, &[]);
+    }
+}
+
+/// Count a response from GitHub as having warned of a deprecation.
+pub(crate) fn record_api_deprecation(header: &str) {
+    if let Some(counter) = API_DEPRECATIONS.get() {
+        counter.add(1, &[KeyValue::new("header", header.to_string())]);
+    }
+}
+
+/// The resident set size of this process, as reported by the kernel. Only
+/// available on Linux.
+fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+
+    let line = status
+        .lines()
+        .find(|line| line.starts_with("VmRSS:"))?;
+
+    let kilobytes = line
+        .split_whitespace()
+        .nth(1)?
+        .parse::<u64>()
+        .ok()?;
+
+    Some(kilobytes * 1024)
+}
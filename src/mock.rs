@@ -0,0 +1,472 @@
+//! A stand-in for the GitHub API, serving canned Runs, Jobs, logs, and test
+//! reports in the same shape as the real thing. Pointing **action-hero** at this (with
+//! HERO_GITHUB_API_URL) allows the query and listen paths to be exercised,
+//! for demonstrations or end-to-end testing, without GitHub being involved.
+//!
+//! Every repository has the same three Runs of whichever workflow is asked
+//! for, of which the middle one fails. Jobs are served for any other Run ID
+//! too (as named by a webhook, say), failing if the ID is even.
+
+use std::io::Write;
+use std::net::Ipv4Addr;
+use std::sync::OnceLock;
+
+use axum::extract::{Path, Query};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::{Json, Router, routing::get};
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use time::format_description::well_known::Rfc3339;
+use time::{Duration, OffsetDateTime};
+use tracing::info;
+
+/// The canned Runs are placed relative to when the server was started, so
+/// that they appear recent but stay the same from one request to the next.
+static EPOCH: OnceLock<OffsetDateTime> = OnceLock::new();
+
+/// Where the server can be reached, for the URLs in its responses which
+/// point back at itself.
+static BASE_URL: OnceLock<String> = OnceLock::new();
+
+const RUN_IDS: [u64; 3] = [1001, 1002, 1003];
+
+const HEAD_SHA: &str = "4f9a2c1d3b5e7f8091a2b3c4d5e6f708192a3b4c";
+
+const WORKFLOW_SOURCE: &str = "\
+name: CI
+on: [push]
+jobs:
+  build:
+    name: Build
+    runs-on: ubuntu-latest
+    steps:
+      - uses: actions/checkout@v4
+      - run: make
+  test:
+    name: Test
+    needs: build
+    runs-on: ubuntu-latest
+    steps:
+      - uses: actions/checkout@v4
+      - run: make test
+";
+
+pub(crate) async fn run_mock_server(host: Ipv4Addr, port: u16) -> anyhow::Result<()> {
+    EPOCH
+        .set(OffsetDateTime::now_utc() - Duration::hours(1))
+        .ok();
+    BASE_URL
+        .set(format!("http://localhost:{}", port))
+        .ok();
+
+    let router = Router::new()
+        .route("/rate_limit", get(rate_limit))
+        .route("/meta", get(meta))
+        .route("/repos/{owner}/{repo}/actions/workflows", get(workflows))
+        .route(
+            "/repos/{owner}/{repo}/actions/workflows/{workflow}",
+            get(workflow),
+        )
+        .route(
+            "/repos/{owner}/{repo}/actions/workflows/{workflow}/runs",
+            get(runs),
+        )
+        .route("/repos/{owner}/{repo}/actions/runs/{run_id}", get(run))
+        .route(
+            "/repos/{owner}/{repo}/actions/runs/{run_id}/attempts/{attempt}",
+            get(run_attempt),
+        )
+        .route(
+            "/repos/{owner}/{repo}/actions/runs/{run_id}/jobs",
+            get(jobs),
+        )
+        .route(
+            "/repos/{owner}/{repo}/actions/jobs/{job_id}/logs",
+            get(logs),
+        )
+        .route(
+            "/repos/{owner}/{repo}/actions/runs/{run_id}/artifacts",
+            get(artifacts),
+        )
+        .route(
+            "/repos/{owner}/{repo}/actions/artifacts/{artifact_id}/zip",
+            get(artifact_archive),
+        )
+        .route("/repos/{owner}/{repo}/contents/{*path}", get(contents))
+        .route("/repos/{owner}/{repo}/deployments", get(empty))
+        .route(
+            "/repos/{owner}/{repo}/actions/runs/{run_id}/approvals",
+            get(empty),
+        )
+        .route(
+            "/repos/{owner}/{repo}/actions/runs/{run_id}/pending_deployments",
+            get(empty),
+        )
+        .route(
+            "/repos/{owner}/{repo}/check-runs/{job_id}/annotations",
+            get(empty),
+        )
+        .fallback(not_found);
+
+    info!("Mock GitHub API listening on {:?}:{}", host, port);
+    info!(
+        "Set HERO_GITHUB_API_URL=http://localhost:{} to use it",
+        port
+    );
+    let address = (host, port);
+
+    let listener = tokio::net::TcpListener::bind(address).await?;
+    axum::serve(listener, router).await?;
+
+    Ok(())
+}
+
+/// A time the given number of seconds after the epoch, as GitHub formats
+/// timestamps.
+fn at(seconds: i64) -> String {
+    let epoch = EPOCH
+        .get()
+        .copied()
+        .unwrap_or_else(OffsetDateTime::now_utc);
+
+    (epoch + Duration::seconds(seconds))
+        .replace_nanosecond(0)
+        .unwrap()
+        .format(&Rfc3339)
+        .unwrap()
+}
+
+/// Where each Run starts, in seconds from the epoch. The canned Runs are 20
+/// minutes apart; any other Run is taken to have started with the first.
+fn run_start(run_id: u64) -> i64 {
+    match RUN_IDS
+        .iter()
+        .position(|id| *id == run_id)
+    {
+        Some(index) => index as i64 * 1200,
+        None => 0,
+    }
+}
+
+fn run_fails(run_id: u64) -> bool {
+    run_id.is_multiple_of(2)
+}
+
+/// The steps of a Job, each as a name and a duration in seconds.
+fn job_plan(name: &str) -> [(&'static str, i64); 4] {
+    match name {
+        "Build" => [
+            ("Set up job", 2),
+            ("Run actions/checkout@v4", 3),
+            ("Run make", 90),
+            ("Complete job", 1),
+        ],
+        _ => [
+            ("Set up job", 2),
+            ("Run actions/checkout@v4", 3),
+            ("Run make test", 120),
+            ("Complete job", 1),
+        ],
+    }
+}
+
+/// The Jobs of a Run, and the time (in seconds from the epoch) that the last
+/// of them finished.
+fn canned_jobs(owner: &str, repo: &str, run_id: u64) -> (Vec<Value>, i64) {
+    let mut jobs = Vec::new();
+    let mut clock = run_start(run_id) + 10;
+
+    for (index, name) in ["Build", "Test"]
+        .into_iter()
+        .enumerate()
+    {
+        let job_id = run_id * 10 + index as u64 + 1;
+        let fails = name == "Test" && run_fails(run_id);
+
+        let queued = clock;
+        clock += 5;
+        let started = clock;
+
+        let steps: Vec<Value> = job_plan(name)
+            .iter()
+            .enumerate()
+            .map(|(number, (step, duration))| {
+                let conclusion = if fails && number == 2 {
+                    "failure"
+                } else if fails && number == 3 {
+                    "skipped"
+                } else {
+                    "success"
+                };
+                let step_start = clock;
+                clock += duration;
+                json!({
+                    "name": step,
+                    "status": "completed",
+                    "conclusion": conclusion,
+                    "number": number + 1,
+                    "started_at": at(step_start),
+                    "completed_at": at(clock),
+                })
+            })
+            .collect();
+
+        jobs.push(json!({
+            "id": job_id,
+            "run_id": run_id,
+            "name": name,
+            "head_branch": "main",
+            "head_sha": HEAD_SHA,
+            "status": "completed",
+            "conclusion": if fails { "failure" } else { "success" },
+            "created_at": at(queued),
+            "started_at": at(started),
+            "completed_at": at(clock),
+            "steps": steps,
+            "html_url": format!(
+                "https://github.com/{}/{}/actions/runs/{}/job/{}",
+                owner, repo, run_id, job_id
+            ),
+            "runner_name": "GitHub Actions 2",
+            "labels": ["ubuntu-latest"],
+        }));
+
+        clock += 5;
+    }
+
+    (jobs, clock)
+}
+
+fn canned_run(owner: &str, repo: &str, workflow: &str, run_id: u64) -> Value {
+    let (_, finished) = canned_jobs(owner, repo, run_id);
+    let number = run_id - RUN_IDS[0] + 1;
+
+    json!({
+        "id": run_id,
+        "name": "CI",
+        "display_title": format!("Change number {}", number),
+        "run_number": number,
+        "run_attempt": 1,
+        "event": "push",
+        "status": "completed",
+        "conclusion": if run_fails(run_id) { "failure" } else { "success" },
+        "head_branch": "main",
+        "head_sha": HEAD_SHA,
+        "path": format!(".github/workflows/{}", workflow),
+        "created_at": at(run_start(run_id)),
+        "updated_at": at(finished),
+        "run_started_at": at(run_start(run_id)),
+        "html_url": format!("https://github.com/{}/{}/actions/runs/{}", owner, repo, run_id),
+        "actor": { "login": "octocat" },
+        "triggering_actor": { "login": "octocat" },
+        "referenced_workflows": [],
+    })
+}
+
+async fn rate_limit() -> Json<Value> {
+    Json(json!({ "resources": {}, "rate": { "limit": 5000, "remaining": 5000 } }))
+}
+
+/// Webhooks from a mock GitHub come from the local machine.
+async fn meta() -> Json<Value> {
+    Json(json!({ "hooks": ["127.0.0.0/8", "::1/128"] }))
+}
+
+async fn workflows(Path((_owner, _repo)): Path<(String, String)>) -> Json<Value> {
+    Json(json!({
+        "total_count": 1,
+        "workflows": [{ "id": 1, "name": "CI", "path": ".github/workflows/ci.yml", "state": "active" }],
+    }))
+}
+
+async fn workflow(Path((_owner, _repo, workflow)): Path<(String, String, String)>) -> Json<Value> {
+    // asked for by number, the workflow is the one in ci.yml
+    let file = if workflow
+        .chars()
+        .all(|c| c.is_ascii_digit())
+    {
+        "ci.yml".to_string()
+    } else {
+        workflow
+    };
+
+    Json(json!({
+        "id": 1,
+        "name": "CI",
+        "path": format!(".github/workflows/{}", file),
+        "state": "active",
+    }))
+}
+
+#[derive(Deserialize)]
+struct Paging {
+    per_page: Option<usize>,
+    page: Option<usize>,
+}
+
+async fn runs(
+    Path((owner, repo, workflow)): Path<(String, String, String)>,
+    Query(paging): Query<Paging>,
+) -> Json<Value> {
+    let per_page = paging
+        .per_page
+        .unwrap_or(30);
+    let page = paging
+        .page
+        .unwrap_or(1)
+        .max(1);
+
+    // newest first, as GitHub returns them
+    let runs: Vec<Value> = RUN_IDS
+        .iter()
+        .rev()
+        .skip((page - 1) * per_page)
+        .take(per_page)
+        .map(|run_id| canned_run(&owner, &repo, &workflow, *run_id))
+        .collect();
+
+    Json(json!({
+        "total_count": RUN_IDS.len(),
+        "workflow_runs": runs,
+    }))
+}
+
+async fn run(Path((owner, repo, run_id)): Path<(String, String, u64)>) -> Json<Value> {
+    Json(canned_run(&owner, &repo, "ci.yml", run_id))
+}
+
+async fn run_attempt(
+    Path((owner, repo, run_id, _attempt)): Path<(String, String, u64, u64)>,
+) -> Json<Value> {
+    Json(canned_run(&owner, &repo, "ci.yml", run_id))
+}
+
+async fn jobs(Path((owner, repo, run_id)): Path<(String, String, u64)>) -> Json<Value> {
+    let (jobs, _) = canned_jobs(&owner, &repo, run_id);
+
+    Json(json!({
+        "total_count": jobs.len(),
+        "jobs": jobs,
+    }))
+}
+
+async fn logs(Path((_owner, _repo, job_id)): Path<(String, String, u64)>) -> String {
+    let run_id = job_id / 10;
+    let fails = job_id % 10 == 2 && run_fails(run_id);
+
+    let start = run_start(run_id);
+    let mut lines = vec![
+        format!("{} ##[group]Run make test", at(start)),
+        format!("{} cargo test --workspace", at(start)),
+        format!("{} ##[endgroup]", at(start)),
+        format!("{} test result: ok. 41 passed; 0 failed", at(start + 60)),
+    ];
+    if fails {
+        lines.push(format!(
+            "{} error: test failed, to rerun pass `--lib`",
+            at(start + 120)
+        ));
+        lines.push(format!(
+            "{} ##[error]Process completed with exit code 101.",
+            at(start + 120)
+        ));
+    }
+
+    lines.join("\n") + "\n"
+}
+
+/// Each Run's Test Job uploads its results as a "test-results" Artifact,
+/// whose ID is that of the Job.
+async fn artifacts(Path((owner, repo, run_id)): Path<(String, String, u64)>) -> Json<Value> {
+    let (_, finished) = canned_jobs(&owner, &repo, run_id);
+    let job_id = run_id * 10 + 2;
+
+    Json(json!({
+        "total_count": 1,
+        "artifacts": [{
+            "id": job_id,
+            "name": "test-results",
+            "size_in_bytes": 1024,
+            "archive_download_url": format!(
+                "{}/repos/{}/{}/actions/artifacts/{}/zip",
+                BASE_URL
+                    .get()
+                    .map(String::as_str)
+                    .unwrap_or_default(),
+                owner,
+                repo,
+                job_id
+            ),
+            "expired": false,
+            // uploaded just before the Job finished
+            "created_at": at(finished - 10),
+        }],
+    }))
+}
+
+async fn artifact_archive(
+    Path((_owner, _repo, artifact_id)): Path<(String, String, u64)>,
+) -> Response {
+    let run_id = artifact_id / 10;
+
+    let failure = if run_fails(run_id) {
+        "<failure message=\"assertion failed: left == right\">thread 'parser::tests::nested' panicked</failure>"
+    } else {
+        ""
+    };
+    let report = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<testsuites>
+  <testsuite name=\"hero\" tests=\"3\">
+    <testcase classname=\"parser::tests\" name=\"flat\" time=\"0.5\"/>
+    <testcase classname=\"parser::tests\" name=\"nested\" time=\"1.25\">{}</testcase>
+    <testcase classname=\"parser::tests\" name=\"empty\" time=\"0\"><skipped/></testcase>
+  </testsuite>
+</testsuites>
+",
+        failure
+    );
+
+    let mut archive = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options =
+        zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    let written = archive
+        .start_file("junit.xml", options)
+        .and_then(|()| {
+            archive
+                .write_all(report.as_bytes())
+                .map_err(zip::result::ZipError::from)
+        })
+        .and_then(|()| archive.finish());
+
+    match written {
+        Ok(cursor) => cursor
+            .into_inner()
+            .into_response(),
+        Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+    }
+}
+
+async fn contents(Path((_owner, _repo, _path)): Path<(String, String, String)>) -> Json<Value> {
+    Json(json!({
+        "type": "file",
+        "encoding": "base64",
+        "sha": "8d2e1f0a7b6c5d4e3f2a1b0c9d8e7f6a5b4c3d2e",
+        "content": BASE64_STANDARD.encode(WORKFLOW_SOURCE),
+    }))
+}
+
+async fn empty() -> Json<Value> {
+    Json(json!([]))
+}
+
+async fn not_found() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "message": "Not Found" })),
+    )
+        .into_response()
+}
@@ -0,0 +1,89 @@
+//! Every vendor's OTLP ingest has its own endpoint and its own idea of which
+//! header carries the API key (and how). Rather than have people work that
+//! out, `--preset` fills in the details for the backends we know about, with
+//! the key read from an environment variable or credential.
+
+use anyhow::{Result, anyhow};
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+
+use crate::lookup_secret;
+
+pub(crate) const PRESETS: [&str; 3] = ["honeycomb", "grafana-cloud", "newrelic"];
+
+/// What a preset supplies. The endpoint is the base URL of the vendor's
+/// ingest; the path of each signal is appended to it when exporting over
+/// HTTP.
+pub(crate) struct Preset {
+    pub(crate) endpoint: String,
+    pub(crate) protocol: String,
+    pub(crate) headers: Vec<(String, String)>,
+}
+
+pub(crate) fn load_preset(name: &str) -> Result<Preset> {
+    match name {
+        "honeycomb" => {
+            let key = read_secret("HONEYCOMB_API_KEY", "honeycomb-api-key")?;
+
+            Ok(Preset {
+                endpoint: "https://api.honeycomb.io".to_string(),
+                protocol: "http/protobuf".to_string(),
+                headers: vec![("x-honeycomb-team".to_string(), key)],
+            })
+        }
+        "grafana-cloud" => {
+            // the gateway is specific to the zone the Grafana Cloud stack is
+            // in, and authentication is Basic with the stack's instance ID as
+            // the username.
+            let zone = read_secret("GRAFANA_CLOUD_ZONE", "grafana-cloud-zone")?;
+            let instance = read_secret("GRAFANA_CLOUD_INSTANCE_ID", "grafana-cloud-instance-id")?;
+            let key = read_secret("GRAFANA_CLOUD_API_KEY", "grafana-cloud-api-key")?;
+
+            let credentials = BASE64_STANDARD.encode(format!("{}:{}", instance, key));
+
+            Ok(Preset {
+                endpoint: format!("https://otlp-gateway-{}.grafana.net/otlp", zone),
+                protocol: "http/protobuf".to_string(),
+                headers: vec![(
+                    "authorization".to_string(),
+                    format!("Basic {}", credentials),
+                )],
+            })
+        }
+        "newrelic" => {
+            let key = read_secret("NEW_RELIC_LICENSE_KEY", "new-relic-license-key")?;
+
+            // accounts in the EU datacenter have their own endpoint
+            let endpoint = match std::env::var("NEW_RELIC_REGION")
+                .ok()
+                .as_deref()
+            {
+                Some("eu") | Some("EU") => "https://otlp.eu01.nr-data.net",
+                _ => "https://otlp.nr-data.net",
+            };
+
+            Ok(Preset {
+                endpoint: endpoint.to_string(),
+                protocol: "http/protobuf".to_string(),
+                headers: vec![("api-key".to_string(), key)],
+            })
+        }
+        _ => Err(anyhow!(
+            "Unknown preset \"{}\"; must be one of {}",
+            name,
+            PRESETS.join(", ")
+        )),
+    }
+}
+
+/// Read a value from the given environment variable or, failing that, from
+/// the named credential in the CREDENTIALS_DIRECTORY.
+fn read_secret(variable: &str, credential: &str) -> Result<String> {
+    lookup_secret(variable, credential)?.ok_or_else(|| {
+        anyhow!(
+            "Either the {} environment variable or a {} credential must be set.",
+            variable,
+            credential
+        )
+    })
+}
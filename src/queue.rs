@@ -0,0 +1,311 @@
+//! A queue of Runs received by webhook which are waiting to be processed,
+//! kept on disk so that they survive the listener being restarted. Each Run
+//! is a file in the queue directory, which is removed once its telemetry has
+//! been sent; anything left behind is picked up again at startup.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::{debug, info, warn};
+
+use crate::deadletter;
+use crate::github::{self, Config, WorkflowRun};
+use crate::history;
+use crate::webhook;
+
+static QUEUE_DIRECTORY: OnceLock<PathBuf> = OnceLock::new();
+
+static ARRIVED: Notify = Notify::const_new();
+
+// Runs which failed are tried again after this long, or sooner if another
+// Run arrives in the meantime.
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+// A Run which has been sitting in the queue this long has failed every time
+// it has been tried, and GitHub will have long since stopped keeping the logs
+// we would need anyway.
+const EXPIRY: Duration = Duration::from_secs(7 * 24 * 3600);
+
+// When there is somewhere to put them, Runs which have failed this many
+// times, over at least this long, are moved to the dead-letter directory
+// rather than being tried again. Requiring both means a burst of arrivals
+// (each of which prompts a retry) can't use up the attempts, and that an
+// outage of the GitHub API has to be a long one before Runs are given up on.
+const MAX_ATTEMPTS: u32 = 5;
+const GIVE_UP_AFTER: Duration = Duration::from_secs(3600);
+
+pub(crate) fn set_queue_directory(directory: &str) -> Result<()> {
+    let path = Path::new(directory);
+    if !path.exists() {
+        std::fs::create_dir_all(path)?;
+    }
+
+    QUEUE_DIRECTORY
+        .set(path.to_path_buf())
+        .unwrap();
+
+    Ok(())
+}
+
+/// Whether Runs received are to be queued rather than processed while GitHub
+/// waits for a response.
+pub(crate) fn is_enabled() -> bool {
+    QUEUE_DIRECTORY
+        .get()
+        .is_some()
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct QueuedRun {
+    pub(crate) host: String,
+    pub(crate) owner: String,
+    pub(crate) repository: String,
+    pub(crate) workflow: String,
+    pub(crate) run: Value,
+    #[serde(default)]
+    pub(crate) inputs: Map<String, Value>,
+    #[serde(default)]
+    pub(crate) delivery: Option<String>, // the webhook delivery it came in
+}
+
+/// Write the Run to the queue. The file is written under a temporary name
+/// and then renamed so that the worker never sees a partial file.
+pub(crate) async fn enqueue(config: &Config, run: &WorkflowRun) -> Result<()> {
+    let directory = QUEUE_DIRECTORY.wait();
+
+    let item = QueuedRun {
+        host: config
+            .host
+            .clone(),
+        owner: config
+            .owner
+            .clone(),
+        repository: config
+            .repository
+            .clone(),
+        workflow: config
+            .workflow
+            .clone(),
+        run: run
+            .payload
+            .clone(),
+        inputs: run
+            .inputs
+            .clone(),
+        delivery: webhook::current_delivery(),
+    };
+
+    let name = format!(
+        "{}-{}-{}",
+        history::host_directory(&config.host),
+        run.run_id,
+        run.run_attempt
+    );
+    let temporary = directory.join(format!(".{}.tmp", name));
+    let path = directory.join(format!("{}.json", name));
+
+    tokio::fs::write(&temporary, serde_json::to_vec(&item)?).await?;
+    tokio::fs::rename(&temporary, &path).await?;
+
+    info!("Queued Run {}", run.run_id);
+    ARRIVED.notify_one();
+
+    Ok(())
+}
+
+/// Remove Runs which have expired, along with any partially written files
+/// left by a listener which died while writing them.
+pub(crate) fn collect_garbage() -> Result<()> {
+    let directory = QUEUE_DIRECTORY.wait();
+
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        let path = entry.path();
+        let age = entry
+            .metadata()?
+            .modified()?
+            .elapsed()
+            .unwrap_or_default();
+
+        let partial = path
+            .extension()
+            .is_some_and(|extension| extension == "tmp");
+        let expired = path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+            && age > EXPIRY;
+
+        if partial {
+            info!("Removing partially written {}", path.display());
+            std::fs::remove_file(&path)?;
+        } else if expired && deadletter::is_enabled() {
+            deadletter::bury(&path, &anyhow::anyhow!("Expired while waiting in queue"), 0)?;
+        } else if expired {
+            warn!("Removing expired {}", path.display());
+            std::fs::remove_file(&path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Process whatever is in the queue, then wait for more. This runs for as
+/// long as the listener does.
+pub(crate) async fn run_worker() {
+    let directory = QUEUE_DIRECTORY.wait();
+
+    // how many times each Run has failed, and when it first did
+    let mut failures: HashMap<PathBuf, (u32, Instant)> = HashMap::new();
+
+    loop {
+        match pending(directory) {
+            Ok(paths) => {
+                for path in paths {
+                    let error = match process_item(&path).await {
+                        Ok(()) => {
+                            failures.remove(&path);
+                            continue;
+                        }
+                        Err(error) => error,
+                    };
+
+                    let (attempts, since) = failures
+                        .entry(path.clone())
+                        .or_insert((0, Instant::now()));
+                    *attempts += 1;
+
+                    let hopeless = match error {
+                        Failure::Permanent(_) => true,
+                        Failure::Transient(_) => {
+                            *attempts >= MAX_ATTEMPTS && since.elapsed() >= GIVE_UP_AFTER
+                        }
+                    };
+
+                    let error = error.into_inner();
+
+                    if hopeless && deadletter::is_enabled() {
+                        if let Err(error) = deadletter::bury(&path, &error, *attempts) {
+                            warn!("Unable to move {} aside: {}", path.display(), error);
+                        }
+                        failures.remove(&path);
+                    } else {
+                        warn!(
+                            "Unable to process {}, will try again: {}",
+                            path.display(),
+                            error
+                        );
+                    }
+                }
+            }
+            Err(error) => warn!("Unable to read queue directory: {}", error),
+        }
+
+        let _ = tokio::time::timeout(RETRY_INTERVAL, ARRIVED.notified()).await;
+    }
+}
+
+/// How many Runs are waiting in the queue.
+pub(crate) fn depth() -> Option<usize> {
+    let directory = QUEUE_DIRECTORY.get()?;
+
+    pending(directory)
+        .ok()
+        .map(|paths| paths.len())
+}
+
+/// The files in the queue, oldest first.
+fn pending(directory: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries = Vec::new();
+
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            let modified = entry
+                .metadata()?
+                .modified()?;
+            entries.push((modified, path));
+        }
+    }
+
+    entries.sort();
+
+    Ok(entries
+        .into_iter()
+        .map(|(_, path)| path)
+        .collect())
+}
+
+/// Something going wrong with the payload itself is never going to come
+/// right by trying again; anything else might.
+enum Failure {
+    Permanent(anyhow::Error),
+    Transient(anyhow::Error),
+}
+
+impl Failure {
+    fn into_inner(self) -> anyhow::Error {
+        match self {
+            Failure::Permanent(error) | Failure::Transient(error) => error,
+        }
+    }
+}
+
+async fn process_item(path: &Path) -> Result<(), Failure> {
+    debug!(?path);
+
+    let bytes = std::fs::read(path).map_err(|error| Failure::Transient(error.into()))?;
+    let item: QueuedRun =
+        serde_json::from_slice(&bytes).map_err(|error| Failure::Permanent(error.into()))?;
+
+    submit_item(item).await?;
+
+    std::fs::remove_file(path).map_err(|error| Failure::Transient(error.into()))?;
+
+    Ok(())
+}
+
+/// Read a Run in the form it is kept in the queue.
+pub(crate) fn read_item(path: &Path) -> Result<QueuedRun> {
+    let bytes = std::fs::read(path)?;
+    let item = serde_json::from_slice(&bytes)?;
+
+    Ok(item)
+}
+
+/// Process a Run that was previously queued, outside of the worker.
+pub(crate) async fn resubmit_item(item: QueuedRun) -> Result<()> {
+    submit_item(item)
+        .await
+        .map_err(Failure::into_inner)
+}
+
+/// Process a Run which had been queued, sending its telemetry.
+async fn submit_item(item: QueuedRun) -> Result<(), Failure> {
+    let config = Config {
+        host: item.host,
+        owner: item.owner,
+        repository: item.repository,
+        workflow: item.workflow,
+        devel: false,
+    };
+
+    let mut run = github::decode_run(item.run).map_err(|error| Failure::Permanent(error.into()))?;
+    run.inputs = item.inputs;
+
+    let client = github::setup_api_client(&config).map_err(Failure::Transient)?;
+
+    webhook::with_delivery(item.delivery, crate::submit_run(&config, &client, &run))
+        .await
+        .map_err(Failure::Transient)?;
+
+    Ok(())
+}
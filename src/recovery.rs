@@ -0,0 +1,160 @@
+//! Recover webhook deliveries which didn't reach us, whether because the
+//! listener was down at the time or because processing failed. GitHub keeps
+//! a record of each attempt a webhook makes to deliver an event, so we check
+//! that periodically and ask for any which failed to be sent again.
+
+use anyhow::{Result, anyhow};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::get_api_token;
+use crate::github::{self, GITHUB_HOST, HookDelivery};
+
+/// A webhook to watch, on either a repository or an organization.
+pub(crate) struct RecoveryTarget {
+    owner: String,
+    repository: Option<String>,
+    hook_id: u64,
+}
+
+/// Targets are given as "owner/repo:ID" or, for an organization's webhook,
+/// as "owner:ID".
+pub(crate) fn parse_target(value: &str) -> Result<RecoveryTarget> {
+    let (target, id) = value
+        .rsplit_once(':')
+        .ok_or_else(|| {
+            anyhow!(
+                "Webhook \"{}\" must be in the form \"owner/repo:ID\"",
+                value
+            )
+        })?;
+
+    let hook_id = id
+        .parse::<u64>()
+        .map_err(|_| anyhow!("Invalid webhook ID \"{}\"", id))?;
+
+    let (owner, repository) = match target.split_once('/') {
+        Some((owner, repository)) => (owner.to_string(), Some(repository.to_string())),
+        None => (target.to_string(), None),
+    };
+
+    Ok(RecoveryTarget {
+        owner,
+        repository,
+        hook_id,
+    })
+}
+
+// Only these events lead to anything being sent; there's no point having
+// GitHub redeliver the others.
+const RECOVERED_EVENTS: &[&str] = &["workflow_run", "workflow_dispatch"];
+
+// A delivery which keeps failing is presumably never going to work.
+const MAX_REDELIVERIES: u32 = 3;
+
+/// Check the given webhooks for failed deliveries, now and then again every
+/// interval. This runs for as long as the listener does.
+pub(crate) async fn run_recovery(targets: Vec<RecoveryTarget>, interval: Duration) {
+    let mut requested: HashMap<String, u32> = HashMap::new();
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        ticker
+            .tick()
+            .await;
+
+        for target in &targets {
+            if let Err(error) = check_deliveries(target, &mut requested).await {
+                warn!(
+                    "Unable to check deliveries of webhook {}: {}",
+                    target.hook_id, error
+                );
+            }
+        }
+    }
+}
+
+async fn check_deliveries(
+    target: &RecoveryTarget,
+    requested: &mut HashMap<String, u32>,
+) -> Result<()> {
+    let token = get_api_token(GITHUB_HOST, &target.owner)
+        .ok_or_else(|| anyhow!("No GitHub token available for owner {}", target.owner))?;
+    let client = github::build_api_client(GITHUB_HOST, token)?;
+
+    let hook_url = format!(
+        "{}/{}",
+        github::hooks_url(
+            GITHUB_HOST,
+            &target.owner,
+            target
+                .repository
+                .as_deref()
+        ),
+        target.hook_id
+    );
+
+    let deliveries = github::retrieve_hook_deliveries(&client, &hook_url).await?;
+
+    // Each attempt to deliver an event is listed separately, with all the
+    // attempts sharing the event's GUID. The event has only gone missing if
+    // none of them succeeded, in which case we redeliver the latest attempt.
+
+    let mut delivered: HashSet<&str> = HashSet::new();
+    let mut failed: HashMap<&str, &HookDelivery> = HashMap::new();
+
+    for delivery in &deliveries {
+        if !RECOVERED_EVENTS.contains(
+            &delivery
+                .event
+                .as_str(),
+        ) {
+            continue;
+        }
+
+        if delivery.succeeded() {
+            delivered.insert(&delivery.guid);
+            continue;
+        }
+
+        let latest = failed
+            .entry(&delivery.guid)
+            .or_insert(delivery);
+        if delivery.delivered_at > latest.delivered_at {
+            *latest = delivery;
+        }
+    }
+
+    for (guid, delivery) in failed {
+        if delivered.contains(guid) {
+            continue;
+        }
+
+        let count = requested
+            .entry(guid.to_string())
+            .or_insert(0);
+        if *count >= MAX_REDELIVERIES {
+            continue;
+        }
+        *count += 1;
+
+        info!(
+            "Delivery {} of {} event failed with status {}",
+            guid, delivery.event, delivery.status_code
+        );
+
+        if let Err(error) = github::request_redelivery(&client, &hook_url, delivery).await {
+            warn!("{}", error);
+        }
+    }
+
+    // once GitHub has forgotten about a delivery so can we
+    requested.retain(|guid, _| {
+        deliveries
+            .iter()
+            .any(|delivery| &delivery.guid == guid)
+    });
+
+    Ok(())
+}
@@ -0,0 +1,132 @@
+//! Scrub secrets from text taken out of Job logs before it is attached to
+//! spans. GitHub masks the secrets it knows about, but not everything that
+//! ends up in a log is a registered secret, and traces are widely readable.
+//!
+//! Our own diagnostic output gets the same treatment: the GitHub tokens and
+//! OTLP header values we were given are registered here as they are read,
+//! and everything logged is scrubbed of them (and of anything matching the
+//! built-in patterns) on its way out. An error from a request can otherwise
+//! carry a URL or header into journald.
+
+use regex::Regex;
+use std::io::Write;
+use std::sync::{LazyLock, RwLock};
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::settings::get_settings;
+
+const REPLACEMENT: &str = "[REDACTED]";
+
+// Patterns for things that look like credentials regardless of context.
+static BUILTIN: LazyLock<Vec<Regex>> = LazyLock::new(|| {
+    [
+        // GitHub personal access, OAuth, app, and refresh tokens
+        r"\b(ghp|gho|ghu|ghs|ghr)_[A-Za-z0-9]{36,}\b",
+        r"\bgithub_pat_[A-Za-z0-9_]{22,}\b",
+        // AWS access key IDs and secret keys given as assignments
+        r"\b(AKIA|ASIA)[0-9A-Z]{16}\b",
+        r"(?i)(aws_secret_access_key|aws_session_token)\s*[=:]\s*\S+",
+        // Authorization headers, whatever the scheme
+        r"(?i)(proxy-)?authorization:\s*\S+(\s+\S+)?",
+        // Slack tokens and JSON Web Tokens
+        r"\bxox[abprs]-[A-Za-z0-9-]{10,}\b",
+        r"\beyJ[A-Za-z0-9_-]{10,}\.[A-Za-z0-9_-]{10,}\.[A-Za-z0-9_-]{10,}\b",
+    ]
+    .iter()
+    .map(|pattern| Regex::new(pattern).unwrap())
+    .collect()
+});
+
+/// Replace anything matching the built-in patterns or the additional
+/// patterns supplied by the user with a marker.
+pub(crate) fn redact(text: &str) -> String {
+    let settings = get_settings();
+
+    BUILTIN
+        .iter()
+        .chain(
+            settings
+                .redactions
+                .iter(),
+        )
+        .fold(text.to_string(), |text, pattern| {
+            pattern
+                .replace_all(&text, REPLACEMENT)
+                .into_owned()
+        })
+}
+
+// Secrets we have been given. Tokens are re-read when the listener reloads,
+// so this only ever grows; a revoked token is still worth scrubbing.
+static SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+// Anything shorter than this is too likely to turn up in ordinary text to be
+// worth scrubbing, and no real credential is this short.
+const MINIMUM_SECRET_LENGTH: usize = 12;
+
+/// Note a value which must never appear in our diagnostic output.
+pub(crate) fn register_secret(secret: &str) {
+    let secret = secret.trim();
+    if secret.len() < MINIMUM_SECRET_LENGTH {
+        return;
+    }
+
+    let mut secrets = SECRETS
+        .write()
+        .unwrap();
+
+    if !secrets
+        .iter()
+        .any(|existing| existing == secret)
+    {
+        secrets.push(secret.to_string());
+    }
+}
+
+/// Replace any registered secret, or anything matching the built-in
+/// patterns, with a marker. Unlike redact() the user's patterns aren't
+/// applied; they describe what is sensitive in workflows' logs, not ours.
+pub(crate) fn scrub(text: &str) -> String {
+    let secrets = SECRETS
+        .read()
+        .unwrap();
+
+    let text = secrets
+        .iter()
+        .fold(text.to_string(), |text, secret| {
+            text.replace(secret.as_str(), REPLACEMENT)
+        });
+
+    BUILTIN
+        .iter()
+        .fold(text, |text, pattern| {
+            pattern
+                .replace_all(&text, REPLACEMENT)
+                .into_owned()
+        })
+}
+
+/// Writes log output to stdout, as the fmt subscriber would by default, but
+/// scrubbed of secrets first. The subscriber formats each event in full
+/// before writing it, so a secret won't be split across writes.
+pub(crate) struct ScrubbingWriter;
+
+impl<'a> MakeWriter<'a> for ScrubbingWriter {
+    type Writer = ScrubbingWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        ScrubbingWriter
+    }
+}
+
+impl Write for ScrubbingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        std::io::stdout().write_all(scrub(&text).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stdout().flush()
+    }
+}
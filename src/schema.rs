@@ -0,0 +1,160 @@
+//! Lenient handling of the objects GitHub sends us. GitHub adds and renames
+//! fields in its API payloads from time to time; normally a field we rely on
+//! going missing fails the whole Run. In lenient mode we fill in a fallback
+//! for anything that isn't essential, and report fields we haven't seen
+//! before, so that such a change shows up in the logs rather than as a
+//! silent gap in the traces.
+
+use serde_json::Value;
+use std::collections::HashSet;
+use std::sync::{LazyLock, Mutex};
+use tracing::{info, warn};
+
+pub(crate) enum Presence {
+    /// Without this field the object is of no use to us.
+    Required,
+    /// Absence is normal and already handled when decoding.
+    Optional,
+    /// Substitute this value, given as JSON, if the field is missing or null.
+    Fallback(&'static str),
+}
+
+use Presence::{Fallback, Optional, Required};
+
+pub(crate) const RUN_FIELDS: &[(&str, Presence)] = &[
+    ("id", Required),
+    ("actor", Fallback(r#"{"login": "unknown"}"#)),
+    ("run_number", Fallback("0")),
+    ("run_attempt", Fallback("1")),
+    ("head_branch", Fallback(r#""""#)),
+    ("head_sha", Fallback(r#""""#)),
+    ("name", Fallback(r#""""#)),
+    ("display_title", Fallback(r#""""#)),
+    ("event", Fallback(r#""""#)),
+    ("status", Fallback(r#""""#)),
+    ("conclusion", Optional),
+    ("created_at", Required),
+    ("updated_at", Required),
+    ("run_started_at", Optional),
+    ("html_url", Fallback(r#""""#)),
+    ("path", Fallback(r#""""#)),
+    ("referenced_workflows", Optional),
+];
+
+pub(crate) const JOB_FIELDS: &[(&str, Presence)] = &[
+    ("id", Required),
+    ("name", Fallback(r#""""#)),
+    ("head_branch", Fallback(r#""""#)),
+    ("status", Fallback(r#""""#)),
+    ("conclusion", Fallback(r#""""#)),
+    ("created_at", Optional),
+    ("started_at", Required),
+    ("completed_at", Required),
+    ("steps", Fallback("[]")),
+    ("html_url", Fallback(r#""""#)),
+    ("labels", Optional),
+];
+
+pub(crate) const STEP_FIELDS: &[(&str, Presence)] = &[
+    ("name", Fallback(r#""""#)),
+    ("status", Fallback(r#""""#)),
+    ("conclusion", Fallback(r#""""#)),
+    ("started_at", Required),
+    ("completed_at", Optional),
+];
+
+// The values GitHub is known to use for the status and conclusion of Runs,
+// Jobs, and Steps. Others do turn up from time to time as GitHub adds them.
+
+pub(crate) const STATUSES: &[&str] = &[
+    "requested",
+    "queued",
+    "pending",
+    "waiting",
+    "in_progress",
+    "completed",
+];
+
+pub(crate) const CONCLUSIONS: &[&str] = &[
+    "success",
+    "failure",
+    "neutral",
+    "cancelled",
+    "skipped",
+    "timed_out",
+    "action_required",
+    "stale",
+    "startup_failure",
+];
+
+// Each oddity is only worth mentioning the first time we come across it;
+// after that it would just be noise on every Run.
+static REPORTED: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+
+fn first_report(key: &str) -> bool {
+    REPORTED
+        .lock()
+        .unwrap()
+        .insert(key.to_string())
+}
+
+/// Fill in fallbacks for any missing non-essential fields of the given
+/// object, and report (once each) fields which are missing or which we don't
+/// know about. The kind is used only to name the field in the log messages.
+pub(crate) fn tolerate(kind: &str, value: &mut Value, fields: &[(&str, Presence)]) {
+    let Some(object) = value.as_object_mut() else {
+        return;
+    };
+
+    for key in object.keys() {
+        if !fields
+            .iter()
+            .any(|(name, _)| name == key)
+        {
+            let key = format!("{}.{}", kind, key);
+            if first_report(&key) {
+                info!("Unrecognized field {}", key);
+            }
+        }
+    }
+
+    for (name, presence) in fields {
+        let absent = object
+            .get(*name)
+            .is_none_or(Value::is_null);
+
+        if !absent {
+            continue;
+        }
+
+        let key = format!("{}.{}", kind, name);
+
+        match presence {
+            Required => {
+                if first_report(&key) {
+                    warn!("Missing field {}", key);
+                }
+            }
+            Optional => {}
+            Fallback(json) => {
+                if first_report(&key) {
+                    warn!("Missing field {}; using {}", key, json);
+                }
+                object.insert(name.to_string(), serde_json::from_str(json).unwrap());
+            }
+        }
+    }
+}
+
+/// Report (once each) a value we haven't seen GitHub use for the given field
+/// before. The value is still passed through into the telemetry as it is.
+pub(crate) fn check_value(kind: &str, field: &str, value: &str, known: &[&str]) {
+    if value.is_empty() || known.contains(&value) {
+        return;
+    }
+
+    let key = format!("{}.{}={}", kind, field, value);
+    if first_report(&key) {
+        warn!("Unrecognized {} {} \"{}\"", kind, field, value);
+    }
+}
@@ -0,0 +1,382 @@
+//! Options which affect how Runs are processed into telemetry. These apply
+//! regardless of whether we are answering a query or receiving webhooks, so
+//! they are held globally rather than being passed around with each Config.
+
+use anyhow::{Result, anyhow};
+use opentelemetry::trace::SpanKind;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{OnceLock, RwLock};
+
+pub(crate) struct Settings {
+    /// Whether to retrieve the logs of Jobs with failing Steps in order to
+    /// extract error messages from them.
+    pub(crate) logs: bool,
+
+    /// Whether to retrieve the logs of every Job to find out whether the
+    /// caches its Steps restored were hit.
+    pub(crate) cache_stats: bool,
+
+    /// Whether to retrieve the logs of every Job to give Steps a span for
+    /// each group their output was divided into.
+    pub(crate) log_groups: bool,
+
+    /// Which of a Run's Artifacts hold JUnit test reports to be turned into
+    /// spans for each test.
+    pub(crate) test_reports: Option<Regex>,
+
+    /// Additional patterns to be scrubbed from any text taken from logs,
+    /// over and above the built-in ones.
+    pub(crate) redactions: Vec<Regex>,
+
+    /// What to do about Steps which GitHub skipped.
+    pub(crate) skipped: SkippedSteps,
+
+    /// Whether each repository's traces are sent as a service of their own.
+    pub(crate) service_per_repository: bool,
+
+    /// Whether to send only the traces of Runs that failed.
+    pub(crate) only_failures: bool,
+
+    /// Whether to send spans for the time within a Job between its Steps.
+    pub(crate) gaps: bool,
+
+    /// Whether to tolerate fields missing from GitHub's payloads, rather than
+    /// failing the Run.
+    pub(crate) lenient: bool,
+
+    /// Which Steps to send spans for.
+    pub(crate) steps: StepFilter,
+
+    /// Additional attributes lifted from the Run and Job objects.
+    pub(crate) attributes: AttributeMappings,
+
+    /// The SpanKind to use for each layer of spans.
+    pub(crate) span_kinds: SpanKinds,
+
+    /// Template for a link to a trace in the telemetry backend's UI.
+    pub(crate) trace_url: Option<String>,
+
+    /// Whether to give each Run a new TraceId rather than the one it would
+    /// normally be assigned, so that it is sent again as a separate trace.
+    pub(crate) fresh_trace_ids: bool,
+
+    /// Whether TraceIds begin with the Run's start time, as AWS X-Ray
+    /// requires.
+    pub(crate) xray_trace_ids: bool,
+}
+
+impl Settings {
+    /// What is switched on, for reporting which configuration a running
+    /// listener has. Patterns and mappings are only counted; redactions in
+    /// particular can say a lot about what is being kept secret.
+    pub(crate) fn summary(&self) -> serde_json::Value {
+        serde_json::json!({
+            "logs": self.logs,
+            "cache_stats": self.cache_stats,
+            "log_groups": self.log_groups,
+            "test_reports": self
+                .test_reports
+                .is_some(),
+            "redactions": self
+                .redactions
+                .len(),
+            "skipped": format!("{:?}", self.skipped).to_lowercase(),
+            "service_per_repository": self.service_per_repository,
+            "only_failures": self.only_failures,
+            "gaps": self.gaps,
+            "lenient": self.lenient,
+            "step_patterns": self
+                .steps
+                .include
+                .len()
+                + self
+                    .steps
+                    .exclude
+                    .len(),
+            "attribute_mappings": self
+                .attributes
+                .run
+                .len()
+                + self
+                    .attributes
+                    .job
+                    .len(),
+            "trace_url": self
+                .trace_url
+                .is_some(),
+            "fresh_trace_ids": self.fresh_trace_ids,
+            "xray_trace_ids": self.xray_trace_ids,
+        })
+    }
+}
+
+/// Patterns selecting which Steps are sent, so that noisy steps (such as
+/// "Set up job" or "Post *") can be left out. If there are any include
+/// patterns then only Steps matching one of them are sent; Steps matching an
+/// exclude pattern are never sent.
+#[derive(Debug, Default)]
+pub(crate) struct StepFilter {
+    pub(crate) include: Vec<Regex>,
+    pub(crate) exclude: Vec<Regex>,
+}
+
+impl StepFilter {
+    pub(crate) fn allows(&self, name: &str) -> bool {
+        let included = self
+            .include
+            .is_empty()
+            || self
+                .include
+                .iter()
+                .any(|pattern| pattern.is_match(name));
+
+        let excluded = self
+            .exclude
+            .iter()
+            .any(|pattern| pattern.is_match(name));
+
+        included && !excluded
+    }
+}
+
+/// Patterns are globs, where '*' matches any run of characters and '?' any
+/// single character, unless prefixed with "re:" in which case the remainder
+/// is a regular expression.
+pub(crate) fn parse_pattern(pattern: &str) -> Result<Regex, regex::Error> {
+    if let Some(expression) = pattern.strip_prefix("re:") {
+        return Regex::new(expression);
+    }
+
+    let mut expression = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '*' => expression.push_str(".*"),
+            '?' => expression.push('.'),
+            _ => expression.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    expression.push('$');
+
+    Regex::new(&expression)
+}
+
+/// Attributes to be added to spans, by name, along with the JSON pointer
+/// (RFC 6901) locating the value in the object GitHub sent us.
+#[derive(Debug, Default)]
+pub(crate) struct AttributeMappings {
+    pub(crate) run: Vec<(String, String)>,
+    pub(crate) job: Vec<(String, String)>,
+}
+
+// The config file is optional; anything which can be given in it is also
+// available as a command-line option, except for those things which are too
+// unwieldy to express that way. Options on the command-line add to rather
+// than replace what is in the file.
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ConfigFile {
+    pub(crate) steps: ConfigSteps,
+    pub(crate) attributes: ConfigAttributes,
+    pub(crate) profiles: BTreeMap<String, ConfigProfile>,
+}
+
+/// Options which differ from one deployment to another, such as where the
+/// telemetry is sent and which GitHub token is used, so that the same config
+/// file can be used everywhere with the profile picked by --profile.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ConfigProfile {
+    pub(crate) otlp: ConfigExporter,
+    /// A vendor preset, as with --preset.
+    pub(crate) preset: Option<String>,
+    /// The service name the workflows' telemetry is sent as, in place of
+    /// "github-actions". For some backends this chooses the dataset.
+    pub(crate) service_name: Option<String>,
+    /// The name of the environment variable or credential holding the
+    /// default GitHub token, in place of GITHUB_TOKEN.
+    pub(crate) token: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ConfigExporter {
+    pub(crate) endpoint: Option<String>,
+    pub(crate) protocol: Option<String>,
+    pub(crate) headers: BTreeMap<String, String>,
+    /// in milliseconds, as with --otlp-timeout
+    pub(crate) timeout: Option<u64>,
+    /// in milliseconds, as with --otlp-connect-timeout
+    pub(crate) connect_timeout: Option<u64>,
+    /// in milliseconds, as with --otlp-keepalive
+    pub(crate) keepalive: Option<u64>,
+    /// in milliseconds, as with --otlp-keepalive-timeout
+    pub(crate) keepalive_timeout: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ConfigSteps {
+    pub(crate) include: Vec<String>,
+    pub(crate) exclude: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ConfigAttributes {
+    pub(crate) run: BTreeMap<String, String>,
+    pub(crate) job: BTreeMap<String, String>,
+}
+
+pub(crate) fn load_config_file(path: &Path) -> Result<ConfigFile> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|error| anyhow!("Unable to read config file {}: {}", path.display(), error))?;
+
+    serde_json::from_str(&text)
+        .map_err(|error| anyhow!("Invalid config file {}: {}", path.display(), error))
+}
+
+/// Load the config file given by --config, if any, and pick out the profile
+/// named by --profile. Without a profile the result is empty, leaving the
+/// command-line and environment to determine everything.
+pub(crate) fn load_profile(
+    config: Option<&String>,
+    name: Option<&String>,
+) -> Result<ConfigProfile> {
+    let Some(name) = name else {
+        return Ok(ConfigProfile::default());
+    };
+
+    let path = config.ok_or_else(|| anyhow!("--profile requires a --config file"))?;
+
+    let mut file = load_config_file(Path::new(path))?;
+
+    file.profiles
+        .remove(name)
+        .ok_or_else(|| anyhow!("No profile \"{}\" in config file {}", name, path))
+}
+
+/// Field paths can be given either as a JSON pointer such as
+/// "/head_commit/author/email" or in dotted form as "head_commit.author.email".
+pub(crate) fn parse_field_path(path: &str) -> String {
+    if path.starts_with('/') {
+        return path.to_string();
+    }
+
+    path.split('.')
+        .map(|segment| {
+            format!(
+                "/{}",
+                segment
+                    .replace('~', "~0")
+                    .replace('/', "~1")
+            )
+        })
+        .collect()
+}
+
+impl AttributeMappings {
+    pub(crate) fn from_config(config: &ConfigAttributes) -> AttributeMappings {
+        let convert = |section: &BTreeMap<String, String>| {
+            section
+                .iter()
+                .map(|(name, path)| (name.clone(), parse_field_path(path)))
+                .collect()
+        };
+
+        AttributeMappings {
+            run: convert(&config.run),
+            job: convert(&config.job),
+        }
+    }
+}
+
+/// Everything is Internal by default, but some backends key features such as
+/// service maps off the kind of span so it can be useful to (for example)
+/// present the root span of a Run as a Server span.
+#[derive(Clone, Debug)]
+pub(crate) struct SpanKinds {
+    pub(crate) run: SpanKind,
+    pub(crate) job: SpanKind,
+    pub(crate) step: SpanKind,
+}
+
+impl Default for SpanKinds {
+    fn default() -> Self {
+        SpanKinds {
+            run: SpanKind::Internal,
+            job: SpanKind::Internal,
+            step: SpanKind::Internal,
+        }
+    }
+}
+
+pub(crate) fn parse_span_kind(value: &str) -> Option<SpanKind> {
+    match value {
+        "internal" => Some(SpanKind::Internal),
+        "server" => Some(SpanKind::Server),
+        "client" => Some(SpanKind::Client),
+        "producer" => Some(SpanKind::Producer),
+        "consumer" => Some(SpanKind::Consumer),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SkippedSteps {
+    /// Don't send spans for skipped Steps at all.
+    Omit,
+    /// Send a zero duration span for each skipped Step, marked as skipped.
+    Mark,
+    /// As for Mark, but consecutive skipped Steps share a single span.
+    Collapse,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            logs: true,
+            cache_stats: false,
+            log_groups: false,
+            test_reports: None,
+            redactions: Vec::new(),
+            skipped: SkippedSteps::Omit,
+            service_per_repository: false,
+            only_failures: false,
+            gaps: false,
+            lenient: false,
+            steps: StepFilter::default(),
+            attributes: AttributeMappings::default(),
+            span_kinds: SpanKinds::default(),
+            trace_url: None,
+            fresh_trace_ids: false,
+            xray_trace_ids: false,
+        }
+    }
+}
+
+// The listener can be told to reload its configuration, so the Settings are
+// replaceable. As with the API tokens each is leaked so that references
+// handed out by get_settings() stay valid.
+static SETTINGS: RwLock<Option<&'static Settings>> = RwLock::new(None);
+
+static DEFAULT: OnceLock<Settings> = OnceLock::new();
+
+pub(crate) fn set_settings(settings: Settings) {
+    let settings = Box::leak(Box::new(settings));
+
+    *SETTINGS
+        .write()
+        .unwrap() = Some(settings);
+}
+
+pub(crate) fn get_settings() -> &'static Settings {
+    let current = *SETTINGS
+        .read()
+        .unwrap();
+
+    current.unwrap_or_else(|| DEFAULT.get_or_init(Settings::default))
+}
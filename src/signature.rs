@@ -0,0 +1,138 @@
+//! Check that webhook deliveries were sent by GitHub. When the webhook has a
+//! secret, GitHub signs the body of each delivery with it (an HMAC-SHA256,
+//! given in the X-Hub-Signature-256 header); deliveries without a valid
+//! signature are refused before anything is done with them. Without a secret
+//! configured every delivery is accepted, as before.
+
+use anyhow::Result;
+use axum::body::Body;
+use axum::http::{Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+use tracing::{info, warn};
+
+use crate::admin::constant_time_eq;
+use crate::{lookup_secret, redaction};
+
+// GitHub caps payloads at 25 MB.
+const MAX_PAYLOAD: usize = 25 * 1024 * 1024;
+
+// The block size of SHA-256, which the key is padded (or hashed) to.
+const BLOCK_SIZE: usize = 64;
+
+static SECRET: OnceLock<String> = OnceLock::new();
+
+/// Use the given secret to verify deliveries, or failing that one from
+/// GITHUB_WEBHOOK_SECRET or a `webhook-secret` credential, the same secret
+/// `hero install-webhook` gives GitHub to sign with.
+pub(crate) fn load_webhook_secret(secret: Option<String>) -> Result<()> {
+    let secret = match secret {
+        Some(secret) => Some(secret),
+        None => lookup_secret("GITHUB_WEBHOOK_SECRET", "webhook-secret")?,
+    };
+
+    let Some(secret) = secret.filter(|secret| !secret.is_empty()) else {
+        warn!("No webhook secret configured; deliveries will not be verified");
+        return Ok(());
+    };
+
+    redaction::register_secret(&secret);
+
+    SECRET
+        .set(secret)
+        .ok();
+
+    info!("Verifying signatures of deliveries");
+
+    Ok(())
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(block.map(|byte| byte ^ 0x36));
+    inner.update(message);
+
+    let mut outer = Sha256::new();
+    outer.update(block.map(|byte| byte ^ 0x5c));
+    outer.update(inner.finalize());
+
+    outer
+        .finalize()
+        .to_vec()
+}
+
+/// The signature GitHub would send for this body, as it appears in the
+/// header.
+fn form_signature(secret: &str, body: &[u8]) -> String {
+    let digest = hmac_sha256(secret.as_bytes(), body);
+
+    let mut signature = String::from("sha256=");
+    for byte in digest {
+        signature.push_str(&format!("{:02x}", byte));
+    }
+    signature
+}
+
+/// Read the delivery's body and check its signature before letting it
+/// through. Anything other than a POST is let through.
+pub(crate) async fn verify_signature(request: Request<Body>, next: Next) -> Response {
+    let Some(secret) = SECRET.get() else {
+        return next
+            .run(request)
+            .await;
+    };
+
+    if request.method() != Method::POST {
+        return next
+            .run(request)
+            .await;
+    }
+
+    let given = request
+        .headers()
+        .get("X-Hub-Signature-256")
+        .and_then(|value| {
+            value
+                .to_str()
+                .ok()
+        })
+        .map(str::to_string);
+
+    let (parts, body) = request.into_parts();
+
+    let body = match axum::body::to_bytes(body, MAX_PAYLOAD).await {
+        Ok(body) => body,
+        Err(error) => {
+            warn!("Unable to read delivery: {}", error);
+            return (StatusCode::BAD_REQUEST, "Unable to read delivery").into_response();
+        }
+    };
+
+    let Some(given) = given else {
+        warn!("Refusing delivery without a signature");
+        return (
+            StatusCode::UNAUTHORIZED,
+            "Missing X-Hub-Signature-256 header",
+        )
+            .into_response();
+    };
+
+    let expected = form_signature(secret, &body);
+
+    if !constant_time_eq(expected.as_bytes(), given.as_bytes()) {
+        warn!("Refusing delivery with an invalid signature");
+        return (StatusCode::UNAUTHORIZED, "Invalid signature").into_response();
+    }
+
+    next.run(Request::from_parts(parts, Body::from(body)))
+        .await
+}
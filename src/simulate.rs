@@ -0,0 +1,229 @@
+//! Fabricated Runs, for finding out whether a collector (and the backend
+//! behind it) can keep up before pointing a large organization's webhooks at
+//! it. The Runs, Jobs, and Steps are built as GitHub would describe them and
+//! then sent through the same machinery as real ones, so the shape and
+//! volume of the telemetry is what real workflows would produce.
+
+use anyhow::Result;
+use serde_json::{Value, json};
+use time::format_description::well_known::Rfc3339;
+use time::{Duration, OffsetDateTime};
+use tracing::info;
+
+use crate::github::{self, Config, GITHUB_HOST, WorkflowJob, WorkflowRun};
+use crate::{get_program_start, metrics, traces};
+
+pub(crate) struct Simulation {
+    pub(crate) runs: u32,
+    pub(crate) jobs: u32,
+    pub(crate) steps: u32,
+    /// The average duration of a Step, in seconds.
+    pub(crate) step_duration: f64,
+    /// The chance of each Job failing, from 0 to 1.
+    pub(crate) failure_rate: f64,
+    /// How long to wait between sending each Run.
+    pub(crate) interval: std::time::Duration,
+    pub(crate) seed: u64,
+}
+
+/// A small xorshift generator. The simulation only needs to look varied, and
+/// being able to replay the same one from its seed is more use than quality
+/// randomness.
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+
+    /// A number in [0, 1).
+    fn uniform(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A duration in seconds with the given mean. Most Steps are quick with
+    /// the occasional long one, which an exponential distribution mimics
+    /// well enough.
+    fn duration(&mut self, mean: f64) -> i64 {
+        let sample = -mean * (1.0 - self.uniform()).ln();
+        (sample.round() as i64).max(1)
+    }
+}
+
+fn timestamp(datetime: OffsetDateTime) -> String {
+    datetime
+        .replace_nanosecond(0)
+        .unwrap()
+        .format(&Rfc3339)
+        .unwrap()
+}
+
+/// Make up the Jobs of a Run starting at the given time. Returns the Jobs,
+/// whether any failed, and when the last one finished.
+fn fabricate_jobs(
+    simulation: &Simulation,
+    random: &mut Random,
+    run_id: u64,
+    start: OffsetDateTime,
+) -> (Vec<Value>, bool, OffsetDateTime) {
+    let mut jobs = Vec::new();
+    let mut failed = false;
+    let mut finish = start;
+
+    for index in 0..simulation.jobs {
+        let job_id = run_id * 1000 + index as u64;
+        let fails = random.uniform() < simulation.failure_rate;
+        let failing_step = (random.next()
+            % simulation
+                .steps
+                .max(1) as u64) as u32;
+
+        let queued = start + Duration::seconds(random.duration(5.0));
+        let started = queued + Duration::seconds(random.duration(10.0));
+
+        let mut clock = started;
+        let mut steps = Vec::new();
+        for number in 0..simulation.steps {
+            let conclusion = if !fails || number < failing_step {
+                "success"
+            } else if number == failing_step {
+                "failure"
+            } else {
+                "skipped"
+            };
+
+            let step_start = clock;
+            if conclusion != "skipped" {
+                clock += Duration::seconds(random.duration(simulation.step_duration));
+            }
+
+            steps.push(json!({
+                "name": format!("Step {}", number + 1),
+                "status": "completed",
+                "conclusion": conclusion,
+                "number": number + 1,
+                "started_at": timestamp(step_start),
+                "completed_at": timestamp(clock),
+            }));
+        }
+
+        jobs.push(json!({
+            "id": job_id,
+            "run_id": run_id,
+            "name": format!("Job {}", index + 1),
+            "head_branch": "main",
+            "status": "completed",
+            "conclusion": if fails { "failure" } else { "success" },
+            "created_at": timestamp(queued),
+            "started_at": timestamp(started),
+            "completed_at": timestamp(clock),
+            "steps": steps,
+            "html_url": format!("https://github.com/simulated/simulated/actions/runs/{}/job/{}", run_id, job_id),
+            "labels": ["ubuntu-latest"],
+        }));
+
+        failed |= fails;
+        finish = finish.max(clock);
+    }
+
+    (jobs, failed, finish)
+}
+
+pub(crate) async fn run_simulation(simulation: &Simulation) -> Result<()> {
+    let config = Config {
+        host: GITHUB_HOST.to_string(),
+        owner: "simulated".to_string(),
+        repository: "simulated".to_string(),
+        workflow: "simulate.yml".to_string(),
+        devel: false,
+    };
+
+    // nothing is retrieved from GitHub, but the machinery wants a client
+    let client = github::build_api_client(GITHUB_HOST, "unused")?;
+
+    let mut random = Random(
+        simulation
+            .seed
+            .max(1),
+    );
+
+    // Run IDs come from the time the simulation started, so that each
+    // simulation gets traces of its own.
+    let base = get_program_start().unix_timestamp() as u64 * 1000;
+
+    let began = std::time::Instant::now();
+    let mut total_jobs = 0;
+    let mut total_steps = 0;
+
+    for number in 1..=simulation.runs {
+        let run_id = base + number as u64;
+
+        // each Run is laid out as if it started an hour ago; the spans are
+        // sent as soon as they're made so there's no need to wait for it.
+        let start = OffsetDateTime::now_utc() - Duration::hours(1);
+        let (jobs, failed, finish) = fabricate_jobs(simulation, &mut random, run_id, start);
+
+        let value = json!({
+            "id": run_id,
+            "name": "Simulation",
+            "display_title": format!("Simulated Run {}", number),
+            "run_number": number,
+            "run_attempt": 1,
+            "event": "push",
+            "status": "completed",
+            "conclusion": if failed { "failure" } else { "success" },
+            "head_branch": "main",
+            "head_sha": format!("{:040x}", run_id),
+            "path": ".github/workflows/simulate.yml",
+            "created_at": timestamp(start),
+            "updated_at": timestamp(finish),
+            "html_url": format!("https://github.com/simulated/simulated/actions/runs/{}", run_id),
+            "actor": { "login": "simulated" },
+        });
+
+        let run: WorkflowRun = github::decode_run(value)?;
+        let jobs = jobs
+            .into_iter()
+            .map(github::decode_job)
+            .collect::<Result<Vec<WorkflowJob>, _>>()?;
+
+        total_jobs += jobs.len();
+        total_steps += jobs
+            .iter()
+            .map(|job| {
+                job.steps
+                    .len()
+            })
+            .sum::<usize>();
+
+        let context = traces::establish_root_context(&config, &run);
+        traces::display_job_steps(&config, &client, &context, &run, jobs, None, &[]).await?;
+        traces::finalize_root_span(&context, &run);
+
+        metrics::record_run_outcome(&config, &run);
+
+        if !simulation
+            .interval
+            .is_zero()
+        {
+            tokio::time::sleep(simulation.interval).await;
+        }
+    }
+
+    info!(
+        "Sent {} Runs ({} Jobs, {} Steps) in {:.1}s",
+        simulation.runs,
+        total_jobs,
+        total_steps,
+        began
+            .elapsed()
+            .as_secs_f64()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,70 @@
+//! A one line summary of what the listener has been doing, logged
+//! periodically so that someone tailing journald can see at a glance that it
+//! is receiving webhooks and getting Runs out, without needing a metrics
+//! backend to hand.
+
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::info;
+
+use crate::queue;
+
+// Counts since the last summary was logged.
+static WEBHOOKS: AtomicU64 = AtomicU64::new(0);
+static EXPORTED: AtomicU64 = AtomicU64::new(0);
+static FAILED: AtomicU64 = AtomicU64::new(0);
+
+// The number of requests remaining in the current rate limit window, as of
+// the most recent response from GitHub. Negative until one has been seen.
+static RATE_LIMIT_REMAINING: AtomicI64 = AtomicI64::new(-1);
+
+pub(crate) fn record_webhook() {
+    WEBHOOKS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn record_run_processed(sent: bool) {
+    if sent {
+        EXPORTED.fetch_add(1, Ordering::Relaxed);
+    } else {
+        FAILED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub(crate) fn record_rate_limit(remaining: i64) {
+    RATE_LIMIT_REMAINING.store(remaining, Ordering::Relaxed);
+}
+
+/// Log a summary every interval, of the activity since the previous one.
+/// This runs for as long as the listener does.
+pub(crate) async fn run_summary(interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+
+    // the first tick is immediate, and there is nothing to report yet
+    ticker
+        .tick()
+        .await;
+
+    loop {
+        ticker
+            .tick()
+            .await;
+
+        let webhooks = WEBHOOKS.swap(0, Ordering::Relaxed);
+        let exported = EXPORTED.swap(0, Ordering::Relaxed);
+        let failed = FAILED.swap(0, Ordering::Relaxed);
+
+        let queue_depth = queue::depth().map(|depth| depth as u64);
+
+        let rate_limit_remaining = match RATE_LIMIT_REMAINING.load(Ordering::Relaxed) {
+            remaining if remaining >= 0 => Some(remaining),
+            _ => None,
+        };
+
+        let minutes = interval.as_secs() / 60;
+
+        info!(
+            minutes,
+            webhooks, exported, failed, queue_depth, rate_limit_remaining, "Status summary"
+        );
+    }
+}
@@ -1,21 +1,53 @@
+use anyhow::{Result, anyhow};
 use opentelemetry::trace::{
-    Span, SpanBuilder, SpanContext, TraceContextExt, TraceState, TracerProvider,
+    Link, Span, SpanBuilder, SpanContext, TraceContextExt, TraceState, TracerProvider,
+};
+use opentelemetry::{
+    Array, Context, KeyValue, SpanId, StringValue, TraceFlags, TraceId, Value, global,
+    trace::Tracer,
+};
+use opentelemetry_otlp::{
+    Protocol, SpanExporter, WithExportConfig, WithHttpConfig, WithTonicConfig,
 };
-use opentelemetry::{Context, KeyValue, SpanId, TraceFlags, TraceId, global, trace::Tracer};
-use opentelemetry_otlp::SpanExporter;
 use opentelemetry_sdk::Resource;
-use opentelemetry_sdk::trace::SdkTracerProvider;
-use opentelemetry_semantic_conventions::attribute::{SERVICE_NAME, SERVICE_VERSION};
+use opentelemetry_sdk::resource::TelemetryResourceDetector;
+use opentelemetry_sdk::trace::{
+    BatchConfigBuilder, BatchSpanProcessor, SdkTracer, SdkTracerProvider,
+};
+use opentelemetry_semantic_conventions::attribute::{
+    SERVICE_NAME, SERVICE_VERSION, VCS_REF_HEAD_NAME, VCS_REF_HEAD_REVISION,
+    VCS_REPOSITORY_URL_FULL,
+};
 use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::process;
+use std::sync::{Mutex, OnceLock};
 // use opentelemetry_stdout::SpanExporter;
+#[cfg(unix)]
+use hyper_util::rt::TokioIo;
 use sha2::Digest;
 use std::time::SystemTime;
-use time::OffsetDateTime;
-use tracing::debug;
-
-use crate::VERSION;
-use crate::github::{Config, GitHubProblem, WorkflowJob, WorkflowRun, retrieve_job_log};
+use time::{Duration, OffsetDateTime};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tonic::metadata::{MetadataKey, MetadataMap};
+#[cfg(unix)]
+use tonic::transport::Uri;
+use tonic::transport::{Channel, Endpoint};
+#[cfg(unix)]
+use tower::service_fn;
+use tracing::{debug, warn};
+
+use crate::compare::Difference;
+use crate::github::{
+    Cancellation, Config, DeploymentApproval, GITHUB_HOST, GitHubProblem, JobLog, LogGroup,
+    Outcome, PendingDeployment, ReferencedWorkflow, RunDeployment, WorkflowJob, WorkflowRun,
+    WorkflowStep, classify_conclusion, retrieve_job_log,
+};
+use crate::junit::{TestOutcome, TestReport};
+use crate::settings::{SkippedSteps, get_settings};
+use crate::workflow::JobGraph;
+use crate::{VERSION, failure, get_program_start, live, metrics};
 
 /// It turns out that the OpenTelemetry API uses std::time::SystemTime to
 /// represent start and end times (which makes sense, given that is mostly
@@ -28,22 +60,112 @@ fn convert_to_system_time(datetime: &OffsetDateTime) -> SystemTime {
         .into()
 }
 
-fn form_trace_id(config: &Config, run_id: u64) -> TraceId {
-    let input = format!(
+/// GitHub has been seen to report something as finishing before it started.
+/// Exported as is, that gives a span of negative duration which backends make
+/// a mess of, so the finish is moved up to the start instead. Returns the
+/// corrected finish, and whether it needed correcting so the span can be
+/// marked with `time_anomaly`.
+fn clamp_finish(
+    name: &str,
+    start: OffsetDateTime,
+    finish: OffsetDateTime,
+) -> (OffsetDateTime, bool) {
+    if finish >= start {
+        return (finish, false);
+    }
+
+    warn!(
+        "{} finished {} before it started; giving it no duration",
+        name,
+        start - finish
+    );
+    (start, true)
+}
+
+/// The longest a span name taken from a workflow is allowed to be, in
+/// characters.
+const MAX_SPAN_NAME: usize = 100;
+
+/// Job and Step names (and the name of the workflow itself) are whatever
+/// its author wrote, which can include control characters or run to several
+/// hundred characters; neither displays well in a backend, and some reject
+/// them. Control characters are replaced with spaces and long names cut
+/// short. Returns the name to use, and the original if it had to be changed
+/// so that it can be kept as the `original_name` attribute.
+fn sanitize_span_name(name: String) -> (String, Option<String>) {
+    let cleaned: String = name
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    let cleaned = cleaned.trim();
+
+    let cleaned = if cleaned
+        .chars()
+        .count()
+        > MAX_SPAN_NAME
+    {
+        let mut shortened: String = cleaned
+            .chars()
+            .take(MAX_SPAN_NAME - 1)
+            .collect();
+        shortened.push('…');
+        shortened
+    } else {
+        cleaned.to_string()
+    };
+
+    if cleaned == name {
+        (name, None)
+    } else {
+        (cleaned, Some(name))
+    }
+}
+
+pub(crate) fn form_trace_id(config: &Config, run: &WorkflowRun) -> TraceId {
+    let run_id = run.run_id;
+    let run_attempt = run.run_attempt;
+
+    let mut input = format!(
         "{}:{}:{}:{}",
         config.owner, config.repository, config.workflow, run_id
     );
 
+    // Re-running a workflow gives a new attempt of the same Run, which needs
+    // to be a trace of its own. The first attempt is left hashing as it
+    // always did so that Runs exported before attempts were distinguished
+    // keep the same TraceId.
+
+    if run_attempt > 1 {
+        input.push_str(&format!(":{}", run_attempt));
+    }
+
+    // Likewise Runs on github.com keep their TraceIds, while those from a
+    // GitHub Enterprise Server are distinguished by its host.
+
+    if config.host != GITHUB_HOST {
+        input = format!("{}:{}", config.host, input);
+    }
+
     let mut hasher = sha2::Sha256::new();
     hasher.update(input.as_bytes());
 
     // if we signal that we're doing development we mix in the PID to override
     // the otherwise deterministic nature of assigning a TraceID so we can get
-    // separate traces into Honeycomb when testing.
+    // separate traces into Honeycomb when testing. Fresh TraceIds can also be
+    // asked for explicitly, in which case the program's start time goes in
+    // too as PIDs get reused.
 
     if config.devel {
         let pid = process::id();
         hasher.update(pid.to_le_bytes());
+    } else if get_settings().fresh_trace_ids {
+        let pid = process::id();
+        hasher.update(pid.to_le_bytes());
+        hasher.update(
+            get_program_start()
+                .unix_timestamp_nanos()
+                .to_le_bytes(),
+        );
     }
 
     let result = hasher.finalize();
@@ -51,137 +173,1436 @@ fn form_trace_id(config: &Config, run_id: u64) -> TraceId {
     // Trace IDs are defined as being 128 bits, so somewhat arbitrarily we
     // just select half of the 256 bit hash result.
 
-    match result[..16].try_into() {
-        Ok(lower) => TraceId::from_bytes(lower),
-        Err(_) => TraceId::INVALID,
+    let mut bytes: [u8; 16] = match result[..16].try_into() {
+        Ok(lower) => lower,
+        Err(_) => return TraceId::INVALID,
+    };
+
+    // AWS X-Ray rejects TraceIds that don't begin with the time the trace
+    // started, as 32 bits of seconds since the epoch. The Run's start time
+    // is as deterministic as the rest so the TraceId still doesn't change
+    // from one export of the Run to the next.
+
+    if get_settings().xray_trace_ids {
+        let started = (run.created_at + run.delta).unix_timestamp() as u32;
+        bytes[..4].copy_from_slice(&started.to_be_bytes());
     }
+
+    TraceId::from_bytes(bytes)
 }
 
-// returns the earliest start and latest finishing time of jobs seen within
-// the run, so the root span can be updated accordingly. We originally had
-// "context" named "parent" was a somewhat misleading name; it is the current
-// Context _containing_ a span and as such will become the parent.
+/// Span IDs for the Run and its Jobs are derived from the TraceId, so that a
+/// Job's span sent as soon as it finished (and before the Run's span exists)
+/// is still a child of the Run's span, and is linked to by the Jobs that
+/// needed it when the rest of the Run is sent.
+fn form_span_id(trace_id: TraceId, input: &str) -> SpanId {
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(trace_id.to_bytes());
+    hasher.update(input.as_bytes());
+
+    let result = hasher.finalize();
+
+    match result[..8].try_into() {
+        Ok(lower) => SpanId::from_bytes(lower),
+        Err(_) => SpanId::INVALID,
+    }
+}
+
+fn form_job_span_id(trace_id: TraceId, job_id: u64) -> SpanId {
+    form_span_id(trace_id, &format!("job:{}", job_id))
+}
+
+/// The span of a call to a reusable workflow is only sent once all the Jobs
+/// it ran have been, so they are given its ID to be children of in advance.
+fn form_call_span_id(trace_id: TraceId, caller: &str) -> SpanId {
+    form_span_id(trace_id, &format!("call:{}", caller))
+}
+
+fn call_span_context(trace_id: TraceId, caller: &str) -> SpanContext {
+    SpanContext::new(
+        trace_id,
+        form_call_span_id(trace_id, caller),
+        TraceFlags::SAMPLED,
+        false,
+        TraceState::NONE,
+    )
+}
+
+/// Which of the Run's referenced workflows a job called. The workflow's
+/// definition says what each job `uses:`; without it we can only be sure
+/// when there is just the one.
+fn called_workflow<'r>(
+    run: &'r WorkflowRun,
+    graph: Option<&JobGraph>,
+    caller: &str,
+) -> Option<&'r ReferencedWorkflow> {
+    let uses = graph.and_then(|graph| {
+        graph
+            .job_id_of(caller)
+            .and_then(|id| graph.uses_of(id))
+    });
+
+    let Some(uses) = uses else {
+        return match run
+            .referenced_workflows
+            .as_slice()
+        {
+            [called] => Some(called),
+            _ => None,
+        };
+    };
+
+    // the referenced workflow's path always names the repository, whereas a
+    // call to a workflow in the same repository doesn't.
+    let uses = uses
+        .split_once('@')
+        .map(|(path, _)| path)
+        .unwrap_or(uses);
+    let uses = uses
+        .strip_prefix("./")
+        .unwrap_or(uses);
+
+    run.referenced_workflows
+        .iter()
+        .find(|called| {
+            called
+                .path
+                .split_once('@')
+                .map(|(path, _)| path)
+                .unwrap_or(&called.path)
+                .ends_with(uses)
+        })
+}
+
+// We originally had "context" named "parent", which was a somewhat misleading
+// name; it is the current Context _containing_ a span and as such will become
+// the parent.
 pub(crate) async fn display_job_steps(
     config: &Config,
     client: &reqwest::Client,
     context: &Context,
     run: &WorkflowRun,
     jobs: Vec<WorkflowJob>,
+    graph: Option<&JobGraph>,
+    deployments: &[RunDeployment],
 ) -> Result<(), GitHubProblem> {
-    let provider = global::tracer_provider();
-    let tracer = provider.tracer(module_path!());
+    let mut spans = JobSpans::new(config, client, context, run, graph, deployments);
+    spans
+        .display(jobs)
+        .await?;
+    spans.display_calls();
+    Ok(())
+}
 
-    for job in jobs {
-        println!("{}", job.name);
+/// A call to a reusable workflow, whose span takes in the Jobs it ran. How
+/// long it lasted is only known once they have all been seen.
+struct Call<'a> {
+    start: OffsetDateTime,
+    finish: OffsetDateTime,
+    called: Option<&'a ReferencedWorkflow>,
+    jobs: i64,
+    failed: bool,
+}
 
-        // get job start and end times
-        let job_start = job.started_at + run.delta;
-        let job_finish = job.completed_at + run.delta;
+/// Builds the spans of a Run's Jobs. The Jobs can be given a page at a time,
+/// as they are retrieved, so that a Run with thousands of them never has
+/// them all held at once; only what is needed to link later Jobs to earlier
+/// ones is kept from one page to the next.
+pub(crate) struct JobSpans<'a> {
+    config: &'a Config,
+    client: &'a reqwest::Client,
+    context: &'a Context,
+    run: &'a WorkflowRun,
+    graph: Option<&'a JobGraph>,
+    deployments: &'a [RunDeployment],
+
+    // Each Job is linked to the spans of the Jobs it needed, so the backend
+    // can show which Jobs gated which.
+    needed: HashMap<String, Vec<SpanContext>>,
+
+    // Jobs coming from the same call to a reusable workflow are put under a
+    // span for the call, by the name of the calling job.
+    calls: BTreeMap<String, Call<'a>>,
+
+    // Test reports not yet matched to the Job which uploaded them.
+    reports: Vec<TestReport>,
+
+    // The category of the Run's first failure, once there has been one.
+    category: Option<&'static str>,
+
+    // Whether these are Jobs being sent as they finish, rather than with
+    // the rest of their Run.
+    sending_live: bool,
+}
 
-        let job_start = convert_to_system_time(&job_start);
-        let job_finish = convert_to_system_time(&job_finish);
+impl<'a> JobSpans<'a> {
+    pub(crate) fn new(
+        config: &'a Config,
+        client: &'a reqwest::Client,
+        context: &'a Context,
+        run: &'a WorkflowRun,
+        graph: Option<&'a JobGraph>,
+        deployments: &'a [RunDeployment],
+    ) -> JobSpans<'a> {
+        JobSpans {
+            config,
+            client,
+            context,
+            run,
+            graph,
+            deployments,
+            needed: HashMap::new(),
+            calls: BTreeMap::new(),
+            reports: Vec::new(),
+            category: None,
+            sending_live: false,
+        }
+    }
 
-        // setup a new child span
-        let builder = SpanBuilder::from_name(job.name)
-            .with_start_time(job_start)
-            .with_end_time(job_finish);
+    /// Have the tests in these reports shown under the Jobs that uploaded
+    /// them.
+    pub(crate) fn with_test_reports(mut self, reports: Vec<TestReport>) -> JobSpans<'a> {
+        self.reports = reports;
+        self
+    }
 
-        let span = tracer.build_with_context(builder, &context);
+    /// Send the Jobs given even though they are recorded as sent live, as
+    /// that is what is being done.
+    pub(crate) fn live(mut self) -> JobSpans<'a> {
+        self.sending_live = true;
+        self
+    }
 
-        // and again non-obviously, although the Job span is now a child, the
-        // context still has the root span in it. We need to get a new context
-        // before creating spans around the Steps.
-        let context = context.with_span(span);
-        // and stupidly, get it out again
-        let span = context.span();
+    /// Any reports which couldn't be matched to a Job are shown under the
+    /// Run instead.
+    pub(crate) fn display_remaining_test_reports(&mut self) {
+        for report in self
+            .reports
+            .drain(..)
+        {
+            display_test_report(self.config, self.context, self.run, report);
+        }
+    }
+
+    /// Emit the span of each call to a reusable workflow, now that all the
+    /// Jobs it ran have been seen. Jobs being sent live don't do this; the
+    /// rest of the call's Jobs are yet to finish, and the Run will see to it.
+    pub(crate) fn display_calls(&mut self) {
+        if self.sending_live {
+            return;
+        }
+
+        let tracer = workflow_tracer(self.config);
+        let trace_id = self
+            .context
+            .span()
+            .span_context()
+            .trace_id();
+
+        for (caller, call) in std::mem::take(&mut self.calls) {
+            let start = convert_to_system_time(&call.start);
+            let finish = convert_to_system_time(&call.finish);
+
+            let (name, original_name) = sanitize_span_name(caller.clone());
+            let builder = SpanBuilder::from_name(name)
+                .with_kind(
+                    get_settings()
+                        .span_kinds
+                        .job
+                        .clone(),
+                )
+                .with_span_id(form_call_span_id(trace_id, &caller))
+                .with_start_time(start)
+                .with_end_time(finish);
+
+            let mut span = tracer.build_with_context(builder, self.context);
+
+            span.set_attribute(KeyValue::new("layer", "CalledWorkflow"));
+
+            if let Some(original) = original_name {
+                span.set_attribute(KeyValue::new("original_name", original));
+            }
+
+            span.set_attribute(KeyValue::new("caller_job", caller));
+            span.set_attribute(KeyValue::new("job_count", call.jobs));
+            span.set_attribute(KeyValue::new(
+                "conclusion",
+                if call.failed { "failure" } else { "success" },
+            ));
+
+            if let Some(called) = call.called {
+                span.set_attribute(KeyValue::new(
+                    "called_workflow",
+                    called
+                        .path
+                        .clone(),
+                ));
+                span.set_attribute(KeyValue::new(
+                    "called_workflow_sha",
+                    called
+                        .sha
+                        .clone(),
+                ));
+                if let Some(git_ref) = &called.git_ref {
+                    span.set_attribute(KeyValue::new("called_workflow_ref", git_ref.clone()));
+                }
+            }
+
+            span.end_with_timestamp(finish);
+        }
+    }
+
+    /// The category of the first failure among the Jobs displayed so far.
+    pub(crate) fn category(&self) -> Option<&'static str> {
+        self.category
+    }
 
-        span.set_attribute(KeyValue::new("layer", "Job"));
+    pub(crate) async fn display(
+        &mut self,
+        mut jobs: Vec<WorkflowJob>,
+    ) -> Result<(), GitHubProblem> {
+        let JobSpans {
+            config,
+            client,
+            context,
+            run,
+            graph,
+            deployments,
+            needed,
+            calls,
+            reports,
+            category,
+            sending_live,
+        } = self;
+        let (config, client, context, run, graph, deployments) =
+            (*config, *client, *context, *run, *graph, *deployments);
+
+        // the Run's own span, as context is about to mean each Job's.
+        let root = context;
+        let trace_id = root
+            .span()
+            .span_context()
+            .trace_id();
+
+        let tracer = workflow_tracer(config);
+
+        // A Job can't start until those it needs have finished, so going
+        // through them in the order they started means the spans to link to
+        // will already be there. GitHub gives the pages in the order the Jobs
+        // were created, which is close enough from one page to the next.
+        jobs.sort_by_key(|job| job.started_at);
+
+        for job in jobs {
+            println!("{}", job.name);
+
+            let caller = job
+                .caller_job()
+                .map(str::to_string);
+
+            // get job start and end times
+            let job_queued = job
+                .created_at
+                .map(|created_at| created_at + run.delta);
+            let job_start = job.started_at + run.delta;
+            let (job_finish, job_anomaly) =
+                clamp_finish(&job.name, job_start, job.completed_at + run.delta);
+
+            let job_wait = job_queued.map(|queued| job_start - queued);
+
+            // a Job run by a called workflow goes under the span of the call,
+            // which is made to take in each of them.
+            let called = caller
+                .as_deref()
+                .and_then(|caller| called_workflow(run, graph, caller));
+
+            if let Some(caller) = &caller {
+                let failed = classify_conclusion(&job.conclusion) == Outcome::Failure;
+                calls
+                    .entry(caller.clone())
+                    .and_modify(|call| {
+                        call.start = call
+                            .start
+                            .min(job_start);
+                        call.finish = call
+                            .finish
+                            .max(job_finish);
+                        call.jobs += 1;
+                        call.failed |= failed;
+                    })
+                    .or_insert(Call {
+                        start: job_start,
+                        finish: job_finish,
+                        called,
+                        jobs: 1,
+                        failed,
+                    });
+            }
 
-        span.set_attribute(KeyValue::new("job_id", job.job_id as i64));
+            let parent = match &caller {
+                Some(caller) => root.with_remote_span_context(call_span_context(trace_id, caller)),
+                None => root.clone(),
+            };
+
+            // The time between this attempt of the Run starting and the Job
+            // being queued is spent waiting on the workflow itself: for the
+            // Jobs it needs, or for a concurrency group to free up.
+            let run_started = run
+                .run_started_at
+                .unwrap_or(run.created_at)
+                + run.delta;
+            let queue_wait = job_queued
+                .map(|queued| queued - run_started)
+                .filter(|wait| !wait.is_negative());
+
+            let labels = job
+                .labels
+                .join(",");
+
+            let job_start = convert_to_system_time(&job_start);
+            let job_finish = convert_to_system_time(&job_finish);
+
+            // where the Job is in the workflow's definition, if we know
+            let definition = graph.and_then(|graph| {
+                graph
+                    .job_id_of(&job.name)
+                    .map(|id| (graph, id.to_string()))
+            });
+
+            // A Job whose spans were sent as it finished is not sent again,
+            // but later Jobs still link to it and its tests are still shown
+            // under it.
+            if !*sending_live && let Some(sent) = live::take_sent(job.job_id) {
+                let span_context = SpanContext::new(
+                    trace_id,
+                    form_job_span_id(trace_id, job.job_id),
+                    TraceFlags::SAMPLED,
+                    false,
+                    TraceState::NONE,
+                );
+
+                if let Some((_, id)) = definition {
+                    needed
+                        .entry(id)
+                        .or_default()
+                        .push(span_context.clone());
+                }
+                if category.is_none()
+                    && let Some(found) = sent.category
+                {
+                    root.span()
+                        .set_attribute(KeyValue::new("failure.category", found));
+                    *category = Some(found);
+                }
+
+                let context = Context::new().with_remote_span_context(span_context);
+                for report in reports.extract_if(.., |report| {
+                    report.uploaded >= job.started_at
+                        && report.uploaded < job.completed_at + Duration::seconds(1)
+                }) {
+                    display_test_report(config, &context, run, report);
+                }
+                continue;
+            }
 
-        span.set_attribute(KeyValue::new("conclusion", job.conclusion));
+            // setup a new child span
+            let (job_name, original_name) = sanitize_span_name(
+                job.name
+                    .clone(),
+            );
+            let mut builder = SpanBuilder::from_name(job_name)
+                .with_kind(
+                    get_settings()
+                        .span_kinds
+                        .job
+                        .clone(),
+                )
+                .with_span_id(form_job_span_id(trace_id, job.job_id))
+                .with_start_time(job_start)
+                .with_end_time(job_finish);
+
+            let mut links = Vec::new();
+
+            if let Some((graph, id)) = &definition {
+                for need in graph.needs_of(id) {
+                    for span_context in needed
+                        .get(need)
+                        .into_iter()
+                        .flatten()
+                    {
+                        links.push(Link::new(
+                            span_context.clone(),
+                            vec![
+                                KeyValue::new("link.reason", "needs"),
+                                KeyValue::new("needs", need.clone()),
+                            ],
+                            0,
+                        ));
+                    }
+                }
+            }
 
-        span.set_attribute(KeyValue::new("status", job.status));
+            if !links.is_empty() {
+                builder = builder.with_links(links);
+            }
 
-        span.set_attribute(KeyValue::new("head_branch", job.head_branch));
+            let span = tracer.build_with_context(builder, &parent);
 
-        span.set_attribute(KeyValue::new("html_url", job.html_url));
+            // and again non-obviously, although the Job span is now a child, the
+            // context still has the root span in it. We need to get a new context
+            // before creating spans around the Steps.
+            let context = parent.with_span(span);
+            // and stupidly, get it out again
+            let span = context.span();
 
-        // now iterate through the steps of this job, and extract the details
-        // to be put onto individual grandchild spans.
-        for step in job.steps {
-            // convert start and stop times to a suitable DateTime type. We
-            // add "delta" to reset the origin to the program start time if
-            // doing development.
+            span.set_attribute(KeyValue::new("layer", "Job"));
 
-            let step_start = step.started_at + run.delta;
-            let step_finish = step.completed_at + run.delta;
+            if let Some(original) = original_name {
+                span.set_attribute(KeyValue::new("original_name", original));
+            }
 
-            let step_duration = step_finish - step_start;
+            if job_anomaly {
+                span.set_attribute(KeyValue::new("time_anomaly", true));
+            }
 
-            println!(
-                "    {}: {},{} {}",
-                step.name, step.status, step.conclusion, step_duration
+            // a Job targeting an environment shows up as posting the statuses
+            // of its Deployment. Time spent waiting is the environment's
+            // protection rules holding the Job back.
+            for RunDeployment {
+                deployment,
+                statuses,
+            } in deployments
+            {
+                if !statuses
+                    .iter()
+                    .any(|status| status.job_id() == Some(job.job_id))
+                {
+                    continue;
+                }
+
+                span.set_attribute(KeyValue::new(
+                    "environment.name",
+                    deployment
+                        .environment
+                        .clone(),
+                ));
+
+                let waited: time::Duration = statuses
+                    .windows(2)
+                    .filter(|pair| pair[0].state == "waiting")
+                    .map(|pair| pair[1].created_at - pair[0].created_at)
+                    .sum();
+
+                if waited.is_positive() {
+                    span.set_attribute(KeyValue::new(
+                        "environment.protection_wait_ms",
+                        waited.whole_milliseconds() as i64,
+                    ));
+                }
+            }
+
+            if let Some((_, id)) = definition {
+                span.set_attribute(KeyValue::new("job_key", id.clone()));
+
+                needed
+                    .entry(id)
+                    .or_default()
+                    .push(
+                        span.span_context()
+                            .clone(),
+                    );
+            }
+
+            span.set_attribute(KeyValue::new("job_id", job.job_id as i64));
+
+            span.set_attribute(KeyValue::new(
+                "conclusion",
+                job.conclusion
+                    .clone(),
+            ));
+
+            span.set_attribute(KeyValue::new("status", job.status));
+
+            span.set_attribute(KeyValue::new("head_branch", job.head_branch));
+
+            span.set_attribute(KeyValue::new("html_url", job.html_url));
+
+            for attribute in mapped_attributes(
+                &get_settings()
+                    .attributes
+                    .job,
+                &job.payload,
+            ) {
+                span.set_attribute(attribute);
+            }
+
+            if let Some(caller) = caller {
+                span.set_attribute(KeyValue::new("caller_job", caller.clone()));
+
+                if let Some(called) = called {
+                    span.set_attribute(KeyValue::new(
+                        "called_workflow",
+                        called
+                            .path
+                            .clone(),
+                    ));
+                    span.set_attribute(KeyValue::new(
+                        "called_workflow_sha",
+                        called
+                            .sha
+                            .clone(),
+                    ));
+                    if let Some(git_ref) = &called.git_ref {
+                        span.set_attribute(KeyValue::new("called_workflow_ref", git_ref.clone()));
+                    }
+                }
+            }
+
+            if !labels.is_empty() {
+                span.set_attribute(KeyValue::new("runner.labels", labels.clone()));
+            }
+            if let Some(wait) = queue_wait {
+                span.set_attribute(KeyValue::new(
+                    "queue_wait_ms",
+                    wait.whole_milliseconds() as i64,
+                ));
+            }
+
+            metrics::record_job_waits(
+                config,
+                &labels,
+                queue_wait.map(|wait| wait.as_seconds_f64()),
+                job_wait
+                    .filter(|wait| !wait.is_negative())
+                    .map(|wait| wait.as_seconds_f64()),
             );
 
-            // If GitHub skipped a step we don't need to send telemetry about
-            // it. Otherwise we'd get a distribution where lots of useful
-            // steps had instances with approximately 0 ms duration.
+            // The time between a Job being queued and it starting is spent
+            // waiting for a runner to pick it up. This shows up as a span
+            // alongside the Job's, under the same parent, ending as the Job
+            // starts; starvation of the runner pool is otherwise invisible.
+            if let Some(queued) = job_queued
+                && let Some(wait) = job_wait
+                && wait.is_positive()
+            {
+                span.set_attribute(KeyValue::new(
+                    "runner_wait_ms",
+                    wait.whole_milliseconds() as i64,
+                ));
+
+                let wait_start = convert_to_system_time(&queued);
+
+                let builder = SpanBuilder::from_name("waiting for runner")
+                    .with_start_time(wait_start)
+                    .with_end_time(job_start);
+
+                let mut wait_span = tracer.build_with_context(builder, &parent);
+
+                wait_span.set_attribute(KeyValue::new("layer", "RunnerWait"));
+                if !labels.is_empty() {
+                    wait_span.set_attribute(KeyValue::new("runner.labels", labels.clone()));
+                }
 
-            if step.conclusion == "skipped" {
-                continue;
+                wait_span.end_with_timestamp(job_start);
+            }
+
+            // whether any of the Job's Steps failed, explaining its failure.
+            let mut job_failed = false;
+
+            // the log of the Job, read once for what it says about caches
+            // restored and the groups the output was divided into, and about
+            // why any of its Steps failed. Not being able to get the log just
+            // for the former isn't a reason to lose the Run.
+            let wants_outline = get_settings().cache_stats || get_settings().log_groups;
+            let wants_failure = get_settings().logs
+                && job
+                    .steps
+                    .iter()
+                    .any(|step| step.conclusion == "failure");
+
+            let log = if wants_failure {
+                retrieve_job_log(config, client, job.job_id, wants_outline, true).await?
+            } else if wants_outline {
+                retrieve_job_log(config, client, job.job_id, true, false)
+                    .await
+                    .unwrap_or_else(|error| {
+                        warn!("Unable to retrieve log of Job {}: {}", job.job_id, error);
+                        JobLog::default()
+                    })
+            } else {
+                JobLog::default()
+            };
+
+            // consecutive skipped steps waiting to be collapsed into one span
+            let mut skipped: Vec<WorkflowStep> = Vec::new();
+
+            // where the previous step finished, for measuring the overhead
+            // between steps.
+            let mut previous_finish = job.started_at + run.delta;
+            let mut first = true;
+
+            // now iterate through the steps of this job, and extract the details
+            // to be put onto individual grandchild spans.
+            for step in job.steps {
+                // convert start and stop times to a suitable DateTime type. We
+                // add "delta" to reset the origin to the program start time if
+                // doing development.
+
+                let step_start = step.started_at + run.delta;
+
+                // a Step still running (or whose completion GitHub never
+                // recorded) has no end time; it is shown as lasting until the
+                // Job finished, and marked as incomplete.
+                let incomplete = step
+                    .completed_at
+                    .is_none();
+                let (step_finish, step_anomaly) = match step.completed_at {
+                    Some(completed_at) => {
+                        clamp_finish(&step.name, step_start, completed_at + run.delta)
+                    }
+                    None => ((job.completed_at + run.delta).max(step_start), false),
+                };
+
+                let step_duration = step_finish - step_start;
+
+                println!(
+                    "    {}: {},{} {}",
+                    step.name, step.status, step.conclusion, step_duration
+                );
+
+                // If GitHub skipped a step we don't normally send telemetry
+                // about it. Otherwise we'd get a distribution where lots of
+                // useful steps had instances with approximately 0 ms duration.
+                // If asked to, we do send them, but explicitly marked as skipped
+                // so they can be excluded from duration calculations.
+
+                if step.conclusion == "skipped" {
+                    match get_settings().skipped {
+                        SkippedSteps::Omit => {}
+                        SkippedSteps::Mark => {
+                            display_skipped_steps(config, &context, run, vec![step])
+                        }
+                        SkippedSteps::Collapse => skipped.push(step),
+                    }
+                    continue;
+                }
+
+                display_skipped_steps(config, &context, run, std::mem::take(&mut skipped));
+
+                if get_settings().gaps {
+                    let kind = if first { "setup" } else { "between" };
+                    display_gap(config, &context, kind, previous_finish, step_start);
+                }
+                previous_finish = step_finish;
+                first = false;
+
+                if !incomplete && !step_anomaly {
+                    metrics::record_step_duration(
+                        config,
+                        &job.name,
+                        &step.name,
+                        step_duration.as_seconds_f64(),
+                    );
+                }
+
+                if !get_settings()
+                    .steps
+                    .allows(&step.name)
+                {
+                    continue;
+                }
+
+                // Get read to send OpenTelemetry data
+
+                // And now at last we create a span. It's not clear if setting the
+                // end time does any good here, as we have to close a span with a
+                // timestamp (otherwise it gets told to be now() from a few
+                // places)
+
+                let step_start = convert_to_system_time(&step_start);
+                let step_finish = convert_to_system_time(&step_finish);
+
+                let (step_name, original_name) = sanitize_span_name(
+                    step.name
+                        .clone(),
+                );
+                let builder = SpanBuilder::from_name(step_name)
+                    .with_kind(
+                        get_settings()
+                            .span_kinds
+                            .step
+                            .clone(),
+                    )
+                    .with_start_time(step_start)
+                    .with_end_time(step_finish);
+
+                // because context has a current Span present within it this
+                // will create the new Span as a child of that one as parent!
+                let mut span = tracer.build_with_context(builder, &context);
+
+                span.set_attribute(KeyValue::new("layer", "Step"));
+
+                if let Some(original) = original_name {
+                    span.set_attribute(KeyValue::new("original_name", original));
+                }
+
+                span.set_attribute(KeyValue::new("status", step.status));
+
+                if incomplete {
+                    span.set_attribute(KeyValue::new("incomplete", true));
+                }
+
+                if step_anomaly {
+                    span.set_attribute(KeyValue::new("time_anomaly", true));
+                }
+
+                // log timestamps are finer grained than those of Steps, which
+                // are to the second.
+                let within_step = |at: OffsetDateTime| {
+                    at >= step.started_at
+                        && step
+                            .completed_at
+                            .is_none_or(|completed_at| at < completed_at + Duration::seconds(1))
+                };
+
+                if get_settings().cache_stats
+                    && let Some(cache) = log
+                        .outline
+                        .caches
+                        .iter()
+                        .find(|cache| within_step(cache.at))
+                {
+                    span.set_attribute(KeyValue::new("cache.hit", cache.hit));
+                    span.set_attribute(KeyValue::new(
+                        "cache.key",
+                        cache
+                            .key
+                            .clone(),
+                    ));
+                    if let Some(bytes) = cache.restored_bytes {
+                        span.set_attribute(KeyValue::new("cache.restored_bytes", bytes));
+                    }
+                }
+
+                if get_settings().log_groups {
+                    let step_context = context.with_remote_span_context(
+                        span.span_context()
+                            .clone(),
+                    );
+                    for group in log
+                        .outline
+                        .groups
+                        .iter()
+                        .filter(|group| within_step(group.start))
+                    {
+                        display_log_group(config, &step_context, run, group, step_finish);
+                    }
+                }
+
+                if step.conclusion == "failure" {
+                    span.set_status(opentelemetry::trace::Status::Error {
+                        description: Cow::Borrowed("Step failed"),
+                    });
+
+                    let excerpt = log
+                        .excerpt
+                        .as_ref();
+
+                    if let Some(exit) = log
+                        .exits
+                        .iter()
+                        .find(|exit| within_step(exit.at))
+                    {
+                        span.set_attribute(KeyValue::new("process.exit_code", exit.code));
+                    }
+
+                    let found = failure::classify_step(&step.name, excerpt);
+                    span.set_attribute(KeyValue::new("failure.category", found));
+                    job_failed = true;
+                    if category.is_none() {
+                        root.span()
+                            .set_attribute(KeyValue::new("failure.category", found));
+                        *category = Some(found);
+                    }
+
+                    if let Some(excerpt) = excerpt {
+                        // the lines leading up to the error are often what
+                        // explains it, so they go along as an event.
+                        if !excerpt
+                            .context
+                            .is_empty()
+                        {
+                            span.add_event_with_timestamp(
+                                "log excerpt",
+                                step_finish,
+                                vec![KeyValue::new(
+                                    "log.excerpt",
+                                    excerpt
+                                        .context
+                                        .join("\n"),
+                                )],
+                            );
+                        }
+                        span.set_attribute(KeyValue::new(
+                            "exception.message",
+                            excerpt
+                                .message
+                                .clone(),
+                        ));
+                    }
+                }
+                span.set_attribute(KeyValue::new("conclusion", step.conclusion));
+
+                span.end_with_timestamp(step_finish);
             }
 
-            // Get read to send OpenTelemetry data
+            display_skipped_steps(config, &context, run, skipped);
+
+            if get_settings().gaps {
+                display_gap(
+                    config,
+                    &context,
+                    "teardown",
+                    previous_finish,
+                    job.completed_at + run.delta,
+                );
+            }
 
-            // And now at last we create a span. It's not clear if setting the
-            // end time does any good here, as we have to close a span with a
-            // timestamp (otherwise it gets told to be now() from a few
-            // places)
+            // a Job can fail without any of its Steps failing, such as when it
+            // times out.
+            if !job_failed && let Some(found) = failure::classify_job(&job.conclusion) {
+                span.set_attribute(KeyValue::new("failure.category", found));
+                if category.is_none() {
+                    root.span()
+                        .set_attribute(KeyValue::new("failure.category", found));
+                    *category = Some(found);
+                }
+            }
 
-            let step_start = convert_to_system_time(&step_start);
-            let step_finish = convert_to_system_time(&step_finish);
+            // a report uploaded while the Job was running came from it.
+            for report in reports.extract_if(.., |report| {
+                report.uploaded >= job.started_at
+                    && report.uploaded < job.completed_at + Duration::seconds(1)
+            }) {
+                display_test_report(config, &context, run, report);
+            }
 
-            let builder = SpanBuilder::from_name(step.name)
-                .with_start_time(step_start)
-                .with_end_time(step_finish);
+            // finalize the enclosing job span and send. We kept this in scope
+            // while the spans were created around individual steps so they would
+            // be children of this job's span.
+            span.end_with_timestamp(job_finish);
+        }
 
-            // because context has a current Span present within it this
-            // will create the new Span as a child of that one as parent!
-            let mut span = tracer.build_with_context(builder, &context);
+        Ok(())
+    }
+}
+
+/// Emit a span for each test case in a report. Reports say how long each
+/// test took but not when it ran, so the tests are laid end to end, finishing
+/// at the time the report was uploaded.
+fn display_test_report(config: &Config, context: &Context, run: &WorkflowRun, report: TestReport) {
+    let tracer = workflow_tracer(config);
+
+    let total: f64 = report
+        .cases
+        .iter()
+        .map(|case| case.seconds)
+        .sum();
+    let mut clock = report.uploaded + run.delta - Duration::seconds_f64(total);
+
+    for case in report.cases {
+        let start = clock;
+        clock += Duration::seconds_f64(case.seconds);
+
+        let start = convert_to_system_time(&start);
+        let finish = convert_to_system_time(&clock);
+
+        let qualified = match &case.classname {
+            Some(classname) => format!("{}.{}", classname, case.name),
+            None => case
+                .name
+                .clone(),
+        };
+
+        let (name, original_name) = sanitize_span_name(case.name);
+
+        let builder = SpanBuilder::from_name(name)
+            .with_start_time(start)
+            .with_end_time(finish);
+
+        let mut span = tracer.build_with_context(builder, context);
+
+        span.set_attribute(KeyValue::new("layer", "Test"));
+
+        if let Some(original) = original_name {
+            span.set_attribute(KeyValue::new("original_name", original));
+        }
+
+        span.set_attribute(KeyValue::new("test.case.name", qualified));
+
+        if let Some(suite) = case.suite {
+            span.set_attribute(KeyValue::new("test.suite.name", suite));
+        }
+
+        span.set_attribute(KeyValue::new(
+            "test.case.result.status",
+            case.outcome
+                .as_str(),
+        ));
+
+        span.set_attribute(KeyValue::new(
+            "test.report",
+            report
+                .artifact
+                .clone(),
+        ));
+
+        if case.outcome == TestOutcome::Failed {
+            span.set_status(opentelemetry::trace::Status::Error {
+                description: Cow::Borrowed("Test failed"),
+            });
+            if let Some(message) = case.message {
+                span.set_attribute(KeyValue::new("exception.message", message));
+            }
+        }
+
+        span.end_with_timestamp(finish);
+    }
+}
+
+/// Groups shorter than this are left out. GitHub opens a group at the start
+/// of each Step to show the command being run, which closes again at once.
+const MIN_GROUP_DURATION: Duration = Duration::seconds(1);
+
+/// Emit a span for a section of a Step's output marked as a group, giving
+/// some structure to long Steps which run many things.
+fn display_log_group(
+    config: &Config,
+    context: &Context,
+    run: &WorkflowRun,
+    group: &LogGroup,
+    step_finish: SystemTime,
+) {
+    if group.finish - group.start < MIN_GROUP_DURATION {
+        return;
+    }
+
+    let tracer = workflow_tracer(config);
 
-            span.set_attribute(KeyValue::new("layer", "Step"));
+    let start = convert_to_system_time(&(group.start + run.delta));
+    let finish = convert_to_system_time(&(group.finish + run.delta)).min(step_finish);
 
-            span.set_attribute(KeyValue::new("status", step.status));
+    let (name, original_name) = sanitize_span_name(
+        group
+            .title
+            .clone(),
+    );
+
+    let builder = SpanBuilder::from_name(name)
+        .with_start_time(start)
+        .with_end_time(finish);
+
+    let mut span = tracer.build_with_context(builder, context);
+
+    span.set_attribute(KeyValue::new("layer", "LogGroup"));
+
+    if let Some(original) = original_name {
+        span.set_attribute(KeyValue::new("original_name", original));
+    }
+
+    span.end_with_timestamp(finish);
+}
+
+/// Emit a span covering time within a Job not accounted for by any Step:
+/// before the first Step (pulling actions, setting up containers), between
+/// Steps, and after the last Step (post-job cleanup).
+fn display_gap(
+    config: &Config,
+    context: &Context,
+    kind: &'static str,
+    start: OffsetDateTime,
+    finish: OffsetDateTime,
+) {
+    if finish <= start {
+        return;
+    }
+
+    let tracer = workflow_tracer(config);
+
+    let name = match kind {
+        "setup" => "runner setup overhead",
+        "teardown" => "runner teardown overhead",
+        _ => "step overhead",
+    };
+
+    let start = convert_to_system_time(&start);
+    let finish = convert_to_system_time(&finish);
+
+    let builder = SpanBuilder::from_name(name)
+        .with_start_time(start)
+        .with_end_time(finish);
+
+    let mut span = tracer.build_with_context(builder, context);
+
+    span.set_attribute(KeyValue::new("layer", "Gap"));
+
+    span.set_attribute(KeyValue::new("gap", kind));
+
+    span.end_with_timestamp(finish);
+}
+
+/// Emit a single zero duration span representing one or more consecutive
+/// Steps which GitHub skipped.
+fn display_skipped_steps(
+    config: &Config,
+    context: &Context,
+    run: &WorkflowRun,
+    steps: Vec<WorkflowStep>,
+) {
+    let first = match steps.first() {
+        Some(step) => step,
+        None => return,
+    };
+
+    let tracer = workflow_tracer(config);
+
+    let instant = convert_to_system_time(&(first.started_at + run.delta));
+
+    let name = match steps.len() {
+        1 => first
+            .name
+            .clone(),
+        count => format!("{} skipped steps", count),
+    };
+    let (name, original_name) = sanitize_span_name(name);
+
+    let builder = SpanBuilder::from_name(name)
+        .with_kind(
+            get_settings()
+                .span_kinds
+                .step
+                .clone(),
+        )
+        .with_start_time(instant)
+        .with_end_time(instant);
+
+    let mut span = tracer.build_with_context(builder, context);
+
+    span.set_attribute(KeyValue::new("layer", "Step"));
+
+    if let Some(original) = original_name {
+        span.set_attribute(KeyValue::new("original_name", original));
+    }
+
+    span.set_attribute(KeyValue::new("skipped", true));
+
+    span.set_attribute(KeyValue::new("conclusion", "skipped"));
+
+    if steps.len() > 1 {
+        let names: Vec<StringValue> = steps
+            .iter()
+            .map(|step| {
+                StringValue::from(
+                    step.name
+                        .clone(),
+                )
+            })
+            .collect();
+        span.set_attribute(KeyValue::new("skipped_count", steps.len() as i64));
+        span.set_attribute(KeyValue::new(
+            "skipped_steps",
+            Value::Array(Array::String(names)),
+        ));
+    }
 
-            if step.conclusion == "failure" {
+    span.end_with_timestamp(instant);
+}
+
+/// Emit a span for each Deployment performed by this Run, covering the time
+/// from when the deployment was created through to its final status. Each
+/// transition the deployment went through (queued, in_progress, etc) becomes
+/// a child span lasting until the next status was posted, which makes the
+/// latency of deploying to each environment visible in the trace. Time spent
+/// in the "waiting" state is a wait for a reviewer to approve deployment to a
+/// protected environment, and is labelled with who resolved it.
+pub(crate) fn display_deployments(
+    config: &Config,
+    context: &Context,
+    run: &WorkflowRun,
+    deployments: Vec<RunDeployment>,
+    approvals: &[DeploymentApproval],
+) {
+    let tracer = workflow_tracer(config);
+
+    for RunDeployment {
+        deployment,
+        statuses,
+    } in deployments
+    {
+        println!("{} (deployment)", deployment.environment);
+
+        let deploy_start = deployment.created_at + run.delta;
+        let deploy_finish = match statuses.last() {
+            Some(status) => status.created_at + run.delta,
+            None => deploy_start,
+        };
+
+        let deploy_start = convert_to_system_time(&deploy_start);
+        let deploy_finish = convert_to_system_time(&deploy_finish);
+
+        let builder = SpanBuilder::from_name(format!("Deploy to {}", deployment.environment))
+            .with_start_time(deploy_start)
+            .with_end_time(deploy_finish);
+
+        let span = tracer.build_with_context(builder, context);
+
+        let context = context.with_span(span);
+        let span = context.span();
+
+        span.set_attribute(KeyValue::new("layer", "Deployment"));
+
+        span.set_attribute(KeyValue::new("deployment_id", deployment.id as i64));
+
+        span.set_attribute(KeyValue::new(
+            "environment",
+            deployment
+                .environment
+                .clone(),
+        ));
+
+        if let Some(status) = statuses.last() {
+            span.set_attribute(KeyValue::new(
+                "state",
+                status
+                    .state
+                    .clone(),
+            ));
+
+            if status.state == "failure" || status.state == "error" {
                 span.set_status(opentelemetry::trace::Status::Error {
-                    description: Cow::Borrowed("Step failed"),
+                    description: Cow::Borrowed("Deployment failed"),
                 });
+            }
+        }
+
+        // each status lasts until the next one was posted. The final status
+        // is terminal and has no duration, so it is only recorded as the
+        // state attribute on the deployment span above.
+        for pair in statuses.windows(2) {
+            let (status, next) = (&pair[0], &pair[1]);
+
+            let status_start = convert_to_system_time(&(status.created_at + run.delta));
+            let status_finish = convert_to_system_time(&(next.created_at + run.delta));
+
+            let waiting = status.state == "waiting";
+
+            let name = if waiting {
+                "waiting for approval".to_string()
+            } else {
+                status
+                    .state
+                    .clone()
+            };
+
+            let builder = SpanBuilder::from_name(name)
+                .with_start_time(status_start)
+                .with_end_time(status_finish);
+
+            let mut span = tracer.build_with_context(builder, &context);
 
-                if let Some(message) = retrieve_job_log(config, client, job.job_id).await? {
-                    span.set_attribute(KeyValue::new("exception.message", message));
+            span.set_attribute(KeyValue::new("layer", "DeploymentStatus"));
+
+            span.set_attribute(KeyValue::new(
+                "state",
+                status
+                    .state
+                    .clone(),
+            ));
+
+            if waiting {
+                let approval = approvals
+                    .iter()
+                    .find(|approval| {
+                        approval
+                            .environments
+                            .iter()
+                            .any(|environment| environment.name == deployment.environment)
+                    });
+
+                if let Some(approval) = approval {
+                    span.set_attribute(KeyValue::new(
+                        "approver",
+                        approval
+                            .user
+                            .login
+                            .clone(),
+                    ));
+                    span.set_attribute(KeyValue::new(
+                        "approval",
+                        approval
+                            .state
+                            .clone(),
+                    ));
                 }
             }
-            span.set_attribute(KeyValue::new("conclusion", step.conclusion));
 
-            span.end_with_timestamp(step_finish);
+            span.end_with_timestamp(status_finish);
         }
 
-        // finalize the enclosing job span and send. We kept this in scope
-        // while the spans were created around individual steps so they would
-        // be children of this job's span.
-        span.end_with_timestamp(job_finish);
+        span.end_with_timestamp(deploy_finish);
     }
+}
 
-    Ok(())
+/// For a Run that is still waiting on approval to deploy to a protected
+/// environment, emit a span covering the wait so far.
+pub(crate) fn display_pending_approvals(
+    config: &Config,
+    context: &Context,
+    run: &WorkflowRun,
+    pending: Vec<PendingDeployment>,
+) {
+    let tracer = workflow_tracer(config);
+
+    let now = *get_program_start();
+
+    for deployment in pending {
+        let wait_start = deployment
+            .wait_timer_started_at
+            .unwrap_or(run.updated_at)
+            + run.delta;
+
+        let wait_start = convert_to_system_time(&wait_start);
+        let wait_finish = convert_to_system_time(&(now + run.delta));
+
+        let builder = SpanBuilder::from_name("waiting for approval")
+            .with_start_time(wait_start)
+            .with_end_time(wait_finish);
+
+        let mut span = tracer.build_with_context(builder, context);
+
+        span.set_attribute(KeyValue::new("layer", "DeploymentStatus"));
+
+        span.set_attribute(KeyValue::new("state", "waiting"));
+
+        span.set_attribute(KeyValue::new(
+            "environment",
+            deployment
+                .environment
+                .name,
+        ));
+
+        span.set_attribute(KeyValue::new("pending", true));
+
+        span.end_with_timestamp(wait_finish);
+    }
+}
+
+/// The context of a Run whose own span hasn't been created yet, for sending
+/// the spans of its Jobs as they finish. The Run's span, when it is sent
+/// later, has the same SpanId.
+pub(crate) fn establish_remote_root_context(config: &Config, run: &WorkflowRun) -> Context {
+    let trace_id = form_trace_id(config, run);
+
+    let span_context = SpanContext::new(
+        trace_id,
+        form_span_id(trace_id, "run"),
+        TraceFlags::SAMPLED,
+        true,
+        TraceState::NONE,
+    );
+
+    Context::new().with_remote_span_context(span_context)
+}
+
+/// Send a span into the trace of the newer of two Runs that were compared,
+/// saying how much longer (or shorter) it and each of its Jobs took than in
+/// the older one. The Run's own span has long since been sent, so the
+/// comparison goes alongside it rather than onto it.
+pub(crate) fn display_comparison(
+    config: &Config,
+    newer: &WorkflowRun,
+    older: &WorkflowRun,
+    differences: &[Difference],
+) {
+    let tracer = workflow_tracer(config);
+    let context = establish_remote_root_context(config, newer);
+
+    let start = convert_to_system_time(&(newer.created_at + newer.delta));
+    let finish = convert_to_system_time(&(newer.updated_at + newer.delta));
+
+    let builder = SpanBuilder::from_name(format!("compared with #{}", older.run_number))
+        .with_start_time(start)
+        .with_end_time(finish);
+
+    let mut span = tracer.build_with_context(builder, &context);
+
+    span.set_attribute(KeyValue::new("layer", "Comparison"));
+    span.set_attribute(KeyValue::new("compare.run_id", older.run_id as i64));
+    span.set_attribute(KeyValue::new("compare.run_number", older.run_number as i64));
+
+    let change = (newer.updated_at - newer.created_at) - (older.updated_at - older.created_at);
+    span.set_attribute(KeyValue::new(
+        "compare.duration_change_ms",
+        change.whole_milliseconds() as i64,
+    ));
+
+    for difference in differences
+        .iter()
+        .filter(|difference| {
+            difference
+                .step
+                .is_none()
+        })
+    {
+        if let Some(change) = difference.change() {
+            span.set_attribute(KeyValue::new(
+                format!("compare.job.{}.duration_change_ms", difference.job),
+                change.whole_milliseconds() as i64,
+            ));
+        }
+    }
+
+    span.end_with_timestamp(finish);
+}
+
+/// Send a short span noting that a Job has started, so that a long Run shows
+/// signs of life before any of it has finished. It is marked as provisional;
+/// the Job's real span follows when it completes.
+pub(crate) fn display_provisional_job(
+    config: &Config,
+    context: &Context,
+    run: &WorkflowRun,
+    job_id: u64,
+    name: String,
+    started_at: OffsetDateTime,
+) {
+    let tracer = workflow_tracer(config);
+
+    let job_start = convert_to_system_time(&(started_at + run.delta));
+    let now = SystemTime::now().max(job_start);
+
+    let (name, original_name) = sanitize_span_name(name);
+
+    let builder = SpanBuilder::from_name(name)
+        .with_start_time(job_start)
+        .with_end_time(now);
+
+    let mut span = tracer.build_with_context(builder, context);
+
+    span.set_attribute(KeyValue::new("layer", "JobStarted"));
+    span.set_attribute(KeyValue::new("provisional", true));
+    span.set_attribute(KeyValue::new("job_id", job_id as i64));
+
+    if let Some(original) = original_name {
+        span.set_attribute(KeyValue::new("original_name", original));
+    }
+
+    span.end_with_timestamp(now);
 }
 
 pub(crate) fn establish_root_context(config: &Config, run: &WorkflowRun) -> Context {
-    let provider = global::tracer_provider();
-    let tracer = provider.tracer(module_path!());
+    let tracer = workflow_tracer(config);
 
-    let trace_id = form_trace_id(&config, run.run_id);
+    let trace_id = form_trace_id(config, run);
 
     // this is meant to be the immutable, reusable part of a trace that can be
     // propagated to a remote process (or received from a invoking parent). In our
@@ -194,9 +1615,10 @@ pub(crate) fn establish_root_context(config: &Config, run: &WorkflowRun) -> Cont
         TraceState::NONE,
     );
 
-    let name = run
-        .name
-        .clone();
+    let (name, original_name) = sanitize_span_name(
+        run.name
+            .clone(),
+    );
     let owner = config
         .owner
         .clone();
@@ -226,13 +1648,25 @@ pub(crate) fn establish_root_context(config: &Config, run: &WorkflowRun) -> Cont
     // unhelpful to say the least.
     let context = Context::new().with_remote_span_context(span_context);
 
-    let builder = SpanBuilder::from_name(name).with_start_time(run_start);
+    let builder = SpanBuilder::from_name(name)
+        .with_kind(
+            get_settings()
+                .span_kinds
+                .run
+                .clone(),
+        )
+        .with_span_id(form_span_id(trace_id, "run"))
+        .with_start_time(run_start);
 
     // create the span that will be the root span
     let mut span = tracer.build_with_context(builder, &context);
 
     span.set_attribute(KeyValue::new("layer", "Run"));
 
+    if let Some(original) = original_name {
+        span.set_attribute(KeyValue::new("original_name", original));
+    }
+
     span.set_attribute(KeyValue::new("owner", owner));
 
     span.set_attribute(KeyValue::new("repository", repository));
@@ -252,6 +1686,65 @@ pub(crate) fn establish_root_context(config: &Config, run: &WorkflowRun) -> Cont
 
     span.set_attribute(KeyValue::new("run_attempt", run_attempt));
 
+    // the same details again under the names from the VCS semantic
+    // conventions, so other tooling keyed on them can find these traces.
+    span.set_attribute(KeyValue::new(
+        VCS_REPOSITORY_URL_FULL,
+        format!(
+            "https://{}/{}/{}",
+            config.host, config.owner, config.repository
+        ),
+    ));
+
+    span.set_attribute(KeyValue::new(
+        VCS_REF_HEAD_NAME,
+        run.head_branch
+            .clone(),
+    ));
+
+    span.set_attribute(KeyValue::new(
+        VCS_REF_HEAD_REVISION,
+        run.head_sha
+            .clone(),
+    ));
+
+    // the inputs a workflow_dispatch was invoked with. These are nearly always
+    // strings but booleans and numbers are possible too.
+    for (name, value) in &run.inputs {
+        let key = format!("input.{}", name);
+        span.set_attribute(KeyValue::new(key, convert_json_value(value)));
+    }
+
+    for attribute in mapped_attributes(
+        &get_settings()
+            .attributes
+            .run,
+        &run.payload,
+    ) {
+        span.set_attribute(attribute);
+    }
+
+    if !run
+        .referenced_workflows
+        .is_empty()
+    {
+        let paths: Vec<StringValue> = run
+            .referenced_workflows
+            .iter()
+            .map(|called| {
+                StringValue::from(
+                    called
+                        .path
+                        .clone(),
+                )
+            })
+            .collect();
+        span.set_attribute(KeyValue::new(
+            "referenced_workflows",
+            Value::Array(Array::String(paths)),
+        ));
+    }
+
     // more non-obvious: set the span into the Context,
     let context = context.with_span(span);
 
@@ -259,17 +1752,196 @@ pub(crate) fn establish_root_context(config: &Config, run: &WorkflowRun) -> Cont
     context
 }
 
+fn convert_json_value(value: &serde_json::Value) -> Value {
+    match value {
+        serde_json::Value::String(text) => Value::from(text.clone()),
+        serde_json::Value::Bool(flag) => Value::from(*flag),
+        serde_json::Value::Number(number) => match number.as_i64() {
+            Some(number) => Value::from(number),
+            None => Value::from(number.to_string()),
+        },
+        other => Value::from(other.to_string()),
+    }
+}
+
+/// Look up each of the configured fields in the object GitHub sent us. Fields
+/// which are absent or null are left off rather than being sent empty.
+fn mapped_attributes(mappings: &[(String, String)], payload: &serde_json::Value) -> Vec<KeyValue> {
+    mappings
+        .iter()
+        .filter_map(|(name, pointer)| {
+            payload
+                .pointer(pointer)
+                .filter(|value| !value.is_null())
+                .map(|value| KeyValue::new(name.clone(), convert_json_value(value)))
+        })
+        .collect()
+}
+
+/// Record which version of the workflow's definition the Run used, so that
+/// changes in how long Runs take can be lined up with changes to the
+/// workflow. The hash is shortened as git does; it is the git blob SHA, so
+/// `git hash-object` on the file gives the same value.
+pub(crate) fn annotate_workflow_hash(context: &Context, path: &str, hash: &str) {
+    let span = context.span();
+
+    span.set_attribute(KeyValue::new("workflow.path", path.to_string()));
+    span.set_attribute(KeyValue::new(
+        "workflow.hash",
+        hash.chars()
+            .take(12)
+            .collect::<String>(),
+    ));
+}
+
+/// Record what caused the Run: the event, and a short summary of it such as
+/// "push to main" or "pull_request #42 from feature into main", along with
+/// the particulars of that kind of event. A scheduled Run doesn't say which
+/// schedule fired it, so if the workflow has more than one they are all
+/// given.
+pub(crate) fn annotate_trigger(context: &Context, run: &WorkflowRun, schedules: &[String]) {
+    let span = context.span();
+
+    span.set_attribute(KeyValue::new(
+        "event",
+        run.event
+            .clone(),
+    ));
+
+    let pull_request = run
+        .payload
+        .get("pull_requests")
+        .and_then(|requests| requests.get(0));
+    let branch = |side: &str| {
+        pull_request
+            .and_then(|request| request.pointer(&format!("/{}/ref", side)))
+            .and_then(|name| name.as_str())
+            .map(str::to_string)
+    };
+
+    let summary = match run
+        .event
+        .as_str()
+    {
+        "push" => {
+            // a pushed tag is reported as the head_branch too
+            span.set_attribute(KeyValue::new(
+                "trigger.ref",
+                run.head_branch
+                    .clone(),
+            ));
+            format!("push to {}", run.head_branch)
+        }
+        "schedule" if !schedules.is_empty() => {
+            let cron = schedules.join(", ");
+            span.set_attribute(KeyValue::new("trigger.cron", cron.clone()));
+            format!("schedule {}", cron)
+        }
+        "pull_request" | "pull_request_target" => {
+            let number = pull_request
+                .and_then(|request| request.get("number"))
+                .and_then(|number| number.as_i64());
+
+            // pull requests from forks aren't listed on the Run, so all we
+            // have is the branch and the repository it came from.
+            let head = branch("head").unwrap_or_else(|| {
+                run.head_branch
+                    .clone()
+            });
+            let source = match run
+                .payload
+                .pointer("/head_repository/full_name")
+                .and_then(|name| name.as_str())
+                .filter(|name| {
+                    run.payload
+                        .pointer("/repository/full_name")
+                        .and_then(|name| name.as_str())
+                        != Some(*name)
+                }) {
+                Some(fork) => {
+                    span.set_attribute(KeyValue::new("trigger.head_repository", fork.to_string()));
+                    format!("{}:{}", fork, head)
+                }
+                None => head.clone(),
+            };
+            span.set_attribute(KeyValue::new("trigger.head_ref", head));
+
+            let mut summary = run
+                .event
+                .clone();
+            if let Some(number) = number {
+                span.set_attribute(KeyValue::new("trigger.pull_request", number));
+                summary.push_str(&format!(" #{}", number));
+            }
+            summary.push_str(&format!(" from {}", source));
+            if let Some(base) = branch("base") {
+                summary.push_str(&format!(" into {}", base));
+                span.set_attribute(KeyValue::new("trigger.base_ref", base));
+            }
+            summary
+        }
+        "workflow_dispatch" => format!(
+            "workflow_dispatch by {} on {}",
+            run.actor
+                .login,
+            run.head_branch
+        ),
+        event => format!("{} on {}", event, run.head_branch),
+    };
+
+    span.set_attribute(KeyValue::new("trigger", summary));
+}
+
+/// Record the concurrency group the Run was in, and whether starting it
+/// cancelled any Run of the group already in progress. A Run concluding as
+/// "cancelled" in a group with cancel-in-progress was most likely preempted
+/// by a newer one rather than stopped by someone.
+pub(crate) fn annotate_concurrency(
+    context: &Context,
+    group: String,
+    cancel_in_progress: Option<bool>,
+) {
+    let span = context.span();
+
+    span.set_attribute(KeyValue::new("concurrency.group", group));
+    if let Some(cancel_in_progress) = cancel_in_progress {
+        span.set_attribute(KeyValue::new(
+            "concurrency.cancel_in_progress",
+            cancel_in_progress,
+        ));
+    }
+}
+
+/// Record why the Run was cancelled and, if a person did it, who.
+pub(crate) fn annotate_cancellation(context: &Context, cancellation: Cancellation) {
+    let span = context.span();
+
+    span.set_attribute(KeyValue::new("cancellation.reason", cancellation.reason));
+
+    if let Some(login) = cancellation.by {
+        span.set_attribute(KeyValue::new("cancelled_by", login));
+    }
+}
+
 pub(crate) fn finalize_root_span(context: &Context, run: &WorkflowRun) -> String {
     let span = context.span();
     let span_context = span.span_context();
     let trace_id = span_context.trace_id();
     let span_id = span_context.span_id();
 
-    let run_finish = run.updated_at + run.delta;
+    let (run_finish, anomaly) = clamp_finish(
+        &run.name,
+        run.created_at + run.delta,
+        run.updated_at + run.delta,
+    );
     let run_finish = convert_to_system_time(&run_finish);
     debug!(?span_id);
     debug!(?trace_id);
 
+    if anomaly {
+        span.set_attribute(KeyValue::new("time_anomaly", true));
+    }
+
     // this SHOULD be the root span!
     span.set_attribute(KeyValue::new("debug.omega", true));
     span.end_with_timestamp(run_finish);
@@ -277,35 +1949,505 @@ pub(crate) fn finalize_root_span(context: &Context, run: &WorkflowRun) -> String
     format!("{:x}", trace_id)
 }
 
-pub(crate) fn setup_telemetry_machinery() -> SdkTracerProvider {
-    // Setup OpenTelemetry. First we establish a Resource, which is a set of reusable attributes and
-    // other characteristics which will be applied to all traces.
+/// Substitute the details of a trace into the template for a link to it in
+/// the telemetry backend's UI.
+pub(crate) fn form_trace_url(template: &str, trace_id: &str, run: &WorkflowRun) -> String {
+    let start = (run.created_at + run.delta).unix_timestamp();
+    let end = (run.updated_at + run.delta).unix_timestamp();
+
+    let url = template
+        .replace("{trace_start}", &start.to_string())
+        .replace("{trace_end}", &end.to_string());
+
+    insert_trace_id(&url, trace_id)
+}
+
+/// Whether the template needs the Run's start and finish, as opposed to
+/// just its TraceId.
+pub(crate) fn trace_url_needs_times(template: &str) -> bool {
+    template.contains("{trace_start}") || template.contains("{trace_end}")
+}
+
+pub(crate) fn insert_trace_id(template: &str, trace_id: &str) -> String {
+    if template.contains("{trace_id}") {
+        template.replace("{trace_id}", trace_id)
+    } else {
+        format!("{}{}", template, trace_id)
+    }
+}
+
+/// Attributes supplied by deployment tooling via the standard
+/// OTEL_RESOURCE_ATTRIBUTES environment variable, a comma separated list of
+/// key=value pairs whose values are percent encoded. These are applied to
+/// the Resource of everything we send, but the service name and version we
+/// set ourselves take precedence.
+fn environment_attributes() -> Vec<KeyValue> {
+    let value = match std::env::var("OTEL_RESOURCE_ATTRIBUTES") {
+        Ok(value) => value,
+        Err(_) => return Vec::new(),
+    };
+
+    value
+        .split(',')
+        .filter(|entry| {
+            !entry
+                .trim()
+                .is_empty()
+        })
+        .filter_map(|entry| match entry.split_once('=') {
+            Some((key, value))
+                if !key
+                    .trim()
+                    .is_empty() =>
+            {
+                Some(KeyValue::new(
+                    percent_decode(key.trim()),
+                    percent_decode(value.trim()),
+                ))
+            }
+            _ => {
+                warn!(
+                    "Ignoring malformed OTEL_RESOURCE_ATTRIBUTES entry \"{}\"",
+                    entry
+                );
+                None
+            }
+        })
+        .collect()
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && let Some(byte) = std::str::from_utf8(&bytes[i + 1..i + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            result.push(byte);
+            i += 3;
+        } else {
+            result.push(bytes[i]);
+            i += 1;
+        }
+    }
 
-    let resource = Resource::builder()
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+/// Settings for the OTLP exporter given on the command line. Anything not
+/// specified here falls back to the standard environment variables, first
+/// the OTEL_EXPORTER_OTLP_TRACES_* variant and then OTEL_EXPORTER_OTLP_*.
+#[derive(Clone, Debug, Default)]
+pub struct ExporterOptions {
+    pub endpoint: Option<String>,
+    /// The base URL of a vendor's ingest, from --preset, used when no
+    /// endpoint was given. Over HTTP the signal's path is appended to it.
+    pub base_endpoint: Option<String>,
+    pub protocol: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub timeout: Option<std::time::Duration>,
+    pub connect_timeout: Option<std::time::Duration>,
+    pub keepalive: Option<std::time::Duration>,
+    pub keepalive_timeout: Option<std::time::Duration>,
+    pub queue_size: Option<usize>,
+    pub batch_size: Option<usize>,
+    pub batch_delay: Option<std::time::Duration>,
+}
+
+pub(crate) fn resolve_protocol(options: &ExporterOptions) -> Result<Protocol> {
+    let protocol = options
+        .protocol
+        .clone()
+        .or_else(|| std::env::var("OTEL_EXPORTER_OTLP_TRACES_PROTOCOL").ok())
+        .or_else(|| std::env::var("OTEL_EXPORTER_OTLP_PROTOCOL").ok())
+        .unwrap_or_else(|| "grpc".to_string());
+
+    match protocol.as_str() {
+        "grpc" => Ok(Protocol::Grpc),
+        "http/protobuf" => Ok(Protocol::HttpBinary),
+        "http/json" => Ok(Protocol::HttpJson),
+        other => Err(anyhow!("Unsupported OTLP protocol \"{}\"", other)),
+    }
+}
+
+/// Headers from the command line in the form needed by the gRPC exporter.
+pub(crate) fn grpc_metadata(options: &ExporterOptions) -> Result<MetadataMap> {
+    let mut metadata = MetadataMap::new();
+    for (name, value) in &options.headers {
+        let name = MetadataKey::from_bytes(name.as_bytes())?;
+        metadata.insert(name, value.parse()?);
+    }
+    Ok(metadata)
+}
+
+/// Headers from the command line in the form needed by the HTTP exporter.
+pub(crate) fn http_headers(options: &ExporterOptions) -> HashMap<String, String> {
+    options
+        .headers
+        .iter()
+        .cloned()
+        .collect()
+}
+
+/// A collector on the same host can be reached over a Unix domain socket by
+/// giving an endpoint such as "unix:///run/otelcol.sock". This returns the
+/// path of the socket if that's what was asked for.
+pub(crate) fn unix_socket(options: &ExporterOptions) -> Option<&str> {
+    options
+        .endpoint
+        .as_deref()?
+        .strip_prefix("unix://")
+}
+
+/// The exporter makes its own gRPC channel to the collector unless we give it
+/// one. We do so when connecting over a Unix domain socket, or when the
+/// connection needs tuning: a listener can sit idle for a long stretch between
+/// webhook deliveries, and without keepalives the connection it was holding
+/// can be silently dropped by something in between, so that the first export
+/// afterwards fails.
+pub(crate) fn grpc_channel(options: &ExporterOptions) -> Result<Option<Channel>> {
+    let path = unix_socket(options);
+
+    if path.is_none()
+        && options
+            .connect_timeout
+            .is_none()
+        && options
+            .keepalive
+            .is_none()
+    {
+        return Ok(None);
+    }
+
+    // the URI is required but meaningless when connecting to a socket; every
+    // connection goes to the socket.
+    let url = match path {
+        Some(_) => "http://localhost".to_string(),
+        None => options
+            .endpoint
+            .clone()
+            .or_else(|| {
+                options
+                    .base_endpoint
+                    .clone()
+            })
+            .or_else(|| std::env::var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT").ok())
+            .or_else(|| std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok())
+            .unwrap_or_else(|| "http://localhost:4317".to_string()),
+    };
+
+    let mut endpoint = Endpoint::from_shared(url)?;
+
+    if let Some(timeout) = options.connect_timeout {
+        endpoint = endpoint.connect_timeout(timeout);
+    }
+    if let Some(timeout) = options.timeout {
+        endpoint = endpoint.timeout(timeout);
+    }
+    if let Some(interval) = options.keepalive {
+        endpoint = endpoint
+            .tcp_keepalive(Some(interval))
+            .http2_keep_alive_interval(interval)
+            .keep_alive_while_idle(true);
+        if let Some(timeout) = options.keepalive_timeout {
+            endpoint = endpoint.keep_alive_timeout(timeout);
+        }
+    }
+
+    let channel = match path {
+        #[cfg(unix)]
+        Some(path) => {
+            let path = path.to_string();
+            endpoint.connect_with_connector_lazy(service_fn(move |_: Uri| {
+                let path = path.clone();
+                async move {
+                    let stream = UnixStream::connect(path).await?;
+                    Ok::<_, std::io::Error>(TokioIo::new(stream))
+                }
+            }))
+        }
+        #[cfg(not(unix))]
+        Some(_) => {
+            return Err(anyhow!(
+                "Unix domain sockets are not available on this platform"
+            ));
+        }
+        None => endpoint.connect_lazy(),
+    };
+
+    Ok(Some(channel))
+}
+
+/// An HTTP client which sends every request over the given Unix domain
+/// socket, regardless of the host in the URL.
+#[cfg(unix)]
+pub(crate) fn unix_http_client(path: &str) -> Result<reqwest::blocking::Client> {
+    let client = reqwest::blocking::Client::builder()
+        .unix_socket(path)
+        .build()?;
+
+    Ok(client)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn unix_http_client(_path: &str) -> Result<reqwest::blocking::Client> {
+    Err(anyhow!(
+        "Unix domain sockets are not available on this platform"
+    ))
+}
+
+/// Establish the SpanExporter subsystem that will transmit spans and events
+/// out via OTLP to an otel-collector and onward to Honeycomb. The endpoint,
+/// headers, and timeout are also picked up from the environment by the
+/// exporter itself, but values we supply here take precedence over those.
+fn build_span_exporter(options: &ExporterOptions) -> Result<SpanExporter> {
+    let protocol = resolve_protocol(options)?;
+
+    let exporter = match protocol {
+        Protocol::Grpc => {
+            let mut builder = SpanExporter::builder()
+                .with_tonic()
+                .with_metadata(grpc_metadata(options)?);
+            if let Some(channel) = grpc_channel(options)? {
+                builder = builder.with_channel(channel);
+            } else if let Some(endpoint) = &options.endpoint {
+                builder = builder.with_endpoint(endpoint);
+            } else if let Some(endpoint) = &options.base_endpoint {
+                builder = builder.with_endpoint(endpoint);
+            }
+            if let Some(timeout) = options.timeout {
+                builder = builder.with_timeout(timeout);
+            }
+            builder.build()?
+        }
+        Protocol::HttpBinary | Protocol::HttpJson => {
+            let mut builder = SpanExporter::builder()
+                .with_http()
+                .with_protocol(protocol)
+                .with_headers(http_headers(options));
+            if let Some(path) = unix_socket(options) {
+                builder = builder
+                    .with_http_client(unix_http_client(path)?)
+                    .with_endpoint("http://localhost/v1/traces");
+            } else if let Some(endpoint) = &options.endpoint {
+                builder = builder.with_endpoint(endpoint);
+            } else if let Some(base) = &options.base_endpoint {
+                builder = builder.with_endpoint(format!("{}/v1/traces", base));
+            }
+            if let Some(timeout) = options.timeout {
+                builder = builder.with_timeout(timeout);
+            }
+            builder.build()?
+        }
+    };
+
+    Ok(exporter)
+}
+
+/// The batch processor's queue holds spans waiting to be exported; when it
+/// is full further spans are dropped. The defaults are fine for a trickle of
+/// webhooks but backfilling many Runs at once can overrun them. The SDK
+/// itself reads the OTEL_BSP_* environment variables, which options given on
+/// the command-line then override.
+fn build_span_processor(options: &ExporterOptions) -> Result<BatchSpanProcessor> {
+    let exporter = build_span_exporter(options)?;
+
+    let mut config = BatchConfigBuilder::default();
+
+    if let Some(size) = options.queue_size {
+        config = config.with_max_queue_size(size);
+    }
+    if let Some(size) = options.batch_size {
+        config = config.with_max_export_batch_size(size);
+    }
+    if let Some(delay) = options.batch_delay {
+        config = config.with_scheduled_delay(delay);
+    }
+
+    let processor = BatchSpanProcessor::builder(exporter)
+        .with_batch_config(config.build())
+        .build();
+
+    Ok(processor)
+}
+
+static SERVICE_NAME_OVERRIDE: OnceLock<String> = OnceLock::new();
+
+/// Send the workflows' telemetry as a service other than "github-actions".
+pub(crate) fn set_service_name(name: String) {
+    SERVICE_NAME_OVERRIDE
+        .set(name)
+        .unwrap();
+}
+
+/// The service the workflows' telemetry is sent as, unless each repository
+/// is being sent as a service of its own.
+pub(crate) fn get_service_name() -> String {
+    SERVICE_NAME_OVERRIDE
+        .get()
+        .cloned()
+        .unwrap_or_else(|| "github-actions".to_string())
+}
+
+/// The Resource describing the service that workflow telemetry comes from.
+pub(crate) fn workflow_resource(service_name: String) -> Resource {
+    Resource::builder_empty()
+        .with_detector(Box::new(TelemetryResourceDetector))
+        .with_attributes(environment_attributes())
         .with_attributes([
-            KeyValue::new(SERVICE_NAME, "github-actions"),
+            KeyValue::new(SERVICE_NAME, service_name),
             KeyValue::new(SERVICE_VERSION, VERSION),
         ])
-        .build();
+        .build()
+}
 
-    // Here we establish the SpanExporter subsystem that will transmit spans
-    // and events out via OTLP to an otel-collector and onward to Honeycomb.
+static WORKFLOW_PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
 
-    let exporter = SpanExporter::builder()
-        .with_tonic()
-        .build()
-        .unwrap();
+static EXPORTER_OPTIONS: OnceLock<ExporterOptions> = OnceLock::new();
+
+pub(crate) fn setup_telemetry_machinery(options: &ExporterOptions) -> Result<SdkTracerProvider> {
+    // Setup OpenTelemetry. First we establish a Resource, which is a set of reusable attributes and
+    // other characteristics which will be applied to all traces.
+
+    let resource = workflow_resource(get_service_name());
+
+    let processor = build_span_processor(options)?;
     // let exporter = SpanExporter::default();
 
     // Now we bind this exporter and resource to a TracerProvider whose sole purpose appears to be
     // providing a way to get a Tracer which in turn is the interface used for creating spans.
 
     let provider = SdkTracerProvider::builder()
-        .with_batch_exporter(exporter)
+        .with_span_processor(processor)
         .with_resource(resource)
         .build();
 
     global::set_tracer_provider(provider.clone());
 
-    provider
+    WORKFLOW_PROVIDER
+        .set(provider.clone())
+        .unwrap();
+
+    // kept so that further providers can be made later if need be
+    EXPORTER_OPTIONS
+        .set(options.clone())
+        .unwrap();
+
+    Ok(provider)
+}
+
+// When asked to use the repository as the service name, each repository
+// needs its own TracerProvider since the service name is part of the
+// Resource, and a provider has exactly one Resource. These are created as
+// repositories are encountered.
+
+static REPOSITORY_PROVIDERS: Mutex<BTreeMap<String, SdkTracerProvider>> =
+    Mutex::new(BTreeMap::new());
+
+/// Get a Tracer for creating the spans describing a Run of the workflow in
+/// the given Config.
+fn workflow_tracer(config: &Config) -> SdkTracer {
+    let provider = if get_settings().service_per_repository {
+        let service_name = format!("{}/{}", config.owner, config.repository);
+
+        let mut providers = REPOSITORY_PROVIDERS
+            .lock()
+            .unwrap();
+
+        match providers.get(&service_name) {
+            Some(provider) => provider.clone(),
+            None => match build_repository_provider(&service_name) {
+                Ok(provider) => {
+                    providers.insert(service_name, provider.clone());
+                    provider
+                }
+                Err(error) => {
+                    warn!("Unable to setup telemetry for {}: {}", service_name, error);
+                    WORKFLOW_PROVIDER
+                        .wait()
+                        .clone()
+                }
+            },
+        }
+    } else {
+        WORKFLOW_PROVIDER
+            .wait()
+            .clone()
+    };
+
+    provider.tracer(module_path!())
+}
+
+fn build_repository_provider(service_name: &str) -> Result<SdkTracerProvider> {
+    let processor = build_span_processor(EXPORTER_OPTIONS.wait())?;
+
+    let provider = SdkTracerProvider::builder()
+        .with_span_processor(processor)
+        .with_resource(workflow_resource(service_name.to_string()))
+        .build();
+
+    Ok(provider)
+}
+
+/// Ensure all spans sent via the providers for individual repositories are
+/// exported before the program exits.
+pub(crate) fn shutdown_repository_providers() -> Result<()> {
+    let providers = std::mem::take(
+        &mut *REPOSITORY_PROVIDERS
+            .lock()
+            .unwrap(),
+    );
+
+    for provider in providers.into_values() {
+        provider.shutdown()?;
+    }
+
+    Ok(())
+}
+
+// Telemetry about this program itself (as opposed to the workflows it is
+// processing) is sent as a separate service, so it needs its own provider
+// with its own Resource.
+
+static SELF_PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+/// The Resource describing this program itself, as distinct from the
+/// workflows it reports on.
+pub(crate) fn self_resource() -> Resource {
+    Resource::builder_empty()
+        .with_detector(Box::new(TelemetryResourceDetector))
+        .with_attributes(environment_attributes())
+        .with_attributes([
+            KeyValue::new(SERVICE_NAME, "action-hero"),
+            KeyValue::new(SERVICE_VERSION, VERSION),
+        ])
+        .build()
+}
+
+pub(crate) fn setup_self_telemetry(options: &ExporterOptions) -> Result<SdkTracerProvider> {
+    let resource = self_resource();
+
+    let processor = build_span_processor(options)?;
+
+    let provider = SdkTracerProvider::builder()
+        .with_span_processor(processor)
+        .with_resource(resource)
+        .build();
+
+    SELF_PROVIDER
+        .set(provider.clone())
+        .unwrap();
+
+    Ok(provider)
+}
+
+pub(crate) fn self_tracer() -> SdkTracer {
+    SELF_PROVIDER
+        .wait()
+        .tracer(module_path!())
 }
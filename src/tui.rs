@@ -0,0 +1,284 @@
+//! An interactive view of the recent Runs of one or more workflows, showing
+//! whether each has been sent. Runs can be selected and sent (or sent again),
+//! and the trace of a Run opened in the browser.
+
+use anyhow::{Result, anyhow};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Row, Table, TableState};
+use std::io::Write;
+
+use crate::github::{self, Config, WorkflowRun};
+use crate::settings::get_settings;
+use crate::{cli, history, traces};
+
+struct Entry {
+    config: usize, // index of the Config for the workflow the Run is from
+    run: WorkflowRun,
+    state: &'static str,
+    selected: bool,
+}
+
+pub(crate) async fn run_tui(configs: Vec<Config>, count: u32) -> Result<()> {
+    let client = github::setup_api_client(&configs[0])?;
+
+    let mut entries = load_entries(&configs, &client, count).await?;
+    let mut table = TableState::default().with_selected(0);
+    let mut message = String::new();
+
+    let mut terminal = ratatui::init();
+
+    // whatever happens, the terminal needs to be put back the way it was
+    let result = loop {
+        if let Err(error) =
+            terminal.draw(|frame| draw(frame, &configs, &entries, &mut table, &message))
+        {
+            break Err(error.into());
+        }
+
+        let action = match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => key.code,
+            Ok(_) => continue,
+            Err(error) => break Err(error.into()),
+        };
+
+        match action {
+            KeyCode::Char('q') | KeyCode::Esc => break Ok(()),
+            KeyCode::Down | KeyCode::Char('j') => table.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => table.select_previous(),
+            KeyCode::Char(' ') => {
+                if let Some(entry) = table
+                    .selected()
+                    .and_then(|index| entries.get_mut(index))
+                {
+                    entry.selected = !entry.selected;
+                }
+            }
+            KeyCode::Char('o') => {
+                message = match table
+                    .selected()
+                    .and_then(|index| entries.get(index))
+                {
+                    Some(entry) => match open_trace(&configs[entry.config], entry) {
+                        Ok(url) => format!("Opened {}", url),
+                        Err(error) => error.to_string(),
+                    },
+                    None => String::new(),
+                };
+            }
+            KeyCode::Char('e') | KeyCode::Char('r') => {
+                // Processing Runs writes progress to the terminal, so we step
+                // out of the interactive display while that happens.
+                ratatui::restore();
+
+                let outcome = if action == KeyCode::Char('e') {
+                    export_entries(&configs, &client, &entries, table.selected()).await
+                } else {
+                    Ok(())
+                };
+
+                let outcome = match outcome {
+                    Ok(()) => load_entries(&configs, &client, count)
+                        .await
+                        .map(|loaded| entries = loaded),
+                    Err(error) => Err(error),
+                };
+
+                message = match outcome {
+                    Ok(()) => String::new(),
+                    Err(error) => error.to_string(),
+                };
+
+                if action == KeyCode::Char('e') {
+                    wait_for_enter();
+                }
+
+                terminal = ratatui::init();
+            }
+            _ => {}
+        }
+    };
+
+    ratatui::restore();
+
+    result
+}
+
+async fn load_entries(
+    configs: &[Config],
+    client: &reqwest::Client,
+    count: u32,
+) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+
+    for (index, config) in configs
+        .iter()
+        .enumerate()
+    {
+        let runs = github::retrieve_workflow_runs(config, client, count, None).await?;
+
+        for run in runs {
+            let path = history::form_record_filename(config, &run);
+            entries.push(Entry {
+                config: index,
+                state: history::read_record_state(&path),
+                run,
+                selected: false,
+            });
+        }
+    }
+
+    Ok(entries)
+}
+
+fn draw(
+    frame: &mut ratatui::Frame,
+    configs: &[Config],
+    entries: &[Entry],
+    table: &mut TableState,
+    message: &str,
+) {
+    let [top, bottom] =
+        Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+
+    let rows = entries
+        .iter()
+        .map(|entry| {
+            let run = &entry.run;
+            Row::new([
+                if entry.selected { "*" } else { " " }.to_string(),
+                configs[entry.config]
+                    .workflow
+                    .clone(),
+                format!("#{}", run.run_number),
+                run.run_attempt
+                    .to_string(),
+                run.head_branch
+                    .clone(),
+                run.conclusion
+                    .clone()
+                    .unwrap_or_else(|| {
+                        run.status
+                            .clone()
+                    }),
+                entry
+                    .state
+                    .to_string(),
+                run.display_title
+                    .clone(),
+            ])
+        });
+
+    let widths = [
+        Constraint::Length(1),
+        Constraint::Max(24),
+        Constraint::Length(8),
+        Constraint::Length(7),
+        Constraint::Max(20),
+        Constraint::Length(15),
+        Constraint::Length(10),
+        Constraint::Fill(1),
+    ];
+
+    let header = Row::new([
+        "",
+        "WORKFLOW",
+        "RUN",
+        "ATTEMPT",
+        "BRANCH",
+        "CONCLUSION",
+        "SENT",
+        "TITLE",
+    ])
+    .style(Style::new().add_modifier(Modifier::BOLD));
+
+    let title = format!(" {}/{} ", configs[0].owner, configs[0].repository);
+
+    let widget = Table::new(rows, widths)
+        .header(header)
+        .block(Block::bordered().title(title))
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+
+    frame.render_stateful_widget(widget, top, table);
+
+    let status = if message.is_empty() {
+        "space: select  e: send selected  o: open trace  r: refresh  q: quit"
+    } else {
+        message
+    };
+    frame.render_widget(Line::from(status), bottom);
+}
+
+/// Send the selected Runs, or the one under the cursor if none are selected.
+/// Runs which have already been sent are sent again.
+async fn export_entries(
+    configs: &[Config],
+    client: &reqwest::Client,
+    entries: &[Entry],
+    current: Option<usize>,
+) -> Result<()> {
+    let mut chosen: Vec<&Entry> = entries
+        .iter()
+        .filter(|entry| entry.selected)
+        .collect();
+
+    if chosen.is_empty()
+        && let Some(entry) = current.and_then(|index| entries.get(index))
+    {
+        chosen.push(entry);
+    }
+
+    for entry in chosen {
+        let config = &configs[entry.config];
+        let run = &entry.run;
+
+        println!("Sending Run #{} of {}", run.run_number, config.workflow);
+
+        if entry.state == "no" {
+            crate::submit_run(config, client, run).await?;
+        } else {
+            crate::process_run(config, client, run).await?;
+        }
+    }
+
+    Ok(())
+}
+
+fn wait_for_enter() {
+    print!("Press Enter to continue");
+    let _ = std::io::stdout().flush();
+    let mut line = String::new();
+    let _ = std::io::stdin().read_line(&mut line);
+}
+
+fn open_trace(config: &Config, entry: &Entry) -> Result<String> {
+    let template = get_settings()
+        .trace_url
+        .as_ref()
+        .ok_or_else(|| anyhow!("Give --trace-url to be able to open traces"))?;
+
+    // the TraceId the Run was actually sent as, which isn't necessarily the
+    // one it would get now (with fresh TraceIds, say).
+    let path = history::form_record_filename(config, &entry.run);
+    let trace_id = history::read_record_trace_id(&path).ok_or_else(|| match entry.state {
+        "suppressed" => anyhow!(
+            "Run #{} was suppressed rather than sent",
+            entry
+                .run
+                .run_number
+        ),
+        _ => anyhow!(
+            "Run #{} hasn't been sent",
+            entry
+                .run
+                .run_number
+        ),
+    })?;
+    let url = traces::form_trace_url(template, &trace_id, &entry.run);
+
+    cli::open_in_browser(&url)?;
+
+    Ok(url)
+}
@@ -1,28 +1,118 @@
 //! This is a module to receive webhooks from GitHub when a GitHub Action
 //! workflow is run.
 
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
 
 use anyhow::anyhow;
 use axum::Json;
 use axum::body::Body;
-use axum::extract::FromRequest;
-use axum::http::{Request, StatusCode};
+use axum::extract::{FromRequest, MatchedPath};
+use axum::http::{HeaderValue, Request, StatusCode};
+use axum::middleware::{self, Next};
 use axum::response::{IntoResponse, Response};
 use axum::{Router, routing::get};
+use opentelemetry::context::FutureExt;
+use opentelemetry::trace::{SpanKind, Status, TraceContextExt, Tracer};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_semantic_conventions::attribute::{
+    ERROR_TYPE, HTTP_REQUEST_METHOD, HTTP_RESPONSE_STATUS_CODE, HTTP_ROUTE, URL_PATH,
+    USER_AGENT_ORIGINAL,
+};
 use serde::Deserialize;
-use tracing::info;
+use serde_json::{Map, Value, json};
+use time::{Duration, OffsetDateTime};
+use tokio::sync::Notify;
+use tracing::{Instrument, info, info_span};
 
-use crate::github::{self, Config};
+use crate::github::{self, Config, GitHubProblem, WorkflowRun};
+use crate::settings::get_settings;
+use crate::{GIT_SHA, VERSION};
+use crate::{
+    admin, allowlist, deadletter, forge, forward, history, live, queue, signature, summary, traces,
+};
+
+// For smoke tests: the number of events after which the listener stops, and
+// how many have been handled so far.
+static MAX_EVENTS: OnceLock<u64> = OnceLock::new();
+static EVENTS: AtomicU64 = AtomicU64::new(0);
+static FINISHED: Notify = Notify::const_new();
+
+tokio::task_local! {
+    // The GUID of the webhook delivery being handled, which GitHub's Recent
+    // Deliveries page identifies it by.
+    static DELIVERY: String;
+}
+
+/// The GUID of the webhook delivery which led to whatever is being done, if
+/// it was one.
+pub(crate) fn current_delivery() -> Option<String> {
+    DELIVERY
+        .try_with(String::clone)
+        .ok()
+}
+
+/// Run the future on behalf of the given delivery, so that its GUID is on
+/// every log line, span, and record made along the way.
+pub(crate) async fn with_delivery<F: Future>(delivery: Option<String>, future: F) -> F::Output {
+    match delivery {
+        Some(delivery) => {
+            let span = info_span!("delivery", id = %delivery);
+            DELIVERY
+                .scope(delivery, future.instrument(span))
+                .await
+        }
+        None => future.await,
+    }
+}
+
+pub(crate) fn set_max_events(count: u64) {
+    MAX_EVENTS
+        .set(count)
+        .unwrap();
+}
 
 pub(crate) async fn run_webserver(host: Ipv4Addr, port: u16) -> anyhow::Result<()> {
-    let router = Router::new().route("/", get(hello_world).post(receive_post));
+    let mut router = Router::new()
+        .route("/", get(hello_world).post(receive_post))
+        .route_layer(middleware::from_fn(forward::forward_delivery))
+        .route_layer(middleware::from_fn(signature::verify_signature))
+        .route_layer(middleware::from_fn(allowlist::check_source))
+        .route("/version", get(version));
+
+    // the admin endpoints have their own authentication, rather than being
+    // subject to where webhooks may come from.
+    if let Some(admin) = admin::admin_router() {
+        router = router.merge(admin);
+    }
+
+    let router = router.route_layer(middleware::from_fn(server_span));
 
     info!("Listening on {:?}:{}", host, port);
     let address = (host, port);
 
+    // the address of each connection is needed to check where deliveries
+    // are coming from.
     let listener = tokio::net::TcpListener::bind(address).await?;
-    axum::serve(listener, router).await?;
+    let server = axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    );
+
+    // once the last event has been handled the server stops accepting
+    // connections, finishing the request in flight before returning.
+    if MAX_EVENTS
+        .get()
+        .is_some()
+    {
+        server
+            .with_graceful_shutdown(FINISHED.notified())
+            .await?;
+        info!("Handled the requested number of events; stopping");
+    } else {
+        server.await?;
+    }
 
     Ok(())
 }
@@ -32,7 +122,7 @@ struct RequestPayload {
     action: String,
     organization: WebhookOrganization,
     repository: WebhookRepository,
-    workflow_run: github::WorkflowRun,
+    workflow_run: Value,
 }
 
 #[derive(Deserialize)]
@@ -43,12 +133,167 @@ struct WebhookOrganization {
 #[derive(Deserialize)]
 struct WebhookRepository {
     name: String,
+    html_url: String,
+}
+
+impl WebhookRepository {
+    /// Work out which GitHub the event came from. Only github.com and the
+    /// configured GitHub Enterprise Server are accepted, since we will be
+    /// sending an API token to whichever host this is.
+    fn host(&self) -> Result<String, ErrorWrapper> {
+        let host = github::host_of(&self.html_url)
+            .ok_or_else(|| anyhow!("Could not get host from {}", self.html_url))?;
+
+        if host.eq_ignore_ascii_case(github::GITHUB_HOST) {
+            return Ok(github::GITHUB_HOST.to_string());
+        }
+
+        match github::get_enterprise_host() {
+            Some(enterprise) if enterprise.eq_ignore_ascii_case(host) => Ok(enterprise.to_string()),
+            _ => Err(ErrorWrapper::UnknownHost(host.to_string())),
+        }
+    }
+}
+
+fn header_value(request: &Request<Body>, name: &str) -> Option<String> {
+    request
+        .headers()
+        .get(name)
+        .and_then(|value| {
+            value
+                .to_str()
+                .ok()
+        })
+        .map(str::to_string)
+}
+
+/// Wrap each request in an HTTP server span in our own telemetry, following
+/// the semantic conventions for HTTP servers. GitHub identifies each webhook
+/// delivery with a GUID, which is what its Recent Deliveries page shows, so
+/// we record that too.
+async fn server_span(request: Request<Body>, next: Next) -> Response {
+    let method = request
+        .method()
+        .to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|matched| {
+            matched
+                .as_str()
+                .to_string()
+        });
+    let mut attributes = vec![
+        KeyValue::new(HTTP_REQUEST_METHOD, method.clone()),
+        KeyValue::new(
+            URL_PATH,
+            request
+                .uri()
+                .path()
+                .to_string(),
+        ),
+    ];
+    if let Some(route) = &route {
+        attributes.push(KeyValue::new(HTTP_ROUTE, route.clone()));
+    }
+    if let Some(agent) = header_value(&request, "User-Agent") {
+        attributes.push(KeyValue::new(USER_AGENT_ORIGINAL, agent));
+    }
+    if let Some(delivery) = header_value(&request, "X-GitHub-Delivery") {
+        attributes.push(KeyValue::new("github.delivery", delivery));
+    }
+    if let Some(event) = header_value(&request, "X-GitHub-Event") {
+        attributes.push(KeyValue::new("github.event", event));
+    }
+
+    let name = match &route {
+        Some(route) => format!("{} {}", method, route),
+        None => method,
+    };
+
+    let tracer = traces::self_tracer();
+    let span = tracer
+        .span_builder(name)
+        .with_kind(SpanKind::Server)
+        .with_attributes(attributes)
+        .start(&tracer);
+    let context = Context::current_with_span(span);
+
+    let delivery = header_value(&request, "X-GitHub-Delivery");
+
+    let mut response = with_delivery(
+        delivery.clone(),
+        next.run(request)
+            .with_context(context.clone()),
+    )
+    .await;
+
+    // echoed back so that what GitHub shows of our response says which
+    // delivery it was.
+    if let Some(delivery) = delivery
+        && let Ok(value) = HeaderValue::from_str(&delivery)
+    {
+        response
+            .headers_mut()
+            .insert("X-GitHub-Delivery", value);
+    }
+
+    // only server errors count as errors of ours; a 4xx is the client's
+    // problem as far as the conventions are concerned.
+    let status = response.status();
+    let span = context.span();
+    span.set_attribute(KeyValue::new(
+        HTTP_RESPONSE_STATUS_CODE,
+        status.as_u16() as i64,
+    ));
+    if status.is_server_error() {
+        span.set_attribute(KeyValue::new(
+            ERROR_TYPE,
+            status
+                .as_str()
+                .to_string(),
+        ));
+        span.set_status(Status::error(status.to_string()));
+    }
+    span.end();
+
+    response
 }
 
 async fn hello_world() -> &'static str {
     "Hello world!"
 }
 
+/// Which build this is and how it has been set up, so that tooling looking
+/// after a fleet of listeners can check what is running where. Nothing
+/// secret is given out: no tokens or headers, and no URLs which might carry
+/// credentials.
+async fn version() -> Json<Value> {
+    let mut features = Vec::new();
+    if cfg!(feature = "tui") {
+        features.push("tui");
+    }
+
+    Json(json!({
+        "version": VERSION,
+        "git_sha": GIT_SHA,
+        "features": features,
+        "configuration": {
+            "settings": get_settings().summary(),
+            "enterprise_host": github::get_enterprise_host(),
+            "forge": forge::self_hosted_forge().map(|forge| forge.key()),
+            "record": history::is_enabled(),
+            "queue": queue::is_enabled(),
+            "dead_letter": deadletter::is_enabled(),
+            "live_jobs": live::is_enabled(),
+            "provisional_jobs": live::is_provisional(),
+            "restrict_sources": allowlist::is_enabled(),
+            "forward_targets": forward::target_count(),
+            "admin": admin::is_enabled(),
+        },
+    }))
+}
+
 // Make a wrapper around `anyhow::Error` and other branching escape paths we
 // want to convert into specific response codes.
 enum ErrorWrapper {
@@ -56,6 +301,7 @@ enum ErrorWrapper {
     MissingHeader,
     IgnoredType(String),
     IgnoredAction(String),
+    UnknownHost(String),
     JsonFailure(axum::extract::rejection::JsonRejection),
 }
 
@@ -83,6 +329,11 @@ impl IntoResponse for ErrorWrapper {
                 )
                     .into_response() // such a stupid field name
             }
+            ErrorWrapper::UnknownHost(host) => (
+                StatusCode::FORBIDDEN,
+                format!("Not configured to accept events from {}", host),
+            )
+                .into_response(),
             ErrorWrapper::JsonFailure(problem) => {
                 (StatusCode::UNPROCESSABLE_ENTITY, problem).into_response()
             }
@@ -96,7 +347,49 @@ impl From<anyhow::Error> for ErrorWrapper {
     }
 }
 
-struct GitHubEvent(Json<RequestPayload>);
+#[derive(Deserialize)]
+struct DispatchPayload {
+    #[serde(default)]
+    inputs: Option<Map<String, Value>>,
+    #[serde(rename = "ref")]
+    git_ref: String,
+    organization: WebhookOrganization,
+    repository: WebhookRepository,
+    workflow: String,
+}
+
+#[derive(Deserialize)]
+struct JobPayload {
+    action: String,
+    organization: WebhookOrganization,
+    repository: WebhookRepository,
+    workflow_job: Value,
+}
+
+/// The parts of a workflow_job event's Job needed before it can be decoded,
+/// which can't be until it has completed.
+#[derive(Deserialize)]
+struct JobReference {
+    id: u64,
+    run_id: u64,
+    #[serde(default = "first_attempt")]
+    run_attempt: u64,
+    name: String,
+    #[serde(with = "time::serde::rfc3339::option", default)]
+    started_at: Option<OffsetDateTime>,
+}
+
+fn first_attempt() -> u64 {
+    1
+}
+
+// named after the events GitHub sends, which all concern workflows
+#[allow(clippy::enum_variant_names)]
+enum GitHubEvent {
+    WorkflowRun(Box<RequestPayload>),
+    WorkflowDispatch(DispatchPayload),
+    WorkflowJob(Box<JobPayload>),
+}
 
 impl<S> FromRequest<S> for GitHubEvent
 where
@@ -109,6 +402,20 @@ where
             .headers()
             .get("X-GitHub-Event")
         {
+            if event == "workflow_dispatch" {
+                let result = Json::<DispatchPayload>::from_request(req, state).await;
+                return match result {
+                    Ok(Json(payload)) => Ok(GitHubEvent::WorkflowDispatch(payload)),
+                    Err(problem) => Err(ErrorWrapper::JsonFailure(problem)),
+                };
+            }
+            if event == "workflow_job" && live::is_enabled() {
+                let result = Json::<JobPayload>::from_request(req, state).await;
+                return match result {
+                    Ok(Json(payload)) => Ok(GitHubEvent::WorkflowJob(Box::new(payload))),
+                    Err(problem) => Err(ErrorWrapper::JsonFailure(problem)),
+                };
+            }
             if event != "workflow_run" {
                 return Err(ErrorWrapper::IgnoredType(
                     event
@@ -119,29 +426,196 @@ where
             }
             let result = Json::<RequestPayload>::from_request(req, state).await;
             match result {
-                Ok(json) => Ok(GitHubEvent(json)),
+                Ok(Json(payload)) => Ok(GitHubEvent::WorkflowRun(Box::new(payload))),
                 Err(problem) => Err(ErrorWrapper::JsonFailure(problem)),
             }
         } else {
-            return Err(ErrorWrapper::MissingHeader);
+            Err(ErrorWrapper::MissingHeader)
         }
     }
 }
 
-/// Handler for incoming webhook requests. This will extract the supplied
-/// WorkflowRun, fire off the query to get its jobs and steps, then process
-/// that into telemetry.
-async fn receive_post(GitHubEvent(payload): GitHubEvent) -> Result<(), ErrorWrapper> {
-    let path = payload
-        .workflow_run
-        .path
-        .clone();
-    let filename = path
+/// Handler for incoming webhook requests.
+async fn receive_post(event: GitHubEvent) -> Result<(), ErrorWrapper> {
+    summary::record_webhook();
+
+    let result = match event {
+        GitHubEvent::WorkflowRun(payload) => receive_workflow_run(*payload).await,
+        GitHubEvent::WorkflowDispatch(payload) => receive_workflow_dispatch(payload),
+        GitHubEvent::WorkflowJob(payload) => receive_workflow_job(*payload).await,
+    };
+
+    if let Some(max) = MAX_EVENTS.get() {
+        let handled = EVENTS.fetch_add(1, Ordering::Relaxed) + 1;
+        if handled >= *max {
+            FINISHED.notify_one();
+        }
+    }
+
+    result
+}
+
+// The inputs supplied when a workflow is manually dispatched are not
+// available from the API, nor are they in the workflow_run event. They are,
+// however, in the workflow_dispatch event which arrives as the run is being
+// created. We hold onto them until the corresponding Run completes.
+
+struct Dispatch {
+    host: String,
+    owner: String,
+    repository: String,
+    filename: String,
+    branch: String,
+    received: OffsetDateTime,
+    inputs: Map<String, Value>,
+}
+
+static DISPATCHES: Mutex<Vec<Dispatch>> = Mutex::new(Vec::new());
+
+// Runs taking longer than this are not expected, so if we are still holding
+// the inputs for a dispatch after this long the Run is not going to arrive.
+const DISPATCH_RETENTION: Duration = Duration::days(3);
+
+fn receive_workflow_dispatch(payload: DispatchPayload) -> Result<(), ErrorWrapper> {
+    let host = payload
+        .repository
+        .host()?;
+
+    let inputs = match payload.inputs {
+        Some(inputs) if !inputs.is_empty() => inputs,
+        _ => return Ok(()),
+    };
+
+    let filename = match payload
+        .workflow
         .split('/')
-        .last()
-        .ok_or(anyhow!("Could not get Filename"))?
+        .next_back()
+    {
+        Some(filename) => filename.to_string(),
+        None => return Ok(()),
+    };
+
+    let branch = payload
+        .git_ref
+        .strip_prefix("refs/heads/")
+        .unwrap_or(&payload.git_ref)
         .to_string();
 
+    info!(
+        "Holding {} inputs for dispatch of {}/{} {}",
+        inputs.len(),
+        payload
+            .organization
+            .login,
+        payload
+            .repository
+            .name,
+        filename
+    );
+
+    let now = OffsetDateTime::now_utc();
+
+    let mut dispatches = DISPATCHES
+        .lock()
+        .unwrap();
+
+    dispatches.retain(|dispatch| now - dispatch.received < DISPATCH_RETENTION);
+
+    dispatches.push(Dispatch {
+        host,
+        owner: payload
+            .organization
+            .login,
+        repository: payload
+            .repository
+            .name,
+        filename,
+        branch,
+        received: now,
+        inputs,
+    });
+
+    Ok(())
+}
+
+/// Find the inputs from the workflow_dispatch event which gave rise to this
+/// Run. If there are several candidates we take the one received closest to
+/// when the Run was created.
+fn claim_dispatch_inputs(config: &Config, run: &WorkflowRun) -> Option<Map<String, Value>> {
+    let mut dispatches = DISPATCHES
+        .lock()
+        .unwrap();
+
+    let index = dispatches
+        .iter()
+        .enumerate()
+        .filter(|(_, dispatch)| {
+            dispatch.host == config.host
+                && dispatch.owner == config.owner
+                && dispatch.repository == config.repository
+                && dispatch.filename == config.workflow
+                && dispatch.branch == run.head_branch
+        })
+        .min_by_key(|(_, dispatch)| {
+            (dispatch.received - run.created_at)
+                .abs()
+                .whole_seconds()
+        })
+        .map(|(index, _)| index)?;
+
+    let dispatch = dispatches.remove(index);
+    Some(dispatch.inputs)
+}
+
+/// The workflow a Run is of, identified by its filename. However a Run comes
+/// to be processed, this is what the Config is formed with, so that it goes
+/// into the same trace as when the workflow_run event for it arrived.
+pub(crate) fn workflow_of(run: &WorkflowRun) -> String {
+    run.path
+        .split('/')
+        .next_back()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Retrieve a Run (or one attempt of it) known only by its ID, filling in
+/// the workflow it turns out to be of.
+pub(crate) async fn retrieve_run_of(
+    config: &mut Config,
+    client: &reqwest::Client,
+    run_id: u64,
+    attempt: Option<u64>,
+) -> Result<WorkflowRun, GitHubProblem> {
+    let run = github::retrieve_run(
+        client,
+        &config.host,
+        &config.owner,
+        &config.repository,
+        run_id,
+        attempt,
+    )
+    .await?;
+
+    config.workflow = workflow_of(&run);
+
+    Ok(run)
+}
+
+/// This will extract the supplied WorkflowRun, fire off the query to get its
+/// jobs and steps, then process that into telemetry.
+async fn receive_workflow_run(payload: RequestPayload) -> Result<(), ErrorWrapper> {
+    let host = payload
+        .repository
+        .host()?;
+
+    let mut value = payload.workflow_run;
+    forge::adapt_run(&host, &mut value);
+
+    let mut run = github::decode_run(value)
+        .map_err(|error| anyhow!("Could not decode workflow_run: {}", error))?;
+
+    let filename = workflow_of(&run);
+
     // This served as a useful diagnostic to ensure we had the right fields
     // from the inbound request's JSON object body.
 
@@ -155,22 +629,12 @@ async fn receive_post(GitHubEvent(payload): GitHubEvent) -> Result<(), ErrorWrap
             .repository
             .name,
         filename,
-        payload
-            .workflow_run
-            .display_title,
-        payload
-            .workflow_run
-            .actor
+        run.display_title,
+        run.actor
             .login,
-        payload
-            .workflow_run
-            .event,
-        payload
-            .workflow_run
-            .head_branch,
-        payload
-            .workflow_run
-            .conclusion
+        run.event,
+        run.head_branch,
+        run.conclusion
             .clone()
             .unwrap_or("null".to_string())
     );
@@ -189,6 +653,7 @@ async fn receive_post(GitHubEvent(payload): GitHubEvent) -> Result<(), ErrorWrap
     // drive processing the run.
 
     let config = Config {
+        host,
         owner: payload
             .organization
             .login
@@ -201,9 +666,22 @@ async fn receive_post(GitHubEvent(payload): GitHubEvent) -> Result<(), ErrorWrap
         devel: false,
     };
 
-    let client = github::setup_api_client()?;
+    if run.event == "workflow_dispatch"
+        && let Some(inputs) = claim_dispatch_inputs(&config, &run)
+    {
+        run.inputs = inputs;
+    }
+
+    // rather than making GitHub wait while we process the Run, if there is a
+    // queue we put it there and the worker will take it from there.
+    if crate::queue::is_enabled() {
+        crate::queue::enqueue(&config, &run).await?;
+        return Ok(());
+    }
 
-    let result = crate::process_run(&config, &client, &payload.workflow_run).await;
+    let client = github::setup_api_client(&config)?;
+
+    let result = crate::submit_run(&config, &client, &run).await;
 
     // if there was a problem wrap it in the adapter type so we get something
     // that converts via IntoResponse.
@@ -212,3 +690,83 @@ async fn receive_post(GitHubEvent(payload): GitHubEvent) -> Result<(), ErrorWrap
         Err(err) => Err(ErrorWrapper::AnyhowError(err)),
     }
 }
+
+/// Send the spans of a Job as soon as it completes, and if asked a
+/// provisional span when it starts. The event doesn't say which workflow the
+/// Job is from, so the Run is retrieved to find out.
+async fn receive_workflow_job(payload: JobPayload) -> Result<(), ErrorWrapper> {
+    let reference = JobReference::deserialize(&payload.workflow_job)
+        .map_err(|error| anyhow!("Could not decode workflow_job: {}", error))?;
+
+    println!(
+        "{}: {}/{} job \"{}\" of Run {}",
+        payload.action,
+        payload
+            .organization
+            .login,
+        payload
+            .repository
+            .name,
+        reference.name,
+        reference.run_id
+    );
+
+    let started = payload.action == "in_progress" && live::is_provisional();
+
+    if payload.action != "completed" && !started {
+        return Err(ErrorWrapper::IgnoredAction(
+            payload
+                .action
+                .clone(),
+        ));
+    }
+
+    let host = payload
+        .repository
+        .host()?;
+
+    // the workflow is filled in once we have the Run
+    let mut config = Config {
+        host,
+        owner: payload
+            .organization
+            .login
+            .clone(),
+        repository: payload
+            .repository
+            .name
+            .clone(),
+        workflow: String::new(),
+        devel: false,
+    };
+
+    let client = github::setup_api_client(&config)?;
+
+    // the workflow comes from the Run, so that the Job goes into the same
+    // trace as the rest of its Run.
+    let run = retrieve_run_of(
+        &mut config,
+        &client,
+        reference.run_id,
+        Some(reference.run_attempt),
+    )
+    .await
+    .map_err(|problem| anyhow!("Could not retrieve Run {}: {}", reference.run_id, problem))?;
+
+    if started {
+        if let Some(started_at) = reference.started_at {
+            live::send_started(&config, &run, reference.id, reference.name, started_at);
+        }
+        return Ok(());
+    }
+
+    let mut value = payload.workflow_job;
+    forge::adapt_job(&config.host, &mut value);
+
+    let job = github::decode_job(value)
+        .map_err(|error| anyhow!("Could not decode workflow_job: {}", error))?;
+
+    live::send_job(&config, &client, &run, job).await?;
+
+    Ok(())
+}
@@ -0,0 +1,395 @@
+//! The definition of a workflow, as read from its YAML file. GitHub's API
+//! tells us about the Jobs that ran but not how they relate to one another;
+//! for that we need the `needs:` of each job in the workflow itself, and
+//! which reusable workflow a job `uses:` if it calls one. The workflow's
+//! `concurrency:` is read too, so that a Run cancelled because another in
+//! the same group started can be recognized as such, and the `cron:` of its
+//! schedules, since a scheduled Run doesn't say which fired it.
+
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use crate::github::{Config, WorkflowRun};
+
+#[derive(Deserialize)]
+struct WorkflowFile {
+    #[serde(default)]
+    jobs: BTreeMap<String, JobDefinition>,
+    concurrency: Option<Concurrency>,
+    // the events which trigger the workflow can be given as a single name,
+    // a list of names, or a map from name to configuration; only the last
+    // can have schedules.
+    #[serde(default)]
+    on: serde_yaml_ng::Value,
+}
+
+#[derive(Deserialize)]
+struct JobDefinition {
+    name: Option<String>,
+    #[serde(default)]
+    needs: Needs,
+    uses: Option<String>,
+}
+
+/// A job can need a single other job or a list of them.
+#[derive(Deserialize, Default)]
+#[serde(untagged)]
+enum Needs {
+    #[default]
+    None,
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Concurrency can be given as just the name of the group, or as the group
+/// and whether a new Run cancels one already in progress.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Concurrency {
+    Group(String),
+    Detailed {
+        group: String,
+        #[serde(rename = "cancel-in-progress")]
+        cancel_in_progress: Option<Flag>,
+    },
+}
+
+#[derive(Deserialize)]
+struct Schedule {
+    cron: String,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Flag {
+    Literal(bool),
+    Expression(String),
+}
+
+/// Which jobs each job in the workflow needs, keyed by the job's ID (the key
+/// it is given under `jobs:`).
+pub(crate) struct JobGraph {
+    jobs: Vec<(String, String, Vec<String>, Option<String>)>, // id, name, needs, uses
+    concurrency: Option<(String, String)>,                    // group, cancel-in-progress
+    schedules: Vec<String>,
+}
+
+pub(crate) fn parse_job_graph(text: &str) -> Result<JobGraph> {
+    let file: WorkflowFile = serde_yaml_ng::from_str(text)?;
+
+    let jobs = file
+        .jobs
+        .into_iter()
+        .map(|(id, definition)| {
+            // a name computed from an expression can't be matched against
+            // the Jobs GitHub reports, so we fall back to the ID.
+            let name = definition
+                .name
+                .filter(|name| !name.contains("${{"))
+                .unwrap_or_else(|| id.clone());
+
+            let needs = match definition.needs {
+                Needs::None => Vec::new(),
+                Needs::One(id) => vec![id],
+                Needs::Many(ids) => ids,
+            };
+
+            (id, name, needs, definition.uses)
+        })
+        .collect();
+
+    let concurrency = file
+        .concurrency
+        .map(|concurrency| match concurrency {
+            Concurrency::Group(group) => (group, "false".to_string()),
+            Concurrency::Detailed {
+                group,
+                cancel_in_progress,
+            } => {
+                let cancel = match cancel_in_progress {
+                    None => "false".to_string(),
+                    Some(Flag::Literal(flag)) => flag.to_string(),
+                    Some(Flag::Expression(expression)) => expression,
+                };
+                (group, cancel)
+            }
+        });
+
+    let schedules = file
+        .on
+        .get("schedule")
+        .cloned()
+        .and_then(|value| serde_yaml_ng::from_value::<Vec<Schedule>>(value).ok())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|schedule| schedule.cron)
+        .collect();
+
+    Ok(JobGraph {
+        jobs,
+        concurrency,
+        schedules,
+    })
+}
+
+impl JobGraph {
+    /// Find the ID of the job a Job reported by GitHub was run for. The
+    /// reported name is the job's name, with the values of its matrix
+    /// appended in parenthesis if it has one, or with the name of a job in a
+    /// called reusable workflow appended after a slash.
+    pub(crate) fn job_id_of(&self, name: &str) -> Option<&str> {
+        self.jobs
+            .iter()
+            .find(|(_, candidate, _, _)| {
+                name == candidate
+                    || name
+                        .strip_prefix(candidate.as_str())
+                        .is_some_and(|rest| rest.starts_with(" (") || rest.starts_with(" / "))
+            })
+            .map(|(id, _, _, _)| id.as_str())
+    }
+
+    /// The IDs of the jobs the given job needs.
+    pub(crate) fn needs_of(&self, id: &str) -> &[String] {
+        self.jobs
+            .iter()
+            .find(|(candidate, _, _, _)| candidate == id)
+            .map(|(_, _, needs, _)| needs.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// The reusable workflow the given job calls, as written in its `uses:`
+    /// clause: "owner/repo/.github/workflows/file.yaml@ref", or
+    /// "./.github/workflows/file.yaml" for one in the same repository.
+    pub(crate) fn uses_of(&self, id: &str) -> Option<&str> {
+        self.jobs
+            .iter()
+            .find(|(candidate, _, _, _)| candidate == id)
+            .and_then(|(_, _, _, uses)| uses.as_deref())
+    }
+
+    /// The cron expressions of the workflow's schedules, if it has any.
+    pub(crate) fn schedules(&self) -> &[String] {
+        &self.schedules
+    }
+
+    /// The workflow's concurrency group and whether cancel-in-progress
+    /// applies to it, with any expressions in them evaluated for the given
+    /// Run. There is no group if the workflow doesn't declare one, or if it
+    /// is formed from something we can't know from the Run; likewise
+    /// cancel-in-progress is left unknown rather than guessed.
+    pub(crate) fn concurrency_of(
+        &self,
+        config: &Config,
+        run: &WorkflowRun,
+    ) -> Option<(String, Option<bool>)> {
+        let (group, cancel) = self
+            .concurrency
+            .as_ref()?;
+
+        let lookup = |name: &str| github_context(config, run, name);
+
+        let group = interpolate(group, &lookup)?;
+        let cancel = interpolate(cancel, &lookup).map(|value| is_truthy(&value));
+
+        Some((group, cancel))
+    }
+}
+
+/// The value of a context GitHub would have provided to the Run, as far as
+/// it can be worked out after the fact from the Run itself.
+fn github_context(config: &Config, run: &WorkflowRun, name: &str) -> Option<String> {
+    let pull_request = run
+        .payload
+        .get("pull_requests")
+        .and_then(|requests| requests.get(0));
+
+    let number = || {
+        pull_request
+            .and_then(|request| request.get("number"))
+            .and_then(|number| number.as_u64())
+    };
+    let branch = |side: &str| {
+        pull_request
+            .and_then(|request| request.get(side))
+            .and_then(|side| side.get("ref"))
+            .and_then(|name| name.as_str())
+            .map(str::to_string)
+    };
+
+    let name = name.to_lowercase();
+
+    let value = match name.as_str() {
+        "github.workflow" => run
+            .name
+            .clone(),
+        "github.repository" => format!("{}/{}", config.owner, config.repository),
+        "github.repository_owner" => config
+            .owner
+            .clone(),
+        "github.event_name" => run
+            .event
+            .clone(),
+        "github.run_id" => run
+            .run_id
+            .to_string(),
+        "github.run_number" => run
+            .run_number
+            .to_string(),
+        "github.run_attempt" => run
+            .run_attempt
+            .to_string(),
+        "github.actor" => run
+            .actor
+            .login
+            .clone(),
+        // for a pull request this is the merge commit GitHub made, which
+        // the Run doesn't tell us.
+        "github.sha" if run.event == "pull_request" => return None,
+        "github.sha" => run
+            .head_sha
+            .clone(),
+        "github.ref" if run.event == "pull_request" => format!("refs/pull/{}/merge", number()?),
+        "github.ref" if run.event == "pull_request_target" => {
+            format!("refs/heads/{}", branch("base")?)
+        }
+        "github.ref" => format!("refs/heads/{}", run.head_branch),
+        "github.ref_name" if run.event == "pull_request" => format!("{}/merge", number()?),
+        "github.ref_name" if run.event == "pull_request_target" => branch("base")?,
+        "github.ref_name" => run
+            .head_branch
+            .clone(),
+        "github.head_ref" | "github.base_ref" | "github.event.pull_request.number"
+            if !run
+                .event
+                .starts_with("pull_request") =>
+        {
+            String::new()
+        }
+        "github.head_ref" => branch("head")?,
+        "github.base_ref" => branch("base")?,
+        "github.event.pull_request.number" => number()?.to_string(),
+        _ => {
+            let input = name
+                .strip_prefix("inputs.")
+                .or_else(|| name.strip_prefix("github.event.inputs."))?;
+            match run
+                .inputs
+                .iter()
+                .find(|(key, _)| key.to_lowercase() == input)
+                .map(|(_, value)| value)
+            {
+                Some(serde_json::Value::String(text)) => text.clone(),
+                Some(serde_json::Value::Null) | None => String::new(),
+                Some(value) => value.to_string(),
+            }
+        }
+    };
+
+    Some(value)
+}
+
+/// Replace each `${{ }}` in the text with the value of the expression
+/// within it. Only the simpler expressions are understood: contexts,
+/// literals, comparisons with == and !=, and the && and || operators, which
+/// between them cover how concurrency groups are usually written. Anything
+/// else, such as a function call, means the text can't be evaluated.
+fn interpolate(text: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Option<String> {
+    let mut result = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("${{") {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 3..];
+        let end = after.find("}}")?;
+        result.push_str(&evaluate(after[..end].trim(), lookup)?);
+        rest = &after[end + 2..];
+    }
+    result.push_str(rest);
+
+    Some(result)
+}
+
+// As in GitHub's expressions, || gives the first operand which is truthy
+// (or the last), and && gives the first which isn't (or the last).
+fn evaluate(expression: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Option<String> {
+    let mut value = String::new();
+
+    for alternative in split_outside_quotes(expression, "||") {
+        for term in split_outside_quotes(alternative, "&&") {
+            value = compare(term.trim(), lookup)?;
+            if !is_truthy(&value) {
+                break;
+            }
+        }
+        if is_truthy(&value) {
+            break;
+        }
+    }
+
+    Some(value)
+}
+
+fn compare(expression: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Option<String> {
+    for (operator, equal) in [("==", true), ("!=", false)] {
+        let sides = split_outside_quotes(expression, operator);
+        if sides.len() == 1 {
+            continue;
+        }
+        let [left, right] = sides[..] else {
+            return None;
+        };
+
+        // string comparisons in expressions ignore case
+        let same = operand(left.trim(), lookup)?.to_lowercase()
+            == operand(right.trim(), lookup)?.to_lowercase();
+
+        return Some((same == equal).to_string());
+    }
+
+    operand(expression, lookup)
+}
+
+fn operand(text: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Option<String> {
+    if let Some(literal) = text
+        .strip_prefix('\'')
+        .and_then(|text| text.strip_suffix('\''))
+    {
+        return Some(literal.replace("''", "'"));
+    }
+
+    match text {
+        "true" | "false" => Some(text.to_string()),
+        "null" => Some(String::new()),
+        _ if text
+            .parse::<f64>()
+            .is_ok() =>
+        {
+            Some(text.to_string())
+        }
+        _ => lookup(text),
+    }
+}
+
+fn is_truthy(value: &str) -> bool {
+    !matches!(value, "" | "false" | "0")
+}
+
+fn split_outside_quotes<'a>(text: &'a str, separator: &str) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+
+    for (index, c) in text.char_indices() {
+        if c == '\'' {
+            quoted = !quoted;
+        } else if !quoted && index >= start && text[index..].starts_with(separator) {
+            parts.push(&text[start..index]);
+            start = index + separator.len();
+        }
+    }
+    parts.push(&text[start..]);
+
+    parts
+}